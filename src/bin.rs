@@ -1,19 +1,9 @@
-use std::env;
+use std::{env, process};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, Args as ClapArgs};
 use log::info;
-use types::BaseConfig;
-use env_logger;
-use network::ping;
 
-use crate::{network::{arp, port_scan, dns, udp_echo}, types::{ArpConfig, DnsConfig, EchoConfig}};
-
-/**
- * Modules
- */
-mod utils;
-mod types;
-mod network;
+use ndt::{error::Result, output::{Output, Report}, network::{arp, ping, port_scan, dns, dns_trace, dnssec, udp_echo, traceroute, pmtu, sweep, types::edns::DEFAULT_UDP_SIZE}, types::{ArpConfig, BaseConfig, DnsConfig, EdnsConfig, TlsConfig, PingConfig, TracerouteConfig, PmtuConfig, SweepConfig, PortScanConfig, IpVersion, OutputFormat, RecordType, ResolveConfig, Resolver, ScanMode}};
 
 
 #[derive(Parser, Debug)]
@@ -25,6 +15,108 @@ struct Args {
     /// log level = debug, info, warn, error
     #[arg(short, long, default_value_t = String::from("info"))]
     log_level: String,
+    /// Output format of results, json and ndjson are written to stdout
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text, global = true)]
+    output: OutputFormat,
+}
+
+/// Target resolve options
+#[derive(ClapArgs, Debug)]
+struct ResolveArgs {
+    /// Use ipv4 address of target
+    #[arg(short = '4', conflicts_with = "ipv6")]
+    ipv4: bool,
+    /// Use ipv6 address of target
+    #[arg(short = '6')]
+    ipv6: bool,
+    /// Resolver of target hostname
+    #[arg(long, value_enum, default_value_t = Resolver::System)]
+    resolver: Resolver
+}
+
+impl ResolveArgs {
+    fn config(&self) -> ResolveConfig {
+        let ip_version = if self.ipv4 {
+            IpVersion::V4
+        } else if self.ipv6 {
+            IpVersion::V6
+        } else {
+            IpVersion::Any
+        };
+        ResolveConfig { ip_version, resolver: self.resolver }
+    }
+}
+
+/// EDNS0 options of dns query
+#[derive(ClapArgs, Debug)]
+struct EdnsArgs {
+    /// Query without EDNS0 OPT record
+    #[arg(long, conflicts_with_all = ["dnssec_ok", "subnet", "nsid", "cookie"])]
+    no_edns: bool,
+    /// Udp payload size of EDNS0
+    #[arg(long, default_value_t = DEFAULT_UDP_SIZE)]
+    bufsize: u16,
+    /// Set DNSSEC OK bit
+    #[arg(long)]
+    dnssec_ok: bool,
+    /// EDNS client subnet like 192.0.2.0/24
+    #[arg(long)]
+    subnet: Option<String>,
+    /// Request name server identifier
+    #[arg(long)]
+    nsid: bool,
+    /// Send client cookie
+    #[arg(long)]
+    cookie: bool
+}
+
+impl EdnsArgs {
+    fn config(&self) -> EdnsConfig {
+        EdnsConfig {
+            enabled: !self.no_edns,
+            udp_size: self.bufsize,
+            dnssec_ok: self.dnssec_ok,
+            client_subnet: self.subnet.clone(),
+            nsid: self.nsid,
+            cookie: self.cookie
+        }
+    }
+}
+
+/// Encrypted transport of dns query
+#[derive(ClapArgs, Debug)]
+struct TlsArgs {
+    /// Query over DNS-over-TLS, port 853 of the server
+    #[arg(long, group = "encryption", conflicts_with_all = ["tcp", "trace"])]
+    tls: bool,
+    /// Query over DNS-over-HTTPS url like https://dns.example/dns-query, the server option overrides the address of the host
+    #[arg(long, value_name = "URL", group = "encryption", conflicts_with_all = ["tcp", "trace"])]
+    https: Option<String>,
+    /// Send DoH query by GET instead of POST
+    #[arg(long, requires = "https")]
+    get: bool,
+    /// Name of SNI and the server certificate, host of the url or address of the server by default
+    #[arg(long, requires = "encryption")]
+    tls_name: Option<String>,
+    /// Trust the server by base64 sha256 of the public key of its certificate instead of CAs, repeatable
+    #[arg(long, requires = "encryption")]
+    pin: Vec<String>,
+    /// PEM file of CA certificates, the built-in roots by default
+    #[arg(long, requires = "encryption")]
+    ca_file: Option<String>
+}
+
+impl TlsArgs {
+    fn config(&self) -> TlsConfig {
+        TlsConfig {
+            tls: self.tls,
+            https: self.https.clone(),
+            get: self.get,
+            server_name: self.tls_name.clone(),
+            pins: self.pin.clone(),
+            ca_file: self.ca_file.clone()
+        }
+    }
 }
 
 #[allow(unused, non_camel_case_types)]
@@ -32,8 +124,62 @@ struct Args {
 enum Command {
     /// Check ping
     ping {
-        /// Target ip
-        ip: String
+        /// Target ip (ipv4 or ipv6) or hostname
+        ip: String,
+        #[command(flatten)]
+        resolve: ResolveArgs,
+        /// Number of echo requests to send
+        #[arg(short, long, default_value_t = 4)]
+        count: u16,
+        /// Interval between echo requests (ms)
+        #[arg(short, long, default_value_t = 1000)]
+        interval: u64,
+        /// Time to live of echo requests
+        #[arg(short, long, default_value_t = 64)]
+        ttl: u8
+    },
+    /// Trace route
+    traceroute {
+        /// Target ip or hostname
+        ip: String,
+        #[command(flatten)]
+        resolve: ResolveArgs,
+        /// Max number of hops
+        #[arg(short, long, default_value_t = 30)]
+        max_hops: u8,
+        /// Number of probes per hop
+        #[arg(short, long, default_value_t = 3)]
+        probes: u8,
+        /// Annotate routers with names of reverse dns
+        #[arg(long)]
+        rdns: bool
+    },
+    /// Discover path mtu
+    pmtu {
+        /// Target ip or hostname
+        ip: String,
+        #[command(flatten)]
+        resolve: ResolveArgs,
+        /// Smallest packet size to search (bytes)
+        #[arg(long, default_value_t = 68)]
+        min_mtu: u16,
+        /// Largest packet size to search (bytes)
+        #[arg(long, default_value_t = 1500)]
+        max_mtu: u16
+    },
+    /// Ping sweep of subnet
+    sweep {
+        /// Target cidr (192.0.2.0/24) or range (192.0.2.1-192.0.2.50)
+        target: String,
+        /// Max echo requests per second
+        #[arg(short, long, default_value_t = 100)]
+        rate: u32,
+        /// Time to wait replies after the last request (ms)
+        #[arg(short, long, default_value_t = 1000)]
+        timeout: u64,
+        /// Annotate alive hosts with names of reverse dns
+        #[arg(long)]
+        rdns: bool
     },
     /// Check Arp
     arp {
@@ -44,17 +190,70 @@ enum Command {
     },
     /// Check Dns
     dns {
-        /// target domain name
+        /// target domain name, or ip address of reverse lookup
         domain: String,
         /// Network interface name
-        interface_name: String
+        interface_name: String,
+        /// Record type to query
+        #[arg(short = 't', long = "type", value_enum, ignore_case = true, default_value_t = RecordType::A)]
+        record_type: RecordType,
+        /// Dns server like 192.0.2.53, [2001:db8::53]:5353, nameservers of /etc/resolv.conf by default
+        #[arg(short, long)]
+        server: Option<String>,
+        /// Reverse lookup of PTR record, domain is ip address
+        #[arg(short = 'x', long, conflicts_with = "record_type")]
+        reverse: bool,
+        /// Query over tcp instead of udp
+        #[arg(long)]
+        tcp: bool,
+        #[command(flatten)]
+        edns: EdnsArgs,
+        #[command(flatten)]
+        tls: TlsArgs,
+        /// Resolve iteratively from root servers and show each referral
+        #[arg(long, conflicts_with = "server")]
+        trace: bool,
+        /// Root hints file of trace, /usr/share/dns/root.hints or built-in root servers by default
+        #[arg(long, requires = "trace")]
+        root_hints: Option<String>,
+        /// Port of servers in trace
+        #[arg(long, requires = "trace", default_value_t = 53)]
+        port: u16,
+        /// Validate DNSSEC signatures of the answer up to the trust anchor and show the broken link
+        #[arg(long, conflicts_with_all = ["trace", "no_edns"])]
+        dnssec: bool,
+        /// File of DS records of trust anchors, the root key signing keys by default
+        #[arg(long, requires = "dnssec")]
+        trust_anchor: Option<String>
     },
     /// Check port
-    port_scan,
+    port_scan {
+        /// Target ip or hostname
+        ip: String,
+        #[command(flatten)]
+        resolve: ResolveArgs,
+        /// Ports to scan, e.g. 22,80,8000-8100
+        #[arg(short, long, default_value_t = String::from("1-1024"))]
+        ports: String,
+        /// Scan mode, syn and udp need raw socket
+        #[arg(short, long, value_enum, default_value_t = ScanMode::Connect)]
+        mode: ScanMode,
+        /// Timeout of connection, or of responses after the last probe (ms)
+        #[arg(short, long, default_value_t = 1000)]
+        timeout: u64,
+        /// Number of concurrent connections
+        #[arg(short, long, default_value_t = 100)]
+        concurrency: usize,
+        /// Identify services of open tcp ports by banners
+        #[arg(short, long)]
+        banners: bool
+    },
     /// Echo
     echo {
-        /// target ip
+        /// target ip or hostname
         ip: String,
+        #[command(flatten)]
+        resolve: ResolveArgs
     }
 }
 fn main() { let args = Args::parse();
@@ -65,28 +264,101 @@ fn main() { let args = Args::parse();
 
     // Command
     match &args.cmd {
-        Command::ping { ip } => { 
-            let config = BaseConfig { ip: ip.to_string() };
-            ping::ping(config)
+        Command::ping { ip, resolve, count, interval, ttl } => {
+            let config = PingConfig {
+                ip: ip.to_string(),
+                resolve: resolve.config(),
+                count: *count,
+                interval: *interval,
+                ttl: *ttl
+            };
+            let output = Output::new(args.output, "ping", ip, None);
+            print_report(&output, ping::ping_with(config, |probe| output.record(probe)));
+        }
+        Command::traceroute { ip, resolve, max_hops, probes, rdns } => {
+            let config = TracerouteConfig {
+                ip: ip.to_string(),
+                resolve: resolve.config(),
+                max_hops: *max_hops,
+                probes: *probes,
+                rdns: *rdns
+            };
+            let output = Output::new(args.output, "traceroute", ip, None);
+            print_report(&output, traceroute::traceroute_with(config, |hop| output.record(hop)));
+        }
+        Command::pmtu { ip, resolve, min_mtu, max_mtu } => {
+            let config = PmtuConfig {
+                ip: ip.to_string(),
+                resolve: resolve.config(),
+                min_mtu: *min_mtu,
+                max_mtu: *max_mtu
+            };
+            print_report(&Output::new(args.output, "pmtu", ip, None), pmtu::pmtu(config));
+        }
+        Command::sweep { target, rate, timeout, rdns } => {
+            let config = SweepConfig {
+                target: target.to_string(),
+                rate: *rate,
+                timeout: *timeout,
+                rdns: *rdns
+            };
+            print_report(&Output::new(args.output, "sweep", target, None), sweep::sweep(config));
         }
         Command::arp { dest_ip , interface_name } => {
             let config = ArpConfig 
                 { dest_ip: dest_ip.to_string(), interface_name: interface_name.to_string() };
-            arp::arp(config);
+            print_report(&Output::new(args.output, "arp", dest_ip, Some(interface_name)), arp::arp(config));
         }
-        Command::dns { domain, interface_name } => {
+        Command::dns { domain, interface_name, record_type, server, reverse, tcp, edns, tls, trace, root_hints, port, dnssec, trust_anchor } => {
             let config = DnsConfig {
                 domain: domain.to_string(),
-                interface_name: interface_name.to_string()
+                interface_name: interface_name.to_string(),
+                record_type: *record_type,
+                server: server.clone(),
+                reverse: *reverse,
+                tcp: *tcp,
+                edns: edns.config(),
+                tls: tls.config(),
+                trace: *trace,
+                root_hints: root_hints.clone(),
+                port: *port,
+                dnssec: *dnssec,
+                trust_anchor: trust_anchor.clone()
             };
-            dns::dns(config);
+            let output = Output::new(args.output, "dns", domain, Some(interface_name));
+            if config.trace {
+                print_report(&output, dns_trace::dns_trace(config, |step| output.record(step)));
+            } else if config.dnssec {
+                print_report(&output, dnssec::dnssec(config));
+            } else {
+                print_report(&output, dns::dns(config));
+            }
         }
-        Command::port_scan => {
-            port_scan::port_scan();
+        Command::port_scan { ip, resolve, ports, mode, timeout, concurrency, banners } => {
+            let config = PortScanConfig {
+                ip: ip.to_string(),
+                resolve: resolve.config(),
+                ports: ports.to_string(),
+                mode: *mode,
+                timeout: *timeout,
+                concurrency: *concurrency,
+                banners: *banners
+            };
+            print_report(&Output::new(args.output, "port_scan", ip, None), port_scan::port_scan(config));
         }
-        Command::echo { ip } => {
-            let config = BaseConfig { ip: ip.to_string() };
-            udp_echo::udp_echo(config)
+        Command::echo { ip, resolve } => {
+            let config = BaseConfig { ip: ip.to_string(), resolve: resolve.config() };
+            print_report(&Output::new(args.output, "echo", ip, None), udp_echo::udp_echo(config));
         }
     }
-}
\ No newline at end of file
+}
+
+/**
+ * Print report in the output format, and exit with the code of the error
+ */
+fn print_report(output: &Output, report: Result<impl Report>) {
+    output.report(&report);
+    if let Err(e) = report {
+        process::exit(e.exit_code());
+    }
+}
@@ -0,0 +1,133 @@
+use std::{fmt, io};
+
+/**
+ * Error of network probes
+ */
+#[derive(Debug)]
+pub enum NetDoctorError {
+    // raw socket or datalink channel needs root or CAP_NET_RAW
+    PermissionDenied(String),
+    // name of the interface
+    InterfaceNotFound(String),
+    // name of the interface
+    NoIpv4Address(String),
+    // what was waited
+    Timeout(String),
+    MalformedPacket(String),
+    // target which could not be resolved
+    ResolveFailed(String),
+    InvalidArgument(String),
+    // handshake or certificate failure of encrypted dns
+    Tls(String),
+    Io(io::Error)
+}
+
+pub type Result<T> = std::result::Result<T, NetDoctorError>;
+
+impl NetDoctorError {
+    /**
+     * Exit code of the command, taken from sysexits.h
+     */
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            NetDoctorError::InvalidArgument(_) => 64,
+            NetDoctorError::MalformedPacket(_) => 65,
+            NetDoctorError::ResolveFailed(_) => 68,
+            NetDoctorError::InterfaceNotFound(_) => 69,
+            NetDoctorError::Io(_) => 74,
+            NetDoctorError::Timeout(_) => 75,
+            NetDoctorError::Tls(_) => 76,
+            NetDoctorError::PermissionDenied(_) => 77,
+            NetDoctorError::NoIpv4Address(_) => 78
+        }
+    }
+
+    /**
+     * Name of the variant for machine-readable output
+     */
+    pub fn kind(&self) -> &'static str {
+        match self {
+            NetDoctorError::PermissionDenied(_) => "permission_denied",
+            NetDoctorError::InterfaceNotFound(_) => "interface_not_found",
+            NetDoctorError::NoIpv4Address(_) => "no_ipv4_address",
+            NetDoctorError::Timeout(_) => "timeout",
+            NetDoctorError::MalformedPacket(_) => "malformed_packet",
+            NetDoctorError::ResolveFailed(_) => "resolve_failed",
+            NetDoctorError::InvalidArgument(_) => "invalid_argument",
+            NetDoctorError::Tls(_) => "tls",
+            NetDoctorError::Io(_) => "io"
+        }
+    }
+}
+
+impl fmt::Display for NetDoctorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetDoctorError::PermissionDenied(e) =>
+                write!(f, "permission denied ({}), run as root or grant CAP_NET_RAW", e),
+            NetDoctorError::InterfaceNotFound(name) => write!(f, "network interface {} is not found", name),
+            NetDoctorError::NoIpv4Address(name) => write!(f, "network interface {} has no ipv4 address", name),
+            NetDoctorError::Timeout(what) => write!(f, "timed out waiting {}", what),
+            NetDoctorError::MalformedPacket(e) => write!(f, "malformed packet, {}", e),
+            NetDoctorError::ResolveFailed(e) => write!(f, "failed resolve {}", e),
+            NetDoctorError::InvalidArgument(e) => write!(f, "invalid argument, {}", e),
+            NetDoctorError::Tls(e) => write!(f, "tls failure, {}", e),
+            NetDoctorError::Io(e) => write!(f, "i/o error, {}", e)
+        }
+    }
+}
+
+impl std::error::Error for NetDoctorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NetDoctorError::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for NetDoctorError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => NetDoctorError::PermissionDenied(e.to_string()),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => NetDoctorError::Timeout(e.to_string()),
+            _ => NetDoctorError::Io(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_io_error_test() {
+        let e = NetDoctorError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(e, NetDoctorError::PermissionDenied(_)));
+        let e = NetDoctorError::from(io::Error::from(io::ErrorKind::WouldBlock));
+        assert!(matches!(e, NetDoctorError::Timeout(_)));
+        let e = NetDoctorError::from(io::Error::from(io::ErrorKind::AddrInUse));
+        assert!(matches!(e, NetDoctorError::Io(_)));
+    }
+
+    #[test]
+    fn exit_code_test() {
+        let errors = [
+            NetDoctorError::PermissionDenied(String::new()),
+            NetDoctorError::InterfaceNotFound(String::new()),
+            NetDoctorError::NoIpv4Address(String::new()),
+            NetDoctorError::Timeout(String::new()),
+            NetDoctorError::MalformedPacket(String::new()),
+            NetDoctorError::ResolveFailed(String::new()),
+            NetDoctorError::InvalidArgument(String::new()),
+            NetDoctorError::Tls(String::new()),
+            NetDoctorError::Io(io::Error::from(io::ErrorKind::Other))
+        ];
+        let mut codes: Vec<i32> = errors.iter().map(|e| e.exit_code()).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        // codes do not overlap with success, failure and clap usage error
+        assert!(codes.iter().all(|code| *code > 2));
+    }
+}
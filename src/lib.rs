@@ -0,0 +1,8 @@
+/**
+ * Network diagnostics library, the ndt command is a consumer of this api
+ */
+pub mod error;
+pub mod network;
+pub mod output;
+pub mod types;
+pub mod utils;
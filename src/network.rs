@@ -1,6 +1,16 @@
 pub mod arp;
+pub mod banner;
 pub mod ping;
+pub mod pmtu;
 pub mod dns;
+pub mod dns_trace;
+pub mod dnssec;
+pub mod dns_tls;
 pub mod port_scan;
+pub mod resolv_conf;
+pub mod sweep;
+pub mod syn_scan;
+pub mod traceroute;
 pub mod types;
-pub mod udp_echo;
\ No newline at end of file
+pub mod udp_echo;
+pub mod udp_scan;
\ No newline at end of file
@@ -1,20 +1,42 @@
-use std::{vec, net::Ipv4Addr, str::FromStr, thread, time::Duration};
-use log::{error, info, debug};
+use std::{fmt, io::{self, ErrorKind}, vec, net::Ipv4Addr, str::FromStr, time::{Duration, Instant}};
+use log::debug;
 
-use pnet::{packet::{ethernet::{MutableEthernetPacket, EtherTypes, EthernetPacket}, arp::{MutableArpPacket, ArpHardwareType, ArpOperation, ArpPacket, ArpOperations}, Packet}, datalink::{NetworkInterface, channel, Channel}, util::MacAddr};
+use pnet::{packet::{ethernet::{MutableEthernetPacket, EtherTypes, EthernetPacket}, arp::{MutableArpPacket, ArpHardwareType, ArpOperation, ArpPacket, ArpOperations}, Packet}, datalink::{channel, Channel, Config}, util::MacAddr};
 
-use crate::{utils::{os_utils, ip_utils::get_ip_from_nic}, types::ArpConfig};
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, utils::{os_utils, ip_utils::get_ip_from_nic, serde_utils}, types::ArpConfig};
 
 const MAX_PACKET_SIZE: usize = 28;
-const MAX_TIME_OUT_COUNT: usize = 10;
+const ARP_TIME_OUT: u64 = 10000;
+const ARP_READ_TIME_OUT: u64 = 1000;
 
 /**
- * Check Arp
+ * Arp reply of the target
  */
-pub fn arp(config: ArpConfig) {
-    let nic = os_utils::get_active_interface(&config.interface_name);
-    let src_ip = get_ip_from_nic(&nic);
-    let src_mac = nic.mac.unwrap();
+#[derive(Debug, Clone, Serialize)]
+pub struct ArpReply {
+    pub ip: Ipv4Addr,
+    #[serde(serialize_with = "serde_utils::display")]
+    pub mac: MacAddr
+}
+
+impl fmt::Display for ArpReply {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Arp: Target Ip {}, Target Mac Address {}", self.ip, self.mac)
+    }
+}
+
+/**
+ * Check Arp, Timeout if target does not reply
+ */
+pub fn arp(config: ArpConfig) -> Result<ArpReply> {
+    let target_ip = Ipv4Addr::from_str(config.dest_ip.as_str())
+        .map_err(|_| NetDoctorError::InvalidArgument(format!("{} is not ipv4 address", config.dest_ip)))?;
+    let nic = os_utils::get_active_interface(&config.interface_name)?;
+    let src_ip = get_ip_from_nic(&nic)?;
+    let src_mac = nic.mac
+        .ok_or_else(|| NetDoctorError::InvalidArgument(format!("network interface {} has no mac address", nic.name)))?;
     let broadcast = MacAddr::new(255, 255, 255, 255, 255, 255);
 
     // Arp packet
@@ -22,9 +44,9 @@ pub fn arp(config: ArpConfig) {
     let mut a_packet = MutableArpPacket::new(&mut a_packet_buff).unwrap();
     make_arp_packet(&mut a_packet);
     a_packet.set_sender_hw_addr(src_mac);
-    a_packet.set_sender_proto_addr(Ipv4Addr::from_str(src_ip.to_string().as_str()).unwrap());
+    a_packet.set_sender_proto_addr(src_ip);
     a_packet.set_target_hw_addr(MacAddr::new(0,0,0,0,0,0));
-    a_packet.set_target_proto_addr(Ipv4Addr::from_str(config.dest_ip.as_str()).unwrap());
+    a_packet.set_target_proto_addr(target_ip);
 
     // Ethernet packet
     let mut e_packet_buff: Vec<u8> = vec![0; MAX_PACKET_SIZE * 2];
@@ -32,63 +54,53 @@ pub fn arp(config: ArpConfig) {
     e_packet.set_ethertype(EtherTypes::Arp);
     e_packet.set_source(src_mac);
     e_packet.set_destination(broadcast);
-    e_packet.set_payload(&a_packet.packet());
+    e_packet.set_payload(a_packet.packet());
 
-    let channel = channel(&nic, Default::default());
-    let (mut tx, mut rx) =  match channel {
-        Ok(Channel::Ethernet(tx, rx)) => {
-            (tx, rx)
-        }
-        Ok(_) => {
-            error!("Arp: Failed channel");
-            panic!();
-        }
-        Err(e) => {
-            error!("Arp: {:?}", e);
-            panic!();
-        }
+    let channel_config = Config { read_timeout: Some(Duration::from_millis(ARP_READ_TIME_OUT)), ..Default::default() };
+    let (mut tx, mut rx) = match channel(&nic, channel_config)? {
+        Channel::Ethernet(tx, rx) => (tx, rx),
+        _ => return Err(NetDoctorError::Io(io::Error::other(format!("{} is not ethernet channel", nic.name))))
     };
 
     // Send Arp
     debug!("Arp: Send packet {:?}", e_packet);
-    tx.send_to(&e_packet.packet(), None);
+    if let Some(res) = tx.send_to(e_packet.packet(), None) {
+        res?;
+    }
 
     // receive Arp
-    let mut timeout_count= 0;
+    let deadline = Instant::now() + Duration::from_millis(ARP_TIME_OUT);
     loop {
+        if Instant::now() >= deadline {
+            return Err(NetDoctorError::Timeout(format!("arp reply of {}", target_ip)));
+        }
         match rx.next() {
             Ok(res_packet) => {
-                let e_res_packet = EthernetPacket::new(res_packet).unwrap();
+                let Some(e_res_packet) = EthernetPacket::new(res_packet) else {
+                    continue;
+                };
                 debug!("Arp: Response packet {:?}", e_res_packet);
-                match e_res_packet.get_ethertype() {
-                    EtherTypes::Arp => {
-                        let a_res_packet = ArpPacket::new(&e_res_packet.payload()).unwrap();
-                        if a_res_packet.get_operation() != ArpOperations::Reply {
-                            continue;
-                        }
-                        debug!("Arp: Response arp packet {:?}", a_res_packet);
-                        info!("Arp: Target Ip {}, Target Mac Address {}"
-                            , a_res_packet.get_sender_proto_addr(), a_res_packet.get_sender_hw_addr());
-                        break;
-                    }
-                    _ => {
-                        debug!("Arp: Not arp packet, {}", e_res_packet.get_ethertype());
-                    }
+                if e_res_packet.get_ethertype() != EtherTypes::Arp {
+                    debug!("Arp: Not arp packet, {}", e_res_packet.get_ethertype());
+                    continue;
+                }
+                let Some(a_res_packet) = ArpPacket::new(e_res_packet.payload()) else {
+                    continue;
                 };
-
-                if timeout_count > MAX_TIME_OUT_COUNT {
-                    info!("Arp: Getting arp packet is TimeOut");
-                    break;
+                if a_res_packet.get_operation() != ArpOperations::Reply
+                    || a_res_packet.get_sender_proto_addr() != target_ip {
+                    continue;
                 }
-                thread::sleep(Duration::from_millis(1000));
-                timeout_count += 1;
-            }
-            Err(e) => {
-                error!("Arp: Failed Receive {:?}", e);
-                panic!();
+                debug!("Arp: Response arp packet {:?}", a_res_packet);
+                let reply = ArpReply {
+                    ip: a_res_packet.get_sender_proto_addr(),
+                    mac: a_res_packet.get_sender_hw_addr()
+                };
+                return Ok(reply);
             }
+            Err(e) if e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(e.into())
         }
-        
     }
 }
 
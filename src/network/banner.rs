@@ -0,0 +1,329 @@
+use std::{fmt, io::{self, ErrorKind, Read, Write}, net::{IpAddr, SocketAddr, TcpStream}, time::Duration};
+use log::debug;
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+const MAX_BANNER_SIZE: usize = 1024;
+const REDIS_PORT: u16 = 6379;
+const HTTP_PORT: u16 = 80;
+// protocol version of mysql handshake v10
+const MYSQL_PROTOCOL_VERSION: u8 = 10;
+const MYSQL_ERROR_PACKET: u8 = 0xff;
+
+/**
+ * Service identified from banner
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Service {
+    Ssh,
+    Smtp,
+    Ftp,
+    Pop3,
+    Imap,
+    Redis,
+    Mysql,
+    Http,
+    // something answered but it is not known
+    Unknown
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Service::Ssh => "ssh",
+            Service::Smtp => "smtp",
+            Service::Ftp => "ftp",
+            Service::Pop3 => "pop3",
+            Service::Imap => "imap",
+            Service::Redis => "redis",
+            Service::Mysql => "mysql",
+            Service::Http => "http",
+            Service::Unknown => "unknown"
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/**
+ * Banner of the service on a port
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServiceBanner {
+    pub port: u16,
+    pub service: Service,
+    // software and version, e.g. OpenSSH_9.6p1
+    pub version: Option<String>,
+    // first line of the response with control characters escaped
+    pub banner: String
+}
+
+/**
+ * Grab banners of ports concurrently, ports without any response are omitted.
+ * Results are ordered as ports.
+ */
+pub fn grab_banners(dest_ip: IpAddr, ports: &[u16], timeout: Duration, concurrency: usize) -> io::Result<Vec<ServiceBanner>> {
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(concurrency).build().map_err(io::Error::other)?;
+    Ok(pool.install(|| {
+        ports.par_iter()
+            .filter_map(|port| grab_banner(SocketAddr::new(dest_ip, *port), timeout))
+            .collect()
+    }))
+}
+
+/**
+ * Read greeting of the service, or send a probe when the service waits a request
+ */
+pub fn grab_banner(addr: SocketAddr, timeout: Duration) -> Option<ServiceBanner> {
+    let mut stream = TcpStream::connect_timeout(&addr, timeout).ok()?;
+    stream.set_read_timeout(Some(timeout)).ok()?;
+    stream.set_write_timeout(Some(timeout)).ok()?;
+
+    let mut response = read_response(&mut stream, |response| response.ends_with(b"\n"));
+    if response.is_empty() {
+        // redis and http do not greet
+        if addr.port() == REDIS_PORT {
+            stream.write_all(b"INFO server\r\n").ok()?;
+            response = read_response(&mut stream, |response| response.ends_with(b"\n"));
+        } else {
+            stream.write_all(format!("HEAD / HTTP/1.0\r\nHost: {}\r\n\r\n", host_header(addr)).as_bytes()).ok()?;
+            // headers may arrive in several segments, they end with a blank line
+            response = read_response(&mut stream, |response| response.windows(4).any(|end| end == b"\r\n\r\n")
+                || response.windows(2).any(|end| end == b"\n\n"));
+        }
+    }
+    debug!("Banner: {} {:?}", addr, String::from_utf8_lossy(&response));
+    if response.is_empty() {
+        return None;
+    }
+    Some(identify(addr.port(), &response))
+}
+
+/**
+ * Host header of the http probe, ipv6 address is enclosed in brackets and the port is added unless it is 80
+ */
+fn host_header(addr: SocketAddr) -> String {
+    match (addr.ip(), addr.port()) {
+        (IpAddr::V6(ip), HTTP_PORT) => format!("[{}]", ip),
+        (ip, HTTP_PORT) => ip.to_string(),
+        // SocketAddr encloses ipv6 address in brackets
+        _ => addr.to_string()
+    }
+}
+
+/**
+ * Read the first response until it is complete, timeout, close or the size limit
+ */
+fn read_response(stream: &mut TcpStream, is_complete: fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut buf: Vec<u8> = vec![0; MAX_BANNER_SIZE];
+    let mut size = 0;
+    while size < MAX_BANNER_SIZE {
+        match stream.read(&mut buf[size..]) {
+            Ok(0) => break,
+            Ok(n) => {
+                size += n;
+                if is_complete(&buf[..size]) {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => break
+        }
+    }
+    buf.truncate(size);
+    buf
+}
+
+/**
+ * Identify service and version from the response
+ */
+pub fn identify(port: u16, response: &[u8]) -> ServiceBanner {
+    let text = String::from_utf8_lossy(response);
+    let first_line = text.lines().next().unwrap_or_default().trim().to_string();
+    let mut banner = ServiceBanner {
+        port,
+        service: Service::Unknown,
+        version: None,
+        banner: first_line.escape_debug().to_string()
+    };
+
+    if let Some(version) = get_mysql_version(response) {
+        banner.service = Service::Mysql;
+        // message of error packet follows error code
+        banner.banner = match &version {
+            Some(version) => version.clone(),
+            None => String::from_utf8_lossy(response.get(7..).unwrap_or_default()).escape_debug().to_string()
+        };
+        banner.version = version;
+        return banner;
+    }
+
+    // text after the status of greeting line
+    let rest = |prefix: &str| -> Option<String> {
+        let rest = first_line[prefix.len()..].trim_start_matches(['-', ' ']).trim();
+        (!rest.is_empty()).then(|| rest.to_string())
+    };
+    if first_line.starts_with("SSH-") {
+        banner.service = Service::Ssh;
+        // SSH-protoversion-softwareversion SP comments (RFC 4253)
+        banner.version = first_line.splitn(3, '-').nth(2)
+            .map(|software| software.split(' ').next().unwrap_or(software).to_string());
+    } else if first_line.starts_with("220") {
+        let upper = first_line.to_uppercase();
+        banner.service = if upper.contains("SMTP") || upper.contains("MAIL") {
+            Service::Smtp
+        } else if upper.contains("FTP") || port == 21 {
+            Service::Ftp
+        } else if port == 25 || port == 587 {
+            Service::Smtp
+        } else {
+            Service::Unknown
+        };
+        banner.version = rest("220");
+    } else if first_line.starts_with("+OK") {
+        banner.service = Service::Pop3;
+        banner.version = rest("+OK");
+    } else if first_line.starts_with("* OK") {
+        banner.service = Service::Imap;
+        banner.version = rest("* OK");
+    } else if first_line.starts_with("HTTP/") {
+        banner.service = Service::Http;
+        banner.version = text.lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("server").then(|| value.trim().to_string())
+            });
+    } else if text.contains("redis_version:") || first_line.starts_with("-NOAUTH") || first_line.starts_with("-DENIED") {
+        banner.service = Service::Redis;
+        banner.version = text.lines()
+            .find_map(|line| line.strip_prefix("redis_version:"))
+            .map(|version| version.trim().to_string());
+    }
+    banner
+}
+
+/**
+ * Get server version from mysql initial handshake packet.
+ * None if it is not mysql, Some(None) if the server rejected with error packet.
+ */
+fn get_mysql_version(response: &[u8]) -> Option<Option<String>> {
+    // 3 bytes payload length and 1 byte sequence id
+    if response.len() < 5 || response[3] != 0 {
+        return None;
+    }
+    let length = u32::from_le_bytes([response[0], response[1], response[2], 0]) as usize;
+    if length + 4 != response.len() {
+        return None;
+    }
+    let payload = &response[4..];
+    match payload[0] {
+        MYSQL_PROTOCOL_VERSION => {
+            let end = payload.iter().position(|b| *b == 0)?;
+            let version = std::str::from_utf8(&payload[1..end]).ok()?;
+            Some(Some(version.to_string()))
+        }
+        // e.g. Host is not allowed to connect to this MySQL server
+        MYSQL_ERROR_PACKET => Some(None),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, thread};
+
+    use super::*;
+
+    #[test]
+    fn identify_greeting_test() {
+        let banner = identify(22, b"SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13\r\n");
+        assert_eq!(banner.service, Service::Ssh);
+        assert_eq!(banner.version.as_deref(), Some("OpenSSH_9.6p1"));
+        assert_eq!(banner.banner, "SSH-2.0-OpenSSH_9.6p1 Ubuntu-3ubuntu13");
+
+        let banner = identify(25, b"220 mail.example.com ESMTP Postfix (Ubuntu)\r\n");
+        assert_eq!(banner.service, Service::Smtp);
+        assert_eq!(banner.version.as_deref(), Some("mail.example.com ESMTP Postfix (Ubuntu)"));
+
+        assert_eq!(identify(2121, b"220 (vsFTPd 3.0.5)\r\n").service, Service::Ftp);
+        assert_eq!(identify(21, b"220 Welcome\r\n").service, Service::Ftp);
+        assert_eq!(identify(110, b"+OK Dovecot ready.\r\n").service, Service::Pop3);
+
+        let banner = identify(143, b"* OK [CAPABILITY IMAP4rev1 LITERAL+] Dovecot ready.\r\n");
+        assert_eq!(banner.service, Service::Imap);
+        assert_eq!(banner.version.as_deref(), Some("[CAPABILITY IMAP4rev1 LITERAL+] Dovecot ready."));
+
+        assert_eq!(identify(9999, b"hello\r\n").service, Service::Unknown);
+    }
+
+    #[test]
+    fn identify_probe_test() {
+        let banner = identify(8080, b"HTTP/1.1 200 OK\r\nDate: Mon, 01 Jan 2024 00:00:00 GMT\r\nserver: nginx/1.24.0\r\n\r\n");
+        assert_eq!(banner.service, Service::Http);
+        assert_eq!(banner.version.as_deref(), Some("nginx/1.24.0"));
+
+        let banner = identify(6379, b"$120\r\n# Server\r\nredis_version:7.2.4\r\nredis_mode:standalone\r\n");
+        assert_eq!(banner.service, Service::Redis);
+        assert_eq!(banner.version.as_deref(), Some("7.2.4"));
+        assert_eq!(identify(6379, b"-NOAUTH Authentication required.\r\n").service, Service::Redis);
+    }
+
+    #[test]
+    fn identify_mysql_test() {
+        let mut payload = vec![MYSQL_PROTOCOL_VERSION];
+        payload.extend_from_slice(b"8.0.36-0ubuntu0.22.04.1\0");
+        payload.extend_from_slice(&[0x08, 0, 0, 0, 0x2a, 0x3b]);
+        let mut packet = vec![payload.len() as u8, 0, 0, 0];
+        packet.extend_from_slice(&payload);
+
+        let banner = identify(3306, &packet);
+        assert_eq!(banner.service, Service::Mysql);
+        assert_eq!(banner.version.as_deref(), Some("8.0.36-0ubuntu0.22.04.1"));
+
+        let packet = [5, 0, 0, 0, MYSQL_ERROR_PACKET, 0x6a, 0x04, b'H', b'o'];
+        let banner = identify(3306, &packet);
+        assert_eq!(banner.service, Service::Mysql);
+        assert_eq!(banner.version, None);
+        assert_eq!(banner.banner, "Ho");
+    }
+
+    #[test]
+    fn host_header_test() {
+        assert_eq!(host_header(SocketAddr::from(([192, 0, 2, 1], 80))), "192.0.2.1");
+        assert_eq!(host_header(SocketAddr::new(IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]), 80)), "[2001:db8::1]");
+        assert_eq!(host_header(SocketAddr::new(IpAddr::from([0x2001, 0xdb8, 0, 0, 0, 0, 0, 1]), 8080)), "[2001:db8::1]:8080");
+    }
+
+    #[test]
+    fn grab_banners_test() {
+        let greeting = TcpListener::bind("127.0.0.1:0").unwrap();
+        let greeting_port = greeting.local_addr().unwrap().port();
+        let http = TcpListener::bind("127.0.0.1:0").unwrap();
+        let http_port = http.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = greeting.accept().unwrap();
+            stream.write_all(b"SSH-2.0-Test_1.0\r\n").unwrap();
+        });
+        thread::spawn(move || {
+            let (mut stream, _) = http.accept().unwrap();
+            let mut buf = [0; 128];
+            let size = stream.read(&mut buf).unwrap();
+            let request = String::from_utf8_lossy(&buf[..size]).to_string();
+            assert!(request.starts_with(&format!("HEAD / HTTP/1.0\r\nHost: 127.0.0.1:{}\r\n", http_port)), "{}", request);
+            // server header comes in a later segment
+            stream.write_all(b"HTTP/1.0 200 OK\r\n").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            stream.write_all(b"Server: test/2.0\r\n\r\n").unwrap();
+        });
+
+        let mut ports = vec![greeting_port, http_port];
+        ports.sort_unstable();
+        let banners = grab_banners(IpAddr::from([127, 0, 0, 1]), &ports, Duration::from_millis(300), 2).unwrap();
+        assert_eq!(banners.len(), 2);
+        let banner = banners.iter().find(|b| b.port == greeting_port).unwrap();
+        assert_eq!((banner.service, banner.version.as_deref()), (Service::Ssh, Some("Test_1.0")));
+        let banner = banners.iter().find(|b| b.port == http_port).unwrap();
+        assert_eq!((banner.service, banner.version.as_deref()), (Service::Http, Some("test/2.0")));
+    }
+}
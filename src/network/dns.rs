@@ -1,102 +1,762 @@
-use std::{net::{Ipv4Addr, IpAddr, UdpSocket, SocketAddr, SocketAddrV4}, str::FromStr, vec, time::Duration};
-use log::{error, info};
+use std::{fmt, io::{ErrorKind, Read, Write}, net::{Ipv4Addr, Ipv6Addr, IpAddr, TcpStream, UdpSocket, SocketAddr, ToSocketAddrs}, str::FromStr, vec, time::{Duration, Instant}};
+use log::info;
 use log::debug;
 
-use pnet::{transport::{transport_channel, udp_packet_iter, ipv4_packet_iter}, packet::{ip::IpNextHeaderProtocols, udp::{MutableUdpPacket, ipv4_checksum, UdpPacket}, ipv4::{MutableIpv4Packet, Ipv4Packet}, Packet}, datalink::{channel, Channel}};
 use rand::Rng;
+use serde::Serialize;
+use socket2::{Domain, Protocol, Socket, Type};
 
-use crate::{types::DnsConfig, utils::{os_utils::get_active_interface, ip_utils::get_ip_from_nic}};
+use crate::{error::{NetDoctorError, Result}, types::{DnsConfig, EdnsConfig, IpVersion, RecordType, ResolveConfig, Resolver}, utils::{os_utils::get_active_interface, ip_utils::get_ip_from_nic, serde_utils}};
 
-use super::types::{dns_packet::{DnsPacket, DnsHeader, DnsQueryData}};
+use super::{dns_tls::{make_tls, HttpsEndpoint, query_https, query_tls, TlsInfo, TlsOptions, DOT_PORT}, resolv_conf::{parse_server_with_port, ResolvConf, DNS_PORT, RESOLV_CONF_PATH}, types::{dns_packet::{read_header, type_name, DnsPacket, DnsHeader, DnsQueryData, DnsAnswer, DnsMessage, RData, Rcode}, edns::{Edns, EdnsOption}}};
 
-const MAX_PACKET_SIZE: usize = 64;
-const DNS_SERVER: &str = "8.8.8.8";
-const DNS_TIMEOUT: u64 = 3000;
-const DNS_PORT: u16 = 53;
+// udp response is not limited to 512 bytes when the server ignores it
+const MAX_MESSAGE_SIZE: usize = u16::MAX as usize;
+
+/**
+ * Transport of dns messages
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Udp,
+    // messages are prefixed by 2 bytes length (RFC 1035 4.2.2)
+    Tcp,
+    // DNS-over-TLS, tcp messages in TLS (RFC 7858)
+    Tls,
+    // DNS-over-HTTPS, a message in the body of https request (RFC 8484)
+    Https
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transport::Udp => write!(f, "udp"),
+            Transport::Tls => write!(f, "tls"),
+            Transport::Https => write!(f, "https"),
+            Transport::Tcp => write!(f, "tcp")
+        }
+    }
+}
+
+/**
+ * Dns response of the query
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsResponse {
+    // server which answered
+    pub server: SocketAddr,
+    pub domain: String,
+    // queried name, domain may be expanded by search domains
+    pub name: String,
+    pub record_type: RecordType,
+    pub rcode: Rcode,
+    pub flags: DnsHeader,
+    pub answers: Vec<DnsAnswer>,
+    pub transport: Transport,
+    // udp response was truncated and the query was retried over tcp
+    pub truncated: bool,
+    // OPT record of the response
+    pub edns: Option<Edns>,
+    // session of DoT or DoH
+    pub tls: Option<TlsInfo>,
+    // round trip time of the answered query
+    #[serde(rename = "time_ms", serialize_with = "serde_utils::millis")]
+    pub time: Duration
+}
+
+impl fmt::Display for DnsResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.truncated {
+            writeln!(f, "DNS: udp response was truncated, retried over tcp")?;
+        }
+        write!(f, "DNS: {} answers of {} {} from {} over {} in {:.3} ms", self.answers.len(), self.name, self.record_type,
+            self.server, self.transport, self.time.as_secs_f64() * 1000.0)?;
+        write!(f, "\nDNS: status={} opcode={} flags={}", self.rcode, self.flags.opcode, self.flags)?;
+        if let Some(tls) = &self.tls {
+            write!(f, "\nDNS: tls {}", tls)?;
+            for certificate in &tls.certificates {
+                write!(f, "\nDNS: tls certificate {}", certificate)?;
+            }
+        }
+        if let Some(edns) = &self.edns {
+            write!(f, "\nDNS: edns {}", edns)?;
+            for option in &edns.options {
+                write!(f, "\nDNS: edns option {}", option)?;
+            }
+        }
+        for a in &self.answers {
+            write!(f, "\nDNS: answer name={} type={} ttl={} data={}", a.name, type_name(a.dns_type), a.time, a.data)?;
+        }
+        Ok(())
+    }
+}
 
 /**
  * Check Dns
  */
-pub fn dns(config: DnsConfig) {
+pub fn dns(config: DnsConfig) -> Result<DnsResponse> {
 
-    let interface = get_active_interface(&config.interface_name); 
-    let src_str_ip = get_ip_from_nic(&interface).to_string(); 
-    let dest_ip = Ipv4Addr::from_str(DNS_SERVER).unwrap();
+    let interface = get_active_interface(&config.interface_name)?;
+    let src_ip = get_ip_from_nic(&interface)?;
 
-    let mut dns_packet = DnsPacket::new();
-    make_dns_packet(&mut dns_packet, &config.domain);
-    dns_packet.make_packet();
+    let conf = ResolvConf::load(RESOLV_CONF_PATH)?;
+    let servers = nameservers(&config, &conf)?;
 
-    debug!("DNS: dns_packet {:?}", &dns_packet);
+    let (domain, record_type) = query_name(&config)?;
+    let options = QueryOptions { tcp: config.tcp, edns: make_edns(&config.edns)?, tls: make_tls(&config.tls)?, ..Default::default() };
+    let exchange = resolve(&domain, record_type.code(), &servers, &conf, Some(src_ip), &options)?;
+    if exchange.rcode == Rcode::NoError && exchange.answers.is_empty() {
+        info!("DNS: no answer");
+    }
+    if options.edns.is_some() && exchange.edns.is_none() {
+        info!("DNS: {} does not support edns", exchange.server);
+    }
+    Ok(DnsResponse {
+        server: exchange.server,
+        domain: config.domain,
+        name: exchange.name,
+        record_type,
+        rcode: exchange.rcode,
+        flags: exchange.header,
+        answers: exchange.answers,
+        transport: exchange.transport,
+        truncated: exchange.transport == Transport::Tcp && !config.tcp,
+        edns: exchange.edns,
+        tls: exchange.tls,
+        time: exchange.time
+    })
+}
+
+/**
+ * Server of the option, or nameservers of resolv.conf.
+ * DoT uses port 853 unless the server gives one, DoH connects to the host of the url.
+ */
+pub fn nameservers(config: &DnsConfig, conf: &ResolvConf) -> Result<Vec<SocketAddr>> {
+    if let Some(url) = &config.tls.https {
+        return https_servers(url, config.server.as_deref());
+    }
+    let port = if config.tls.tls { DOT_PORT } else { DNS_PORT };
+    match &config.server {
+        Some(server) => Ok(vec![parse_server_with_port(server, port).ok_or_else(|| NetDoctorError::InvalidArgument(
+            format!("server {}, expected like 192.0.2.53, 192.0.2.53:5353 or [2001:db8::53]:5353", server)))?]),
+        None => Ok(conf.nameservers.iter().map(|server| {
+            let mut server = *server;
+            server.set_port(port);
+            server
+        }).collect())
+    }
+}
 
-    let bind_port: u16 = rand::thread_rng().gen();
-    let bind_addr = src_str_ip + ":" + bind_port.to_string().as_str();
-    let socket = UdpSocket::bind(bind_addr).unwrap();
+/**
+ * Address of DoH server, the server option or A and AAAA of the host of the url which is resolved over plain dns
+ */
+fn https_servers(url: &str, server: Option<&str>) -> Result<Vec<SocketAddr>> {
+    let endpoint = HttpsEndpoint::parse(url, false)
+        .ok_or_else(|| NetDoctorError::InvalidArgument(format!("https url {}, expected like https://dns.example/dns-query", url)))?;
+    if let Some(server) = server {
+        return Ok(vec![parse_server_with_port(server, endpoint.port).ok_or_else(|| NetDoctorError::InvalidArgument(
+            format!("server {}, expected like 192.0.2.53 or [2001:db8::53]:8443", server)))?]);
+    }
+    if let Ok(ip) = IpAddr::from_str(&endpoint.host) {
+        return Ok(vec![SocketAddr::new(ip, endpoint.port)]);
+    }
+    // addresses of both versions are tried in order, ipv4 first
+    let addresses: Vec<SocketAddr> = lookup_any(&endpoint.host)?.into_iter().map(|ip| SocketAddr::new(ip, endpoint.port)).collect();
+    if addresses.is_empty() {
+        return Err(NetDoctorError::ResolveFailed(format!("{}, no address of the https server", endpoint.host)));
+    }
+    Ok(addresses)
+}
 
-    let mut is_reach = false;
+/**
+ * Name and type to query, PTR name of the address in reverse lookup
+ */
+pub fn query_name(config: &DnsConfig) -> Result<(String, RecordType)> {
+    if !config.reverse {
+        return Ok((config.domain.clone(), config.record_type));
+    }
+    let ip = IpAddr::from_str(&config.domain)
+        .map_err(|_| NetDoctorError::InvalidArgument(format!("{} is not ip address", config.domain)))?;
+    // absolute name is not expanded by search domains
+    Ok((format!("{}.", reverse_name(&ip)), RecordType::Ptr))
+}
 
-    socket.set_read_timeout(Some(Duration::from_millis(DNS_TIMEOUT)));
-    loop {
-        let send_packet = Ipv4Packet::new(dns_packet.packet()).unwrap();
-        debug!("DNS: send_packet: {:?}", &send_packet);
-        let addr = SocketAddr::V4(SocketAddrV4::new(dest_ip, DNS_PORT));
-        socket.send_to(send_packet.packet(), addr).unwrap();
-        let mut res_buff: Vec<u8> = vec![0; MAX_PACKET_SIZE * 2];
-        match socket.recv_from(&mut res_buff) {
-            Ok(_) => {
-                match DnsPacket::get_answar(res_buff) {
-                    Some(ans) => {
-                        for a in ans {
-                            info!("DNS: answer name={} addr={}", a.name, a.address);
-                            break;
-                        }
-                    }
-                    None => {
-                        error!("DNS: no answer");
-                        break;
-                    }
-                };
-                is_reach = true;
-            }
+/**
+ * OPT record of the query, None if EDNS0 is disabled
+ */
+pub fn make_edns(config: &EdnsConfig) -> Result<Option<Edns>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+    let mut edns = Edns::new(config.udp_size);
+    edns.dnssec_ok = config.dnssec_ok;
+    if let Some(subnet) = &config.client_subnet {
+        edns.options.push(EdnsOption::client_subnet(subnet).ok_or_else(|| NetDoctorError::InvalidArgument(
+            format!("subnet {}, expected like 192.0.2.0/24 or 2001:db8::/56", subnet)))?);
+    }
+    if config.nsid {
+        edns.options.push(EdnsOption::Nsid { id: Vec::new() });
+    }
+    if config.cookie {
+        edns.options.push(EdnsOption::client_cookie());
+    }
+    Ok(Some(edns))
+}
+
+/**
+ * Lookup ip addresses of domain
+ */
+pub fn lookup(domain: &str, record_type: RecordType) -> Result<Vec<IpAddr>> {
+    let conf = ResolvConf::load(RESOLV_CONF_PATH)?;
+    let exchange = resolve(domain, record_type.code(), &conf.nameservers, &conf, None, &QueryOptions::default())?;
+    if exchange.rcode != Rcode::NoError {
+        return Err(NetDoctorError::ResolveFailed(format!("{}, {} from {}", domain, exchange.rcode, exchange.server)));
+    }
+    Ok(exchange.answers
+        .into_iter()
+        .filter_map(|a| match a.data {
+            RData::A(ip) => Some(IpAddr::V4(ip)),
+            RData::Aaaa(ip) => Some(IpAddr::V6(ip)),
+            _ => None
+        })
+        .collect())
+}
+
+/**
+ * Lookup ipv4 and then ipv6 addresses of domain, fails only when no address is found and a lookup failed
+ */
+fn lookup_any(domain: &str) -> Result<Vec<IpAddr>> {
+    let mut ips: Vec<IpAddr> = Vec::new();
+    let mut last_error: Option<NetDoctorError> = None;
+    for record_type in [RecordType::A, RecordType::Aaaa] {
+        match lookup(domain, record_type) {
+            Ok(found) => ips.extend(found),
             Err(e) => {
-                error!("DNS: {:?}", e);
-                panic!("{:?}", e);
+                debug!("DNS: {} {} failed, {}", domain, record_type, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    match last_error {
+        Some(e) if ips.is_empty() => Err(e),
+        _ => Ok(ips)
+    }
+}
+
+/**
+ * Name of PTR query, 1.2.0.192.in-addr.arpa or reversed nibbles of ip6.arpa (RFC 3596)
+ */
+pub fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", octets[3], octets[2], octets[1], octets[0])
+        }
+        IpAddr::V6(ip) => {
+            let nibbles: Vec<String> = ip.octets().iter().rev()
+                .flat_map(|b| [b & 0x0f, b >> 4])
+                .map(|nibble| format!("{:x}", nibble))
+                .collect();
+            format!("{}.ip6.arpa", nibbles.join("."))
+        }
+    }
+}
+
+/**
+ * Look up name of the address by PTR record, None if it has no name
+ */
+pub fn reverse_lookup(ip: &IpAddr) -> Result<Option<String>> {
+    let conf = ResolvConf::load(RESOLV_CONF_PATH)?;
+    let exchange = resolve(&format!("{}.", reverse_name(ip)), RecordType::Ptr.code(), &conf.nameservers, &conf, None, &QueryOptions::default())?;
+    Ok(exchange.answers.into_iter().find_map(|a| match a.data {
+        RData::Ptr(name) => Some(name),
+        _ => None
+    }))
+}
+
+/**
+ * Name of the address for annotation, failure of lookup is only logged
+ */
+pub fn annotate(ip: &IpAddr) -> Option<String> {
+    reverse_lookup(ip).unwrap_or_else(|e| {
+        debug!("DNS: reverse lookup of {} {}", ip, e);
+        None
+    })
+}
+
+/**
+ * Resolve target hostname to ip address
+ */
+pub fn resolve_target(target: &str, resolve: &ResolveConfig) -> Result<IpAddr> {
+    // target is already ip address
+    if let Ok(ip) = IpAddr::from_str(target) {
+        if !resolve.ip_version.matches(&ip) {
+            return Err(NetDoctorError::ResolveFailed(format!("{}, it is not {:?} address", ip, resolve.ip_version)));
+        }
+        return Ok(ip);
+    }
+
+    let ips: Vec<IpAddr> = match resolve.resolver {
+        Resolver::System => {
+            match (target, 0).to_socket_addrs() {
+                Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+                Err(e) => {
+                    debug!("Resolve: {:?}", e);
+                    Vec::new()
+                }
+            }
+        }
+        Resolver::Dns => {
+            match resolve.ip_version {
+                IpVersion::V4 => lookup(target, RecordType::A)?,
+                IpVersion::V6 => lookup(target, RecordType::Aaaa)?,
+                IpVersion::Any => lookup_any(target)?
+            }
+        }
+    };
+
+    let ip = ips.into_iter().find(|ip| resolve.ip_version.matches(ip))
+        .ok_or_else(|| NetDoctorError::ResolveFailed(format!("{} by {:?} resolver", target, resolve.resolver)))?;
+    info!("Resolve: {} is resolved to {} by {:?} resolver", target, ip, resolve.resolver);
+    Ok(ip)
+}
+
+/**
+ * Resolve target to ipv4 address unless ipv6 is forced, for ipv4 only commands
+ */
+pub fn resolve_target_v4(target: &str, resolve: &ResolveConfig) -> Result<Ipv4Addr> {
+    match resolve_target(target, &resolve.ipv4_only())? {
+        IpAddr::V4(ip) => Ok(ip),
+        IpAddr::V6(ip) => Err(NetDoctorError::ResolveFailed(format!("{}, only ipv4 is supported", ip)))
+    }
+}
+
+/**
+ * Options of queries
+ */
+#[derive(Debug, Clone)]
+pub struct QueryOptions {
+    // query over tcp without udp
+    pub tcp: bool,
+    // OPT record of queries, None to query without EDNS0
+    pub edns: Option<Edns>,
+    // RD, false to query authoritative servers iteratively
+    pub recursion_desired: bool,
+    // CD, the resolver returns data which fails its dnssec validation
+    pub checking_disabled: bool,
+    // DoT or DoH instead of udp and tcp
+    pub tls: Option<TlsOptions>
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        QueryOptions { tcp: false, edns: None, recursion_desired: true, checking_disabled: false, tls: None }
+    }
+}
+
+/**
+ * Answers of a query and the server which answered
+ */
+struct Exchange {
+    server: SocketAddr,
+    name: String,
+    header: DnsHeader,
+    rcode: Rcode,
+    answers: Vec<DnsAnswer>,
+    transport: Transport,
+    // OPT record of the response
+    edns: Option<Edns>,
+    tls: Option<TlsInfo>,
+    time: Duration
+}
+
+/**
+ * Query names expanded by search domains in order, until a name has answers.
+ * Ipv4 servers are queried from the source address if it is given.
+ */
+fn resolve(domain: &str, dns_type: u16, servers: &[SocketAddr], conf: &ResolvConf, source: Option<Ipv4Addr>, options: &QueryOptions) -> Result<Exchange> {
+    let mut result: Option<Result<Exchange>> = None;
+    for name in conf.candidates(domain) {
+        match query_servers(&name, dns_type, servers, conf, source, options) {
+            Ok(exchange) if !exchange.answers.is_empty() => return Ok(exchange),
+            Ok(exchange) => {
+                debug!("DNS: no answer of {} from {}", name, exchange.server);
+                result = Some(Ok(exchange));
             }
+            // empty answer of other name is more useful than error
+            Err(e) if matches!(result, Some(Ok(_))) => debug!("DNS: {} {}", name, e),
+            Err(e) => result = Some(Err(e))
+        }
+    }
+    result.unwrap_or_else(|| Err(NetDoctorError::InvalidArgument(format!("domain {}", domain))))
+}
 
+/**
+ * Query servers in turn for attempts rounds, until a server responds.
+ * SERVFAIL, NOTIMP and REFUSED let the next server be tried like glibc.
+ */
+fn query_servers(name: &str, dns_type: u16, servers: &[SocketAddr], conf: &ResolvConf, source: Option<Ipv4Addr>, options: &QueryOptions) -> Result<Exchange> {
+    let mut last_error = NetDoctorError::InvalidArgument("no dns server".to_string());
+    // failure response is more useful than timeout of other servers
+    let mut failure: Option<Exchange> = None;
+    for _ in 0..conf.attempts {
+        for server in servers {
+            let start = Instant::now();
+            match query(*server, source, name, dns_type, options, conf.timeout()) {
+                Ok((message, transport, tls)) => {
+                    let exchange = Exchange {
+                        server: *server,
+                        name: name.to_string(),
+                        header: message.header,
+                        rcode: message.rcode(),
+                        answers: message.answers.iter().map(DnsAnswer::from).collect(),
+                        transport,
+                        edns: message.edns(),
+                        tls,
+                        time: start.elapsed()
+                    };
+                    if !matches!(exchange.rcode, Rcode::ServFail | Rcode::NotImp | Rcode::Refused) {
+                        return Ok(exchange);
+                    }
+                    info!("DNS: {} {} of {}", server, exchange.rcode, name);
+                    failure = Some(exchange);
+                }
+                Err(e) => {
+                    info!("DNS: {} {}", server, e);
+                    last_error = e;
+                }
+            }
+        }
+    }
+    failure.ok_or(last_error)
+}
+
+/**
+ * Query a server over udp and retry over tcp if the response is truncated, or query over tcp only.
+ * DoT and DoH return the session of the encrypted connection.
+ */
+pub fn query(server: SocketAddr, source: Option<Ipv4Addr>, name: &str, dns_type: u16, options: &QueryOptions, timeout: Duration) -> Result<(DnsMessage, Transport, Option<TlsInfo>)> {
+    let mut dns_packet = DnsPacket::new();
+    make_dns_packet(&mut dns_packet, name, dns_type, options.edns.clone());
+    dns_packet.flags.recursion_desired = options.recursion_desired;
+    dns_packet.flags.checking_disabled = options.checking_disabled;
+    if let Some(TlsOptions { https: Some(_), .. }) = &options.tls {
+        // id 0 lets http caches share responses (RFC 8484 4.1)
+        dns_packet.transaction_id = 0;
+    }
+    dns_packet.make_packet()?;
+
+    debug!("DNS: dns_packet {:?}", &dns_packet);
+
+    if let Some(tls) = &options.tls {
+        return match &tls.https {
+            Some(endpoint) => {
+                let (message, session) = query_https(server, source, &dns_packet, tls, endpoint, timeout)?;
+                Ok((message, Transport::Https, Some(session)))
+            }
+            None => {
+                let (message, session) = query_tls(server, source, &dns_packet, tls, timeout)?;
+                Ok((message, Transport::Tls, Some(session)))
+            }
         };
+    }
+    if !options.tcp {
+        let socket = bind_socket(&server, source)?;
+        match query_udp(&socket, server, &dns_packet, timeout)? {
+            Some(message) => return Ok((message, Transport::Udp, None)),
+            None => info!("DNS: response of {} from {} is truncated, retry over tcp", name, server)
+        }
+    }
+    Ok((query_tcp(server, source, &dns_packet, timeout)?, Transport::Tcp, None))
+}
+
+/**
+ * Bind udp socket of the same ip version as server
+ */
+fn bind_socket(server: &SocketAddr, source: Option<Ipv4Addr>) -> Result<UdpSocket> {
+    let bind_ip = match (server, source) {
+        (SocketAddr::V4(_), Some(source)) => IpAddr::V4(source),
+        (SocketAddr::V4(_), None) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        (SocketAddr::V6(_), _) => IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    Ok(UdpSocket::bind(SocketAddr::new(bind_ip, 0))?)
+}
 
-        if is_reach {
-            break;
+/**
+ * Send dns query over udp and receive the response, responses of other source or id are ignored.
+ * None if the response is truncated.
+ */
+fn query_udp(socket: &UdpSocket, server: SocketAddr, dns_packet: &DnsPacket, timeout: Duration) -> Result<Option<DnsMessage>> {
+    socket.send_to(dns_packet.packet(), server)?;
+    let deadline = Instant::now() + timeout;
+    let mut res_buff: Vec<u8> = vec![0; MAX_MESSAGE_SIZE];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(NetDoctorError::Timeout(format!("dns response from {}", server)));
         }
-        std::thread::sleep(Duration::from_millis(1000));
+        socket.set_read_timeout(Some(remaining))?;
+        let (size, source) = match socket.recv_from(&mut res_buff) {
+            Ok(received) => received,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(e) => return Err(e.into())
+        };
+        if (source.ip(), source.port()) != (server.ip(), server.port()) {
+            debug!("DNS: response from other source {}", source);
+            continue;
+        }
+        // sections of truncated response may be cut, so only header is checked
+        let (id, header) = read_header(&res_buff[..size])?;
+        if id != dns_packet.transaction_id {
+            debug!("DNS: response of other id {}", id);
+            continue;
+        }
+        if header.truncated {
+            return Ok(None);
+        }
+        return Ok(Some(DnsMessage::parse(&res_buff[..size])?));
+    }
+}
+
+/**
+ * Send dns query over tcp and receive the response
+ */
+fn query_tcp(server: SocketAddr, source: Option<Ipv4Addr>, dns_packet: &DnsPacket, timeout: Duration) -> Result<DnsMessage> {
+    let mut stream = connect_tcp(server, source, timeout)?;
+    query_stream(&mut stream, server, dns_packet, timeout)
+}
+
+/**
+ * Connect to the server over tcp, ipv4 server is connected from the source address if it is given.
+ * Reads and writes of the stream time out.
+ */
+pub fn connect_tcp(server: SocketAddr, source: Option<Ipv4Addr>, timeout: Duration) -> Result<TcpStream> {
+    let socket = Socket::new(Domain::for_address(server), Type::STREAM, Some(Protocol::TCP))?;
+    if let (SocketAddr::V4(_), Some(source)) = (server, source) {
+        socket.bind(&SocketAddr::new(IpAddr::V4(source), 0).into())?;
+    }
+    socket.connect_timeout(&server.into(), timeout)?;
+    let stream = TcpStream::from(socket);
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
 
+/**
+ * Send 2 bytes length prefixed dns query on the stream and receive the response of the same id
+ */
+pub fn query_stream(stream: &mut (impl Read + Write), server: SocketAddr, dns_packet: &DnsPacket, timeout: Duration) -> Result<DnsMessage> {
+    let deadline = Instant::now() + timeout;
+    let mut request = (dns_packet.packet().len() as u16).to_be_bytes().to_vec();
+    request.extend_from_slice(dns_packet.packet());
+    stream.write_all(&request)?;
+    stream.flush()?;
+    loop {
+        // each read waits the timeout of the underlying socket
+        if Instant::now() >= deadline {
+            return Err(NetDoctorError::Timeout(format!("dns response from {}", server)));
+        }
+        let mut length = [0; 2];
+        stream.read_exact(&mut length)?;
+        let mut res_buff: Vec<u8> = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut res_buff)?;
+        let message = DnsMessage::parse(&res_buff)?;
+        if message.id != dns_packet.transaction_id {
+            debug!("DNS: response of other id {}", message.id);
+            continue;
+        }
+        return Ok(message);
     }
 }
 
 /**
  * Set param to dsn packet
  */
-fn make_dns_packet(packet: &mut DnsPacket, domain: &str) {
+pub fn make_dns_packet(packet: &mut DnsPacket, domain: &str, dns_type: u16, edns: Option<Edns>) {
     let id = rand::thread_rng().gen();
     packet.transaction_id = id;
 
-    let header = DnsHeader {
-        qr_code: 0,
-        ope_code: 0,
-        trunc: 0,
-        recursion: 1,
-        z_code: 0,
-        dns_sec: 1,
-        auth_code: 0
-    };
+    // AD asks whether the resolver validated the answer (RFC 6840 5.7)
+    let header = DnsHeader { recursion_desired: true, authentic_data: true, ..Default::default() };
     packet.flags = header;
     packet.question_count = 1;
     packet.answer_count = 0;
     packet.authority_count = 0;
-    packet.additional_count = 0;
+    packet.additional_count = edns.is_some() as u16;
+    packet.edns = edns;
     packet.query = DnsQueryData {
         name: domain.to_string(),
-        dns_type: 1,
+        dns_type,
         dns_class: 1
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_target_ip_test() {
+        let resolve = ResolveConfig { ip_version: IpVersion::Any, resolver: Resolver::System };
+        assert_eq!(resolve_target("192.0.2.1", &resolve).unwrap(), IpAddr::from_str("192.0.2.1").unwrap());
+        assert_eq!(resolve_target("2001:db8::1", &resolve).unwrap(), IpAddr::from_str("2001:db8::1").unwrap());
+
+        let resolve = ResolveConfig { ip_version: IpVersion::V6, resolver: Resolver::System };
+        assert!(matches!(resolve_target("192.0.2.1", &resolve), Err(NetDoctorError::ResolveFailed(_))));
+    }
+
+    #[test]
+    fn resolve_target_system_test() {
+        let resolve = ResolveConfig { ip_version: IpVersion::V4, resolver: Resolver::System };
+        assert_eq!(resolve_target("localhost", &resolve).unwrap(), IpAddr::from_str("127.0.0.1").unwrap());
+        assert_eq!(resolve_target_v4("localhost", &ResolveConfig { ip_version: IpVersion::Any, ..resolve }).unwrap(), Ipv4Addr::LOCALHOST);
+    }
+
+    #[test]
+    fn get_answar_malformed_test() {
+        let mut dns_packet = DnsPacket::new();
+        make_dns_packet(&mut dns_packet, "example.com", RecordType::A.code(), None);
+        dns_packet.make_packet().unwrap();
+        let mut response = dns_packet.packet().to_vec();
+        // one answer of 93.184.215.14 pointing the question name
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 215, 14]);
+        response[7] = 1;
+        let answers = DnsPacket::get_answar(response.clone()).unwrap();
+        assert_eq!(answers.len(), 1);
+        assert_eq!((answers[0].name.as_str(), answers[0].address.as_str()), ("example.com", "93.184.215.14"));
+
+        // rdata is cut
+        response.truncate(response.len() - 2);
+        assert!(matches!(DnsPacket::get_answar(response.clone()), Err(NetDoctorError::MalformedPacket(_))));
+        // header only
+        assert!(matches!(DnsPacket::get_answar(response[..12].to_vec()), Err(NetDoctorError::MalformedPacket(_))));
+    }
+
+    #[test]
+    fn resolve_search_test() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let stub = std::thread::spawn(move || {
+            // answer only www, search domain has no record
+            for _ in 0..2 {
+                let mut buf = [0; 512];
+                let (size, source) = server.recv_from(&mut buf).unwrap();
+                let query = DnsMessage::parse(&buf[..size]).unwrap();
+                let mut response = buf[..size].to_vec();
+                response[2..4].copy_from_slice(&[0x81, 0x80]);
+                if query.questions[0].name == "www" {
+                    response[7] = 1;
+                    response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 80]);
+                }
+                server.send_to(&response, source).unwrap();
+            }
+        });
+
+        let conf = ResolvConf { search: vec!["corp.example".to_string()], timeout: 1, ..ResolvConf::parse("") };
+        let exchange = resolve("www", RecordType::A.code(), &[server_addr], &conf, None, &QueryOptions::default()).unwrap();
+        assert_eq!((exchange.server, exchange.name.as_str()), (server_addr, "www"));
+        assert_eq!(exchange.answers[0].data, RData::A(Ipv4Addr::new(192, 0, 2, 80)));
+        stub.join().unwrap();
+    }
+
+    #[test]
+    fn query_servers_failover_test() {
+        // the first server refuses, the second says the name does not exist
+        let stubs: Vec<(SocketAddr, std::thread::JoinHandle<()>)> = [0x85, 0x83].into_iter().map(|flags| {
+            let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+            let server_addr = server.local_addr().unwrap();
+            let stub = std::thread::spawn(move || {
+                let mut buf = [0; 512];
+                let (size, source) = server.recv_from(&mut buf).unwrap();
+                let mut response = buf[..size].to_vec();
+                response[2..4].copy_from_slice(&[0x81, flags]);
+                server.send_to(&response, source).unwrap();
+            });
+            (server_addr, stub)
+        }).collect();
+
+        let servers: Vec<SocketAddr> = stubs.iter().map(|(addr, _)| *addr).collect();
+        let conf = ResolvConf { timeout: 1, attempts: 1, ..ResolvConf::parse("") };
+        let exchange = query_servers("nx.example", RecordType::A.code(), &servers, &conf, None, &QueryOptions::default()).unwrap();
+        assert_eq!((exchange.server, exchange.rcode), (servers[1], Rcode::NxDomain));
+        assert!(exchange.header.response && exchange.header.recursion_available);
+        stubs.into_iter().for_each(|(_, stub)| stub.join().unwrap());
+    }
+
+    #[test]
+    fn resolve_truncated_test() {
+        let udp_server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = udp_server.local_addr().unwrap();
+        let tcp_server = std::net::TcpListener::bind(server_addr).unwrap();
+        let udp_stub = std::thread::spawn(move || {
+            // header only with TC bit
+            let mut buf = [0; 512];
+            let (size, source) = udp_server.recv_from(&mut buf).unwrap();
+            let mut response = buf[..size].to_vec();
+            response[2..4].copy_from_slice(&[0x83, 0x80]);
+            udp_server.send_to(&response[..12], source).unwrap();
+        });
+        let tcp_stub = std::thread::spawn(move || {
+            // 40 answers exceed 512 bytes of udp
+            for _ in 0..2 {
+                let (mut stream, _) = tcp_server.accept().unwrap();
+                let mut length = [0; 2];
+                stream.read_exact(&mut length).unwrap();
+                let mut response = vec![0; u16::from_be_bytes(length) as usize];
+                stream.read_exact(&mut response).unwrap();
+                response[2..4].copy_from_slice(&[0x81, 0x80]);
+                response[6..8].copy_from_slice(&40u16.to_be_bytes());
+                for i in 0..40 {
+                    response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, i]);
+                }
+                stream.write_all(&(response.len() as u16).to_be_bytes()).unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+
+        let conf = ResolvConf { timeout: 1, ..ResolvConf::parse("") };
+        let exchange = resolve("many.example.", RecordType::A.code(), &[server_addr], &conf, None, &QueryOptions::default()).unwrap();
+        assert_eq!((exchange.transport, exchange.answers.len()), (Transport::Tcp, 40));
+        assert_eq!(exchange.answers[39].data, RData::A(Ipv4Addr::new(192, 0, 2, 39)));
+        udp_stub.join().unwrap();
+
+        // tcp is used without udp
+        let exchange = resolve("many.example.", RecordType::A.code(), &[server_addr], &conf, None, &QueryOptions { tcp: true, ..Default::default() }).unwrap();
+        assert_eq!((exchange.transport, exchange.answers.len()), (Transport::Tcp, 40));
+        tcp_stub.join().unwrap();
+    }
+
+    #[test]
+    fn reverse_name_test() {
+        assert_eq!(reverse_name(&IpAddr::from_str("192.0.2.1").unwrap()), "1.2.0.192.in-addr.arpa");
+        assert_eq!(reverse_name(&IpAddr::from_str("2001:db8::1").unwrap()),
+            "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa");
+    }
+
+    #[test]
+    fn resolve_reverse_test() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let stub = std::thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, source) = server.recv_from(&mut buf).unwrap();
+            let query = DnsMessage::parse(&buf[..size]).unwrap();
+            assert_eq!((query.questions[0].name.as_str(), query.questions[0].dns_type), ("1.2.0.192.in-addr.arpa", 12));
+            let mut response = buf[..size].to_vec();
+            response[2..4].copy_from_slice(&[0x81, 0x80]);
+            response[7] = 1;
+            // PTR of router.example
+            response.extend_from_slice(&[0xc0, 12, 0, 12, 0, 1, 0, 0, 0, 60, 0, 16]);
+            response.extend_from_slice(b"\x06router\x07example\x00");
+            server.send_to(&response, source).unwrap();
+        });
+
+        // search domains are not applied to absolute name
+        let conf = ResolvConf { search: vec!["corp.example".to_string()], timeout: 1, ..ResolvConf::parse("") };
+        let name = format!("{}.", reverse_name(&IpAddr::from_str("192.0.2.1").unwrap()));
+        let exchange = resolve(&name, RecordType::Ptr.code(), &[server_addr], &conf, None, &QueryOptions::default()).unwrap();
+        assert_eq!(exchange.answers[0].data, RData::Ptr("router.example".to_string()));
+        stub.join().unwrap();
+    }
+}
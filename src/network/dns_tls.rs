@@ -0,0 +1,725 @@
+use std::{fmt, io::{self, BufRead, BufReader, ErrorKind, Read, Write}, net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream}, str::FromStr, sync::Arc, time::{Duration, Instant}};
+use log::{debug, info};
+
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use ring::digest;
+use rustls::{client::{danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier}, WebPkiServerVerifier}, crypto::{ring::default_provider, verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms}, pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime}, ClientConfig, ClientConnection, DigitallySignedStruct, ProtocolVersion, RootCertStore, SignatureScheme, StreamOwned};
+use serde::Serialize;
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+use crate::{error::{NetDoctorError, Result}, types::TlsConfig, utils::serde_utils};
+
+use super::{dns::{connect_tcp, query_stream}, types::dns_packet::{DnsMessage, DnsPacket}};
+
+// DNS-over-TLS (RFC 7858 3.1)
+pub const DOT_PORT: u16 = 853;
+pub const HTTPS_PORT: u16 = 443;
+// media type of DoH request and response (RFC 8484 6)
+const DNS_MESSAGE_TYPE: &str = "application/dns-message";
+// path of the url which has no path, common among public resolvers
+const DEFAULT_PATH: &str = "/dns-query";
+const MAX_HEADER_SIZE: usize = 8192;
+const MAX_BODY_SIZE: usize = u16::MAX as usize;
+const PIN_SIZE: usize = 32;
+
+/**
+ * Client of encrypted dns, DoH if the endpoint is given, otherwise DoT
+ */
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    pub client: Arc<ClientConfig>,
+    // SNI and the name of the certificate, server address if None
+    pub server_name: Option<String>,
+    pub https: Option<HttpsEndpoint>
+}
+
+/**
+ * Url of DoH server
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpsEndpoint {
+    pub host: String,
+    pub port: u16,
+    // path with query of the url
+    pub path: String,
+    // query by GET with dns parameter instead of POST
+    pub get: bool
+}
+
+impl HttpsEndpoint {
+    /**
+     * Parse url like https://dns.example/dns-query, https://192.0.2.53:8443/dns-query or https://[2001:db8::53]/dns-query.
+     * Path is /dns-query if the url has no path.
+     */
+    pub fn parse(url: &str, get: bool) -> Option<HttpsEndpoint> {
+        let rest = url.strip_prefix("https://")?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, DEFAULT_PATH)
+        };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(v6) => {
+                let (host, rest) = v6.split_once(']')?;
+                Ipv6Addr::from_str(host).ok()?;
+                match rest {
+                    "" => (host, None),
+                    _ => (host, Some(rest.strip_prefix(':')?))
+                }
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None)
+            }
+        };
+        if host.is_empty() || host.contains('@') {
+            return None;
+        }
+        let port = match port {
+            Some(port) => port.parse().ok()?,
+            None => HTTPS_PORT
+        };
+        Some(HttpsEndpoint { host: host.to_string(), port, path: path.to_string(), get })
+    }
+
+    /**
+     * Host header, ipv6 address is enclosed in brackets and the port is added unless it is 443
+     */
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') { format!("[{}]", self.host) } else { self.host.clone() };
+        match self.port {
+            HTTPS_PORT => host,
+            port => format!("{}:{}", host, port)
+        }
+    }
+
+    /**
+     * HTTP/1.1 request of the dns message, POST body or base64url dns parameter of GET (RFC 8484 4.1)
+     */
+    fn request(&self, message: &[u8]) -> Vec<u8> {
+        let headers = format!("Host: {}\r\nUser-Agent: ndt/{}\r\nAccept: {}\r\nConnection: close\r\n",
+            self.host_header(), env!("CARGO_PKG_VERSION"), DNS_MESSAGE_TYPE);
+        if self.get {
+            let separator = if self.path.contains('?') { '&' } else { '?' };
+            return format!("GET {}{}dns={} HTTP/1.1\r\n{}\r\n", self.path, separator, URL_SAFE_NO_PAD.encode(message), headers).into_bytes();
+        }
+        let mut request = format!("POST {} HTTP/1.1\r\n{}Content-Type: {}\r\nContent-Length: {}\r\n\r\n",
+            self.path, headers, DNS_MESSAGE_TYPE, message.len()).into_bytes();
+        request.extend_from_slice(message);
+        request
+    }
+}
+
+impl fmt::Display for HttpsEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "https://{}{}", self.host_header(), self.path)
+    }
+}
+
+/**
+ * Session of the encrypted connection
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct TlsInfo {
+    // e.g. TLSv1.3
+    pub version: String,
+    pub cipher_suite: String,
+    // protocol negotiated by ALPN, http/1.1 of DoH
+    pub alpn: Option<String>,
+    // SNI or the address which the certificate is verified for
+    pub server_name: String,
+    #[serde(rename = "handshake_ms", serialize_with = "serde_utils::millis")]
+    pub handshake_time: Duration,
+    // chain sent by the server, the server certificate first
+    pub certificates: Vec<CertificateInfo>
+}
+
+impl TlsInfo {
+    fn new(connection: &ClientConnection, server_name: String, handshake_time: Duration) -> TlsInfo {
+        let version = match connection.protocol_version() {
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+            Some(version) => format!("{:?}", version),
+            None => "unknown".to_string()
+        };
+        TlsInfo {
+            version,
+            cipher_suite: connection.negotiated_cipher_suite().map(|suite| format!("{:?}", suite.suite())).unwrap_or_default(),
+            alpn: connection.alpn_protocol().map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+            server_name,
+            handshake_time,
+            certificates: connection.peer_certificates().unwrap_or_default().iter().filter_map(CertificateInfo::parse).collect()
+        }
+    }
+}
+
+impl fmt::Display for TlsInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "version={} cipher={} name={}", self.version, self.cipher_suite, self.server_name)?;
+        if let Some(alpn) = &self.alpn {
+            write!(f, " alpn={}", alpn)?;
+        }
+        write!(f, " handshake={:.3} ms", self.handshake_time.as_secs_f64() * 1000.0)
+    }
+}
+
+/**
+ * Certificate of the server
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct CertificateInfo {
+    pub subject: String,
+    pub issuer: String,
+    // hex bytes separated by colon
+    pub serial: String,
+    pub not_before: String,
+    pub not_after: String,
+    // dns names and addresses of subject alternative name
+    pub names: Vec<String>,
+    // base64 sha256 of SubjectPublicKeyInfo, the value of --pin
+    pub pin: String
+}
+
+impl CertificateInfo {
+    fn parse(certificate: &CertificateDer) -> Option<CertificateInfo> {
+        let (_, x509) = parse_x509_certificate(certificate).ok()?;
+        let mut names: Vec<String> = Vec::new();
+        if let Ok(Some(san)) = x509.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) => names.push(name.to_string()),
+                    GeneralName::IPAddress(ip) => match ip.len() {
+                        4 => names.push(Ipv4Addr::from(<[u8; 4]>::try_from(*ip).ok()?).to_string()),
+                        16 => names.push(Ipv6Addr::from(<[u8; 16]>::try_from(*ip).ok()?).to_string()),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+        Some(CertificateInfo {
+            subject: x509.subject().to_string(),
+            issuer: x509.issuer().to_string(),
+            serial: x509.raw_serial_as_string(),
+            not_before: x509.validity().not_before.to_string(),
+            not_after: x509.validity().not_after.to_string(),
+            names,
+            pin: STANDARD.encode(spki_pin(certificate)?)
+        })
+    }
+}
+
+impl fmt::Display for CertificateInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "subject=\"{}\" issuer=\"{}\" not_after=\"{}\" names={} pin={}", self.subject, self.issuer, self.not_after,
+            self.names.join(","), self.pin)
+    }
+}
+
+/**
+ * Verifier of the server certificate by the roots, or by SPKI pins of the server certificate instead of the roots
+ * (RFC 7858 4.2 out-of-band key-pinned privacy profile).
+ * Intermediates are not matched, they are public and the chain to them is not verified.
+ */
+#[derive(Debug)]
+struct PinVerifier {
+    pins: Vec<Vec<u8>>,
+    webpki: Arc<WebPkiServerVerifier>,
+    algorithms: WebPkiSupportedAlgorithms
+}
+
+impl ServerCertVerifier for PinVerifier {
+    fn verify_server_cert(&self, end_entity: &CertificateDer, intermediates: &[CertificateDer], server_name: &ServerName, ocsp_response: &[u8],
+        now: UnixTime) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        if self.pins.is_empty() {
+            return self.webpki.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now);
+        }
+        let matched = spki_pin(end_entity).is_some_and(|pin| self.pins.contains(&pin));
+        if matched {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("server certificate does not match the pins".to_string()))
+        }
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], cert: &CertificateDer, dss: &DigitallySignedStruct)
+        -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], cert: &CertificateDer, dss: &DigitallySignedStruct)
+        -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/**
+ * Sha256 of SubjectPublicKeyInfo of the certificate
+ */
+fn spki_pin(certificate: &CertificateDer) -> Option<Vec<u8>> {
+    let (_, x509) = parse_x509_certificate(certificate).ok()?;
+    Some(digest::digest(&digest::SHA256, x509.public_key().raw).as_ref().to_vec())
+}
+
+/**
+ * Parse base64 sha256 pin, optionally prefixed by sha256/
+ */
+fn parse_pin(pin: &str) -> Option<Vec<u8>> {
+    let pin = STANDARD.decode(pin.strip_prefix("sha256/").unwrap_or(pin)).ok()?;
+    (pin.len() == PIN_SIZE).then_some(pin)
+}
+
+/**
+ * Client of DoT or DoH, None if the query is not encrypted
+ */
+pub fn make_tls(config: &TlsConfig) -> Result<Option<TlsOptions>> {
+    if !config.tls && config.https.is_none() {
+        return Ok(None);
+    }
+    let https = match &config.https {
+        Some(url) => Some(HttpsEndpoint::parse(url, config.get)
+            .ok_or_else(|| NetDoctorError::InvalidArgument(format!("https url {}, expected like https://dns.example/dns-query", url)))?),
+        None => None
+    };
+    let pins = config.pins.iter()
+        .map(|pin| parse_pin(pin).ok_or_else(|| NetDoctorError::InvalidArgument(format!("pin {}, expected base64 of sha256", pin))))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut roots = RootCertStore::empty();
+    match &config.ca_file {
+        Some(path) => {
+            let certificates = CertificateDer::pem_file_iter(path)
+                .map_err(|e| NetDoctorError::InvalidArgument(format!("ca file {}, {}", path, e)))?;
+            for certificate in certificates {
+                let certificate = certificate.map_err(|e| NetDoctorError::InvalidArgument(format!("ca file {}, {}", path, e)))?;
+                roots.add(certificate).map_err(|e| NetDoctorError::InvalidArgument(format!("ca file {}, {}", path, e)))?;
+            }
+            if roots.is_empty() {
+                return Err(NetDoctorError::InvalidArgument(format!("ca file {} has no certificate", path)));
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned())
+    }
+
+    let provider = Arc::new(default_provider());
+    let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| NetDoctorError::Tls(e.to_string()))?;
+    let verifier = PinVerifier { pins, webpki, algorithms: provider.signature_verification_algorithms };
+    let mut client = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| NetDoctorError::Tls(e.to_string()))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    if https.is_some() {
+        client.alpn_protocols = vec![b"http/1.1".to_vec()];
+    }
+
+    let server_name = config.server_name.clone().or_else(|| https.as_ref().map(|endpoint| endpoint.host.clone()));
+    Ok(Some(TlsOptions { client: Arc::new(client), server_name, https }))
+}
+
+/**
+ * Connect to the server and complete the TLS handshake
+ */
+fn connect_tls(server: SocketAddr, source: Option<Ipv4Addr>, options: &TlsOptions, timeout: Duration)
+    -> Result<(StreamOwned<ClientConnection, TcpStream>, TlsInfo)> {
+    let name = match &options.server_name {
+        Some(name) => ServerName::try_from(name.clone())
+            .map_err(|e| NetDoctorError::InvalidArgument(format!("tls name {}, {}", name, e)))?,
+        None => ServerName::from(server.ip())
+    };
+    let server_name = options.server_name.clone().unwrap_or_else(|| server.ip().to_string());
+
+    let mut stream = connect_tcp(server, source, timeout)?;
+    let start = Instant::now();
+    let mut connection = ClientConnection::new(options.client.clone(), name).map_err(|e| NetDoctorError::Tls(e.to_string()))?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream).map_err(|e| tls_error(e, server))?;
+    }
+    let info = TlsInfo::new(&connection, server_name, start.elapsed());
+    debug!("DNS: tls {} with {}", info, server);
+    Ok((StreamOwned::new(connection, stream), info))
+}
+
+/**
+ * Timeout of the handshake, otherwise failure of TLS
+ */
+fn tls_error(e: io::Error, server: SocketAddr) -> NetDoctorError {
+    match e.kind() {
+        ErrorKind::TimedOut | ErrorKind::WouldBlock => NetDoctorError::Timeout(format!("tls handshake with {}", server)),
+        _ => NetDoctorError::Tls(format!("{} with {}", e, server))
+    }
+}
+
+/**
+ * Send dns query over TLS, messages are prefixed by length as tcp (RFC 7858 3.3)
+ */
+pub fn query_tls(server: SocketAddr, source: Option<Ipv4Addr>, dns_packet: &DnsPacket, options: &TlsOptions, timeout: Duration)
+    -> Result<(DnsMessage, TlsInfo)> {
+    let (mut stream, info) = connect_tls(server, source, options, timeout)?;
+    let message = query_stream(&mut stream, server, dns_packet, timeout)?;
+    stream.conn.send_close_notify();
+    if let Err(e) = stream.conn.complete_io(&mut stream.sock) {
+        debug!("DNS: close of tls with {} failed, {}", server, e);
+    }
+    Ok((message, info))
+}
+
+/**
+ * Send dns query over HTTPS and receive the response in the body (RFC 8484)
+ */
+pub fn query_https(server: SocketAddr, source: Option<Ipv4Addr>, dns_packet: &DnsPacket, options: &TlsOptions, endpoint: &HttpsEndpoint,
+    timeout: Duration) -> Result<(DnsMessage, TlsInfo)> {
+    let (mut stream, info) = connect_tls(server, source, options, timeout)?;
+    info!("DNS: {} {} via {}", if endpoint.get { "GET" } else { "POST" }, endpoint, server);
+    stream.write_all(&endpoint.request(dns_packet.packet()))?;
+    stream.flush()?;
+
+    let response = read_http_response(&mut BufReader::new(&mut stream))?;
+    if response.status != 200 {
+        return Err(NetDoctorError::ResolveFailed(format!("{}, http status {}", endpoint, response.status)));
+    }
+    match &response.content_type {
+        Some(content_type) if content_type.starts_with(DNS_MESSAGE_TYPE) => {}
+        content_type => return Err(NetDoctorError::MalformedPacket(
+            format!("content type {} of {}", content_type.as_deref().unwrap_or("none"), endpoint)))
+    }
+    let message = DnsMessage::parse(&response.body)?;
+    if message.id != dns_packet.transaction_id {
+        return Err(NetDoctorError::MalformedPacket(format!("response of other id {} from {}", message.id, endpoint)));
+    }
+    Ok((message, info))
+}
+
+/**
+ * Response of HTTP/1.1
+ */
+#[derive(Debug)]
+struct HttpResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>
+}
+
+/**
+ * Read status, headers and the body which is sized by Content-Length, chunked or closed by the server
+ */
+fn read_http_response(reader: &mut impl BufRead) -> Result<HttpResponse> {
+    let mut header_size = 0;
+    let status_line = read_line(reader, &mut header_size)?;
+    let status = match status_line.split_whitespace().collect::<Vec<_>>()[..] {
+        [version, status, ..] if version.starts_with("HTTP/") => status.parse().ok(),
+        _ => None
+    }.ok_or_else(|| NetDoctorError::MalformedPacket(format!("http status line {}", status_line)))?;
+
+    let mut content_type: Option<String> = None;
+    let mut content_length: Option<usize> = None;
+    let mut chunked = false;
+    loop {
+        let line = read_line(reader, &mut header_size)?;
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(NetDoctorError::MalformedPacket(format!("http header {}", line)));
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-type" => content_type = Some(value.to_ascii_lowercase()),
+            "content-length" => content_length = Some(value.parse()
+                .map_err(|_| NetDoctorError::MalformedPacket(format!("http content length {}", value)))?),
+            "transfer-encoding" => chunked = value.to_ascii_lowercase().contains("chunked"),
+            _ => {}
+        }
+    }
+
+    let mut body: Vec<u8> = Vec::new();
+    if chunked {
+        loop {
+            let line = read_line(reader, &mut 0)?;
+            let size = usize::from_str_radix(line.split(';').next().unwrap_or_default().trim(), 16)
+                .map_err(|_| NetDoctorError::MalformedPacket(format!("http chunk size {}", line)))?;
+            if size == 0 {
+                // trailers end with an empty line
+                while !read_line(reader, &mut 0)?.is_empty() {}
+                break;
+            }
+            if body.len() + size > MAX_BODY_SIZE {
+                return Err(NetDoctorError::MalformedPacket(format!("http body over {} bytes", MAX_BODY_SIZE)));
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            read_line(reader, &mut 0)?;
+        }
+    } else if let Some(length) = content_length {
+        if length > MAX_BODY_SIZE {
+            return Err(NetDoctorError::MalformedPacket(format!("http body over {} bytes", MAX_BODY_SIZE)));
+        }
+        body.resize(length, 0);
+        reader.read_exact(&mut body)?;
+    } else {
+        // the body ends when the server closes, some servers close without close_notify
+        match reader.by_ref().take(MAX_BODY_SIZE as u64 + 1).read_to_end(&mut body) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {}
+            result => { result?; }
+        }
+        if body.len() > MAX_BODY_SIZE {
+            return Err(NetDoctorError::MalformedPacket(format!("http body over {} bytes", MAX_BODY_SIZE)));
+        }
+    }
+    Ok(HttpResponse { status, content_type, body })
+}
+
+/**
+ * Read a line without CRLF, size counts the bytes read by the lines of a header
+ */
+fn read_line(reader: &mut impl BufRead, size: &mut usize) -> Result<String> {
+    let mut line = String::new();
+    let read = reader.by_ref().take((MAX_HEADER_SIZE - *size) as u64).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(NetDoctorError::MalformedPacket(if *size + read >= MAX_HEADER_SIZE {
+            format!("http header over {} bytes", MAX_HEADER_SIZE)
+        } else {
+            "http response is closed".to_string()
+        }));
+    }
+    *size += read;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor, net::TcpListener, thread};
+
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use rustls::{pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer}, ServerConfig, ServerConnection};
+
+    use crate::network::dns::{query, QueryOptions, Transport};
+    use crate::network::types::dns_packet::RData;
+
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /**
+     * Stand-in server of dns.test and 127.0.0.1 signed by a self-signed CA
+     */
+    struct StandIn {
+        ca_file: String,
+        // pin of the server key
+        pin: String,
+        ca: CertificateDer<'static>,
+        config: Arc<ServerConfig>
+    }
+
+    impl StandIn {
+        fn new(name: &str, https: bool) -> StandIn {
+            let ca_key = KeyPair::generate().unwrap();
+            let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            ca_params.distinguished_name.push(DnType::CommonName, "ndt test CA");
+            let ca = ca_params.self_signed(&ca_key).unwrap();
+
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(vec!["dns.test".to_string(), "127.0.0.1".to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, "dns.test");
+            let certificate = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+            let ca_file = std::env::temp_dir().join(format!("ndt-{}-{}.pem", name, std::process::id()));
+            fs::write(&ca_file, ca.pem()).unwrap();
+            let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![certificate.der().clone()], PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())))
+                .unwrap();
+            if https {
+                config.alpn_protocols = vec![b"http/1.1".to_vec()];
+            }
+            StandIn {
+                ca_file: ca_file.to_string_lossy().into_owned(),
+                pin: STANDARD.encode(digest::digest(&digest::SHA256, &key.public_key_der())),
+                ca: ca.der().clone(),
+                config: Arc::new(config)
+            }
+        }
+
+        /**
+         * Answer the queries of the connections over DoT or DoH, connections which fail the handshake are skipped
+         */
+        fn serve(&self, connections: usize, https: bool) -> SocketAddr {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let config = self.config.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().take(connections) {
+                    let connection = ServerConnection::new(config.clone()).unwrap();
+                    let mut stream = StreamOwned::new(connection, stream.unwrap());
+                    let _ = if https { serve_https(&mut stream) } else { serve_tls(&mut stream) };
+                }
+            });
+            address
+        }
+    }
+
+    fn serve_tls(stream: &mut (impl Read + Write)) -> io::Result<()> {
+        let mut length = [0; 2];
+        stream.read_exact(&mut length)?;
+        let mut request = vec![0; u16::from_be_bytes(length) as usize];
+        stream.read_exact(&mut request)?;
+        let response = answer(&request);
+        stream.write_all(&(response.len() as u16).to_be_bytes())?;
+        stream.write_all(&response)?;
+        stream.flush()
+    }
+
+    fn serve_https(stream: &mut (impl Read + Write)) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            if line.trim_end().is_empty() {
+                break;
+            }
+            if let Some(length) = line.strip_prefix("Content-Length: ") {
+                content_length = length.trim().parse().unwrap();
+            }
+        }
+        let request = match request_line.split_once("?dns=") {
+            Some((_, query)) => URL_SAFE_NO_PAD.decode(query.split(' ').next().unwrap()).unwrap(),
+            None => {
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body)?;
+                body
+            }
+        };
+        // chunked body of the answer
+        let response = answer(&request);
+        let stream = reader.get_mut();
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n", DNS_MESSAGE_TYPE, response.len())?;
+        stream.write_all(&response)?;
+        stream.write_all(b"\r\n0\r\n\r\n")?;
+        stream.flush()
+    }
+
+    /**
+     * Response of A 192.0.2.53 to the query without additional records
+     */
+    fn answer(request: &[u8]) -> Vec<u8> {
+        let mut response = request.to_vec();
+        response[2] |= 0x80;
+        response[6..8].copy_from_slice(&1u16.to_be_bytes());
+        response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 53]);
+        response
+    }
+
+    fn query_a(server: SocketAddr, config: &TlsConfig) -> Result<(DnsMessage, Transport, Option<TlsInfo>)> {
+        let options = QueryOptions { tls: make_tls(config)?, ..Default::default() };
+        query(server, None, "example.com", 1, &options, TIMEOUT)
+    }
+
+    #[test]
+    fn parse_https_url_test() {
+        let endpoint = HttpsEndpoint::parse("https://dns.example/dns-query", false).unwrap();
+        assert_eq!((endpoint.host.as_str(), endpoint.port, endpoint.path.as_str()), ("dns.example", 443, "/dns-query"));
+        assert_eq!(endpoint.host_header(), "dns.example");
+        let endpoint = HttpsEndpoint::parse("https://192.0.2.53:8443/resolve?ct", true).unwrap();
+        assert_eq!((endpoint.host_header().as_str(), endpoint.path.as_str()), ("192.0.2.53:8443", "/resolve?ct"));
+        assert!(String::from_utf8(endpoint.request(&[0, 0])).unwrap().starts_with("GET /resolve?ct&dns=AAA HTTP/1.1\r\n"));
+        let endpoint = HttpsEndpoint::parse("https://[2001:db8::53]", false).unwrap();
+        assert_eq!((endpoint.host.as_str(), endpoint.path.as_str()), ("2001:db8::53", "/dns-query"));
+        assert_eq!(endpoint.to_string(), "https://[2001:db8::53]/dns-query");
+        assert_eq!(HttpsEndpoint::parse("http://dns.example/dns-query", false), None);
+        assert_eq!(HttpsEndpoint::parse("https://dns.example:99999/", false), None);
+        assert_eq!(HttpsEndpoint::parse("https://[dns.example]/", false), None);
+
+        assert_eq!(parse_pin("sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=").map(|pin| pin.len()), Some(32));
+        assert_eq!(parse_pin("AAAA"), None);
+    }
+
+    #[test]
+    fn read_http_response_test() {
+        let response = read_http_response(&mut Cursor::new(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/dns-message\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n1;x=y\r\nc\r\n0\r\n\r\n")).unwrap();
+        assert_eq!((response.status, response.content_type.as_deref(), response.body.as_slice()), (200, Some(DNS_MESSAGE_TYPE), &b"abc"[..]));
+        let response = read_http_response(&mut Cursor::new("HTTP/1.1 415 Unsupported Media Type\r\nContent-Length: 2\r\n\r\nnoextra")).unwrap();
+        assert_eq!((response.status, response.content_type, response.body), (415, None, b"no".to_vec()));
+        let response = read_http_response(&mut Cursor::new("HTTP/1.0 200 OK\r\n\r\nclosed")).unwrap();
+        assert_eq!(response.body, b"closed");
+        assert!(matches!(read_http_response(&mut Cursor::new("SSH-2.0\r\n\r\n")), Err(NetDoctorError::MalformedPacket(_))));
+        assert!(matches!(read_http_response(&mut Cursor::new("HTTP/1.1 200 OK\r\nContent")), Err(NetDoctorError::MalformedPacket(_))));
+        let large = format!("HTTP/1.1 200 OK\r\nX: {}\r\n\r\n", "a".repeat(MAX_HEADER_SIZE));
+        assert!(matches!(read_http_response(&mut Cursor::new(large)), Err(NetDoctorError::MalformedPacket(_))));
+    }
+
+    #[test]
+    fn query_tls_test() {
+        let stand_in = StandIn::new("dot", false);
+        let server = stand_in.serve(4, false);
+        let config = TlsConfig { tls: true, server_name: Some("dns.test".to_string()), ca_file: Some(stand_in.ca_file.clone()), ..Default::default() };
+        let (message, transport, tls) = query_a(server, &config).unwrap();
+        assert_eq!(transport, Transport::Tls);
+        assert!(matches!(message.answers[0].rdata, RData::A(ip) if ip == Ipv4Addr::new(192, 0, 2, 53)));
+        let tls = tls.unwrap();
+        assert_eq!((tls.version.as_str(), tls.server_name.as_str(), tls.alpn), ("TLSv1.3", "dns.test", None));
+        assert_eq!(tls.certificates.len(), 1);
+        assert_eq!(tls.certificates[0].names, vec!["dns.test", "127.0.0.1"]);
+        assert_eq!(tls.certificates[0].issuer, "CN=ndt test CA");
+        assert_eq!(tls.certificates[0].pin, stand_in.pin);
+
+        // certificate of other name
+        let wrong_name = TlsConfig { server_name: Some("other.test".to_string()), ..config.clone() };
+        assert!(matches!(query_a(server, &wrong_name), Err(NetDoctorError::Tls(_))));
+        // pin is trusted without the CA, the address is the name of the certificate by default
+        let pinned = TlsConfig { tls: true, pins: vec![stand_in.pin.clone()], ..Default::default() };
+        assert_eq!(query_a(server, &pinned).unwrap().2.unwrap().server_name, "127.0.0.1");
+        let wrong_pin = TlsConfig { pins: vec!["47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string()], ..pinned };
+        assert!(matches!(query_a(server, &wrong_pin), Err(NetDoctorError::Tls(_))));
+        fs::remove_file(&stand_in.ca_file).unwrap();
+    }
+
+    #[test]
+    fn query_tls_pinned_intermediate_test() {
+        // self-signed certificate of other key sent with the public certificate of the pinned CA
+        let stand_in = StandIn::new("intermediate", false);
+        let key = KeyPair::generate().unwrap();
+        let forged = CertificateParams::new(vec!["127.0.0.1".to_string()]).unwrap().self_signed(&key).unwrap();
+        let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![forged.der().clone(), stand_in.ca.clone()], PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())))
+            .unwrap();
+        let ca_pin = STANDARD.encode(spki_pin(&stand_in.ca).unwrap());
+        let forged = StandIn { config: Arc::new(config), ..stand_in };
+        let server = forged.serve(1, false);
+        let pinned = TlsConfig { tls: true, pins: vec![ca_pin], ..Default::default() };
+        assert!(matches!(query_a(server, &pinned), Err(NetDoctorError::Tls(_))));
+        fs::remove_file(&forged.ca_file).unwrap();
+    }
+
+    #[test]
+    fn query_https_test() {
+        let stand_in = StandIn::new("doh", true);
+        let server = stand_in.serve(2, true);
+        let url = format!("https://dns.test:{}/dns-query", server.port());
+        for get in [false, true] {
+            let config = TlsConfig { https: Some(url.clone()), get, ca_file: Some(stand_in.ca_file.clone()), ..Default::default() };
+            let (message, transport, tls) = query_a(server, &config).unwrap();
+            assert_eq!((transport, message.id), (Transport::Https, 0));
+            assert!(matches!(message.answers[0].rdata, RData::A(ip) if ip == Ipv4Addr::new(192, 0, 2, 53)));
+            let tls = tls.unwrap();
+            assert_eq!((tls.server_name.as_str(), tls.alpn.as_deref()), ("dns.test", Some("http/1.1")));
+        }
+        fs::remove_file(&stand_in.ca_file).unwrap();
+    }
+}
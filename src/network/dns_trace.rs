@@ -0,0 +1,455 @@
+use std::{fmt, fs, io::ErrorKind, net::{IpAddr, Ipv4Addr, SocketAddr}, str::FromStr, time::{Duration, Instant}};
+use log::{debug, info};
+
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, types::{DnsConfig, RecordType}, utils::{os_utils::get_active_interface, ip_utils::get_ip_from_nic, serde_utils}};
+
+use super::{dns::{lookup, make_edns, query, query_name, QueryOptions, Transport}, resolv_conf::{ResolvConf, RESOLV_CONF_PATH}, types::dns_packet::{type_name, DnsAnswer, DnsMessage, RData, Rcode}};
+
+pub const ROOT_HINTS_PATH: &str = "/usr/share/dns/root.hints";
+// a referral gets closer to the name by at least a label
+const MAX_TRACE_STEPS: usize = 32;
+// ipv4 addresses of root servers, used when root hints file is not installed
+const ROOT_SERVERS: [(&str, [u8; 4]); 13] = [
+    ("a.root-servers.net", [198, 41, 0, 4]),
+    ("b.root-servers.net", [170, 247, 170, 2]),
+    ("c.root-servers.net", [192, 33, 4, 12]),
+    ("d.root-servers.net", [199, 7, 91, 13]),
+    ("e.root-servers.net", [192, 203, 230, 10]),
+    ("f.root-servers.net", [192, 5, 5, 241]),
+    ("g.root-servers.net", [192, 112, 36, 4]),
+    ("h.root-servers.net", [198, 97, 190, 53]),
+    ("i.root-servers.net", [192, 36, 148, 17]),
+    ("j.root-servers.net", [192, 58, 128, 30]),
+    ("k.root-servers.net", [193, 0, 14, 129]),
+    ("l.root-servers.net", [199, 7, 83, 42]),
+    ("m.root-servers.net", [202, 12, 27, 33])
+];
+
+/**
+ * Name server of a zone and its address
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NameServer {
+    pub name: String,
+    pub address: IpAddr
+}
+
+/**
+ * Response of an authoritative server in the trace
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceStep {
+    // zone which the server is delegated
+    pub zone: String,
+    pub server: String,
+    pub address: SocketAddr,
+    pub rcode: Rcode,
+    pub authoritative: bool,
+    // child zone and its name servers when the server refers
+    pub referral: Option<String>,
+    pub nameservers: Vec<String>,
+    pub answers: Vec<DnsAnswer>,
+    pub transport: Transport,
+    #[serde(rename = "time_ms", serialize_with = "serde_utils::millis")]
+    pub time: Duration
+}
+
+impl fmt::Display for TraceStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DNS: {:<24} {} ({}) {:.3} ms", self.zone, self.server, self.address, self.time.as_secs_f64() * 1000.0)?;
+        match &self.referral {
+            Some(child) => write!(f, "  referral to {} [{}]", child, self.nameservers.join(" ")),
+            None => write!(f, "  {} {} answers{}", self.rcode, self.answers.len(), if self.authoritative { " aa" } else { "" })
+        }
+    }
+}
+
+/**
+ * Port and options of iterative queries
+ */
+struct Tracer {
+    // port of all servers, 53 unless servers are stand-ins
+    port: u16,
+    source: Option<Ipv4Addr>,
+    options: QueryOptions,
+    timeout: Duration
+}
+
+/**
+ * Report of iterative resolution from root
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsTraceReport {
+    pub name: String,
+    pub record_type: RecordType,
+    pub steps: Vec<TraceStep>,
+    // final response of the authoritative server
+    pub rcode: Rcode,
+    pub answers: Vec<DnsAnswer>,
+    #[serde(rename = "time_ms", serialize_with = "serde_utils::millis")]
+    pub time: Duration
+}
+
+impl fmt::Display for DnsTraceReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DNS: {} {} is {} with {} answers in {} steps, time {:.3} ms", self.name, self.record_type, self.rcode,
+            self.answers.len(), self.steps.len(), self.time.as_secs_f64() * 1000.0)?;
+        for a in &self.answers {
+            write!(f, "\nDNS: answer name={} type={} ttl={} data={}", a.name, type_name(a.dns_type), a.time, a.data)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Resolve iteratively from root servers and call on_step with each response as soon as it arrives
+ */
+pub fn dns_trace(config: DnsConfig, on_step: impl FnMut(&TraceStep)) -> Result<DnsTraceReport> {
+    let interface = get_active_interface(&config.interface_name)?;
+    let src_ip = get_ip_from_nic(&interface)?;
+
+    let hints = match &config.root_hints {
+        Some(path) => parse_root_hints(&fs::read_to_string(path)?),
+        None => match fs::read_to_string(ROOT_HINTS_PATH) {
+            Ok(text) => parse_root_hints(&text),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                debug!("DNS: {} is not found, built-in root servers are used", ROOT_HINTS_PATH);
+                ROOT_SERVERS.iter()
+                    .map(|(name, octets)| NameServer { name: name.to_string(), address: IpAddr::V4(Ipv4Addr::from(*octets)) })
+                    .collect()
+            }
+            Err(e) => return Err(e.into())
+        }
+    };
+    if hints.is_empty() {
+        return Err(NetDoctorError::InvalidArgument(format!("root hints {} has no address", config.root_hints.unwrap_or_default())));
+    }
+
+    let (name, record_type) = query_name(&config)?;
+    let tracer = Tracer {
+        port: config.port,
+        source: Some(src_ip),
+        options: QueryOptions { tcp: config.tcp, edns: make_edns(&config.edns)?, recursion_desired: false, ..Default::default() },
+        timeout: ResolvConf::load(RESOLV_CONF_PATH)?.timeout()
+    };
+    tracer.trace(&name, record_type, hints, on_step)
+}
+
+impl Tracer {
+    /**
+     * Follow referrals from the hints down to the server which answers
+     */
+    fn trace(&self, name: &str, record_type: RecordType, hints: Vec<NameServer>, mut on_step: impl FnMut(&TraceStep)) -> Result<DnsTraceReport> {
+        let name = name.trim_end_matches('.');
+        let start = Instant::now();
+        let mut steps: Vec<TraceStep> = Vec::new();
+        let mut zone = ".".to_string();
+        let mut servers = hints;
+        info!("DNS: trace {} {} from {} root servers", name, record_type, servers.len());
+
+        while steps.len() < MAX_TRACE_STEPS {
+            let mut next: Option<(String, Vec<NameServer>)> = None;
+            for server in &servers {
+                let address = SocketAddr::new(server.address, self.port);
+                let query_start = Instant::now();
+                let (message, transport, _) = match query(address, self.source, name, record_type.code(), &self.options, self.timeout) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        info!("DNS: {} ({}) {}", server.name, address, e);
+                        continue;
+                    }
+                };
+                let referral = find_referral(&message, name, &zone);
+                let step = TraceStep {
+                    zone: zone.clone(),
+                    server: server.name.clone(),
+                    address,
+                    rcode: message.rcode(),
+                    authoritative: message.header.authoritative,
+                    referral: referral.as_ref().map(|(child, _)| child.clone()),
+                    nameservers: referral.as_ref().map(|(_, names)| names.clone()).unwrap_or_default(),
+                    answers: message.answers.iter().map(DnsAnswer::from).collect(),
+                    transport,
+                    time: query_start.elapsed()
+                };
+                info!("{}", step);
+                on_step(&step);
+
+                // lame or misconfigured server refuses or fails, try the other servers of the zone
+                if !matches!(step.rcode, Rcode::NoError | Rcode::NxDomain) {
+                    steps.push(step);
+                    continue;
+                }
+                let is_final = !step.answers.is_empty() || step.rcode == Rcode::NxDomain || step.authoritative;
+                steps.push(step);
+                if is_final {
+                    let last = &steps[steps.len() - 1];
+                    return Ok(DnsTraceReport {
+                        name: name.to_string(),
+                        record_type,
+                        rcode: last.rcode,
+                        answers: last.answers.clone(),
+                        steps,
+                        time: start.elapsed()
+                    });
+                }
+                match referral {
+                    Some((child, names)) => {
+                        let nameservers = find_addresses(&message, &names);
+                        if nameservers.is_empty() {
+                            info!("DNS: no address of name servers of {}", child);
+                            continue;
+                        }
+                        next = Some((child, nameservers));
+                        break;
+                    }
+                    // lame server neither answers nor refers, try the other servers of the zone
+                    None => info!("DNS: {} ({}) has neither answer nor referral for {}", server.name, address, zone)
+                }
+            }
+
+            match next {
+                Some((child, nameservers)) => {
+                    zone = child;
+                    servers = nameservers;
+                }
+                None => return Err(NetDoctorError::ResolveFailed(format!("{}, no server of {} answered or referred", name, zone)))
+            }
+        }
+        Err(NetDoctorError::ResolveFailed(format!("{}, more than {} referrals", name, MAX_TRACE_STEPS)))
+    }
+}
+
+/**
+ * Child zone and its name servers of NS records in authority section.
+ * The child has to be under the current zone and contain the name, or the referral would not end.
+ */
+fn find_referral(message: &DnsMessage, name: &str, zone: &str) -> Option<(String, Vec<String>)> {
+    let records: Vec<(&String, &String)> = message.authorities.iter()
+        .filter_map(|record| match &record.rdata {
+            RData::Ns(target) => Some((&record.name, target)),
+            _ => None
+        })
+        .collect();
+    let child = records.first()?.0;
+    if child.eq_ignore_ascii_case(zone) || !is_subdomain(child, zone) || !is_subdomain(name, child) {
+        debug!("DNS: referral to {} is not under {} or does not contain {}", child, zone, name);
+        return None;
+    }
+    let names = records.iter().filter(|(owner, _)| owner.eq_ignore_ascii_case(child)).map(|(_, target)| target.to_string()).collect();
+    Some((child.clone(), names))
+}
+
+/**
+ * Addresses of name servers by glue of additional section.
+ * Names are looked up by resolv.conf only when no name has glue. Ipv4 addresses come first.
+ */
+fn find_addresses(message: &DnsMessage, names: &[String]) -> Vec<NameServer> {
+    let mut nameservers: Vec<NameServer> = names.iter()
+        .flat_map(|name| message.additionals.iter()
+            .filter(|record| record.name.eq_ignore_ascii_case(name))
+            .filter_map(|record| match record.rdata {
+                RData::A(ip) => Some(IpAddr::V4(ip)),
+                RData::Aaaa(ip) => Some(IpAddr::V6(ip)),
+                _ => None
+            })
+            .map(|address| NameServer { name: name.clone(), address }))
+        .collect();
+    if nameservers.is_empty() {
+        for name in names {
+            match lookup(&format!("{}.", name), RecordType::A) {
+                Ok(addresses) => nameservers.extend(addresses.into_iter().map(|address| NameServer { name: name.clone(), address })),
+                Err(e) => debug!("DNS: lookup of glueless {} {}", name, e)
+            }
+        }
+    }
+    nameservers.sort_by_key(|server| server.address.is_ipv6());
+    nameservers
+}
+
+/**
+ * Name is the zone or under the zone, root contains all names
+ */
+pub fn is_subdomain(name: &str, zone: &str) -> bool {
+    let (name, zone) = (name.to_ascii_lowercase(), zone.to_ascii_lowercase());
+    zone == "." || name == zone || name.ends_with(&format!(".{}", zone))
+}
+
+/**
+ * Parse addresses of root servers in root hints file (named.root), A and AAAA lines are used
+ */
+pub fn parse_root_hints(text: &str) -> Vec<NameServer> {
+    let mut nameservers: Vec<NameServer> = Vec::new();
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        // owner, optional ttl and class, type and address
+        let Some(position) = words.iter().position(|word| word.eq_ignore_ascii_case("A") || word.eq_ignore_ascii_case("AAAA")) else {
+            continue;
+        };
+        match (words.first(), words.get(position + 1).and_then(|address| IpAddr::from_str(address).ok())) {
+            (Some(owner), Some(address)) if position > 0 => {
+                nameservers.push(NameServer { name: owner.trim_end_matches('.').to_ascii_lowercase(), address });
+            }
+            _ => debug!("DNS: invalid root hints line {}", line)
+        }
+    }
+    nameservers.sort_by_key(|server| server.address.is_ipv6());
+    nameservers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, thread::{self, JoinHandle}};
+
+    use crate::network::types::dns_packet::DnsQueryData;
+
+    use super::*;
+
+    #[test]
+    fn parse_root_hints_test() {
+        let hints = parse_root_hints("\
+;       This file holds the information on root name servers
+.                        3600000      NS    A.ROOT-SERVERS.NET.
+A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+b.root-servers.net.      IN           A     170.247.170.2
+broken                   A            not-address
+");
+        assert_eq!(hints.len(), 3);
+        assert_eq!(hints[0], NameServer { name: "a.root-servers.net".to_string(), address: IpAddr::from([198, 41, 0, 4]) });
+        assert_eq!(hints[1].address, IpAddr::from([170, 247, 170, 2]));
+        assert!(hints[2].address.is_ipv6());
+    }
+
+    #[test]
+    fn is_subdomain_test() {
+        assert!(is_subdomain("www.example.com", "."));
+        assert!(is_subdomain("www.Example.com", "example.COM"));
+        assert!(is_subdomain("example.com", "example.com"));
+        assert!(!is_subdomain("www.badexample.com", "example.com"));
+        assert!(!is_subdomain("com", "example.com"));
+    }
+
+    // name in wire format
+    fn encode_name(name: &str) -> Vec<u8> {
+        let mut query = DnsQueryData { name: name.to_string(), dns_type: 0, dns_class: 0 }.get_query().unwrap();
+        query.truncate(query.len() - 4);
+        query
+    }
+
+    // resource record of IN class and an hour ttl
+    fn encode_record(owner: &str, dns_type: u16, rdata: &[u8]) -> Vec<u8> {
+        let mut buf = encode_name(owner);
+        buf.extend_from_slice(&dns_type.to_be_bytes());
+        buf.extend_from_slice(&[0, 1, 0, 0, 0x0e, 0x10]);
+        buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        buf.extend_from_slice(rdata);
+        buf
+    }
+
+    // authoritative server answering one query with flags and records of answer, authority and additional sections
+    fn spawn_server(address: SocketAddr, flags: [u8; 2], sections: [Vec<Vec<u8>>; 3]) -> JoinHandle<()> {
+        let server = UdpSocket::bind(address).unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            let (size, source) = server.recv_from(&mut buf).unwrap();
+            // iterative query does not desire recursion
+            assert_eq!(buf[2] & 0x01, 0);
+            let mut response = buf[..12].to_vec();
+            response[2..4].copy_from_slice(&flags);
+            let message = DnsMessage::parse(&buf[..size]).unwrap();
+            response[4..6].copy_from_slice(&1u16.to_be_bytes());
+            for (i, records) in sections.iter().enumerate() {
+                response[6 + i * 2..8 + i * 2].copy_from_slice(&(records.len() as u16).to_be_bytes());
+            }
+            response.extend_from_slice(&encode_name(&message.questions[0].name));
+            response.extend_from_slice(&[0, 1, 0, 1]);
+            sections.iter().flatten().for_each(|record| response.extend_from_slice(record));
+            server.send_to(&response, source).unwrap();
+        })
+    }
+
+    fn make_tracer(port: u16, timeout: u64) -> Tracer {
+        Tracer {
+            port,
+            source: None,
+            options: QueryOptions { recursion_desired: false, ..Default::default() },
+            timeout: Duration::from_millis(timeout)
+        }
+    }
+
+    #[test]
+    fn trace_test() {
+        // root on 127.0.0.1 refers to com on 127.0.0.2, which refers to example.com on 127.0.0.3
+        let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let root = spawn_server(SocketAddr::from(([127, 0, 0, 1], port)), [0x80, 0x00], [
+            Vec::new(),
+            vec![encode_record("com", 2, &encode_name("ns.com"))],
+            vec![encode_record("ns.com", 1, &[127, 0, 0, 2])]
+        ]);
+        let com = spawn_server(SocketAddr::from(([127, 0, 0, 2], port)), [0x80, 0x00], [
+            Vec::new(),
+            vec![encode_record("example.com", 2, &encode_name("ns1.example.com")),
+                encode_record("example.com", 2, &encode_name("ns2.example.com"))],
+            vec![encode_record("ns1.example.com", 1, &[127, 0, 0, 3])]
+        ]);
+        let example = spawn_server(SocketAddr::from(([127, 0, 0, 3], port)), [0x84, 0x00], [
+            vec![encode_record("www.example.com", 1, &[192, 0, 2, 80])],
+            Vec::new(),
+            Vec::new()
+        ]);
+
+        let hints = vec![NameServer { name: "root".to_string(), address: IpAddr::from([127, 0, 0, 1]) }];
+        let mut streamed = 0;
+        let report = make_tracer(port, 1000).trace("www.example.com.", RecordType::A, hints, |_| streamed += 1).unwrap();
+        assert_eq!(streamed, 3);
+        let zones: Vec<&str> = report.steps.iter().map(|step| step.zone.as_str()).collect();
+        assert_eq!(zones, vec![".", "com", "example.com"]);
+        assert_eq!(report.steps[1].nameservers, vec!["ns1.example.com", "ns2.example.com"]);
+        assert_eq!((report.steps[2].server.as_str(), report.steps[2].authoritative), ("ns1.example.com", true));
+        assert_eq!((report.rcode, report.answers[0].data.clone()), (Rcode::NoError, RData::A(Ipv4Addr::new(192, 0, 2, 80))));
+        [root, com, example].into_iter().for_each(|server| server.join().unwrap());
+    }
+
+    #[test]
+    fn trace_broken_delegation_test() {
+        // root refers to com, but the only server of com does not respond
+        let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let root = spawn_server(SocketAddr::from(([127, 0, 0, 1], port)), [0x80, 0x00], [
+            Vec::new(),
+            vec![encode_record("com", 2, &encode_name("ns.com"))],
+            vec![encode_record("ns.com", 1, &[127, 0, 0, 2])]
+        ]);
+
+        let hints = vec![NameServer { name: "root".to_string(), address: IpAddr::from([127, 0, 0, 1]) }];
+        let result = make_tracer(port, 200).trace("www.example.com", RecordType::A, hints, |_| {});
+        match result {
+            Err(NetDoctorError::ResolveFailed(e)) => assert!(e.contains("no server of com"), "{}", e),
+            other => panic!("unexpected {:?}", other.map(|report| report.steps.len()))
+        }
+        root.join().unwrap();
+    }
+
+    #[test]
+    fn trace_refused_test() {
+        // first root server refuses, the other answers
+        let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let refused = spawn_server(SocketAddr::from(([127, 0, 0, 1], port)), [0x80, 0x05], [Vec::new(), Vec::new(), Vec::new()]);
+        let root = spawn_server(SocketAddr::from(([127, 0, 0, 2], port)), [0x84, 0x00], [
+            vec![encode_record("www.example.com", 1, &[192, 0, 2, 80])],
+            Vec::new(),
+            Vec::new()
+        ]);
+
+        let hints = vec![NameServer { name: "lame".to_string(), address: IpAddr::from([127, 0, 0, 1]) },
+            NameServer { name: "root".to_string(), address: IpAddr::from([127, 0, 0, 2]) }];
+        let report = make_tracer(port, 1000).trace("www.example.com", RecordType::A, hints, |_| {}).unwrap();
+        let rcodes: Vec<Rcode> = report.steps.iter().map(|step| step.rcode).collect();
+        assert_eq!(rcodes, vec![Rcode::Refused, Rcode::NoError]);
+        assert_eq!((report.rcode, report.answers.len()), (Rcode::NoError, 1));
+        [refused, root].into_iter().for_each(|server| server.join().unwrap());
+    }
+}
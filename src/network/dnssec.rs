@@ -0,0 +1,965 @@
+use std::{collections::{HashMap, HashSet}, fmt, fs, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use log::{debug, info};
+
+use ring::{digest, signature::{RsaPublicKeyComponents, UnparsedPublicKey}};
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, types::{DnsConfig, RecordType}, utils::{byte_utils::from_hex, os_utils::get_active_interface, ip_utils::get_ip_from_nic, serde_utils}};
+
+use super::{dns::{make_edns, nameservers, query, query_name, QueryOptions}, dns_tls::make_tls, dns_trace::is_subdomain, resolv_conf::{ResolvConf, RESOLV_CONF_PATH}, types::dns_packet::{type_name, write_name, DnsAnswer, DnsMessage, DnsRecord, RData, Rcode}};
+
+// DS of the root key signing keys KSK-2017 and KSK-2024, published at https://data.iana.org/root-anchors/
+pub const ROOT_ANCHORS: &str = "\
+. IN DS 20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D
+. IN DS 38696 8 2 683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16
+";
+// flags of DNSKEY (RFC 4034 2.1.1, RFC 5011 7)
+const FLAG_ZONE_KEY: u16 = 0x0100;
+const FLAG_REVOKE: u16 = 0x0080;
+const CLASS_IN: u16 = 1;
+// a name has at most 127 labels, so the chain has at most 128 zones
+const MAX_CHAIN_ZONES: usize = 128;
+
+/**
+ * Reason why a link of the chain of trust is broken
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ChainError {
+    // no RRSIG covers the rrset
+    MissingSignature,
+    SignatureExpired { expiration: u32 },
+    SignatureNotYetValid { inception: u32 },
+    // RRSIG is made by a zone which is not the owner or its parent
+    SignerMismatch { signer: String },
+    // no DNSKEY has the key tag of RRSIG or DS
+    MissingKey { key_tag: u16 },
+    // DNSKEY of the key tag has other algorithm than RRSIG or DS refers
+    AlgorithmMismatch { key_tag: u16, expected: u8, actual: u8 },
+    UnsupportedAlgorithm { algorithm: u8 },
+    UnsupportedDigest { digest_type: u8 },
+    // signature is not verified by the key
+    BadSignature { key_tag: u16 },
+    // digest of DS does not match the key
+    DigestMismatch { key_tag: u16 },
+    // the zone has no key
+    MissingDnskey,
+    // the parent has no DS of the zone, so the delegation is not signed
+    MissingDs,
+    // the response has no record to validate
+    NoRecords,
+    // signatures of the negative response are verified, but NSEC or NSEC3 are not checked to cover the name and type
+    DenialNotProven,
+    // records of the link could not be fetched
+    QueryFailed { error: String }
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChainError::MissingSignature => write!(f, "no signature"),
+            ChainError::SignatureExpired { expiration } => write!(f, "signature expired at {}", expiration),
+            ChainError::SignatureNotYetValid { inception } => write!(f, "signature is not valid until {}", inception),
+            ChainError::SignerMismatch { signer } => write!(f, "signed by unrelated zone {}", signer),
+            ChainError::MissingKey { key_tag } => write!(f, "no DNSKEY of key tag {}", key_tag),
+            ChainError::AlgorithmMismatch { key_tag, expected, actual } =>
+                write!(f, "algorithm mismatch, key {} is algorithm {} but {} is expected", key_tag, actual, expected),
+            ChainError::UnsupportedAlgorithm { algorithm } => write!(f, "unsupported algorithm {}", algorithm),
+            ChainError::UnsupportedDigest { digest_type } => write!(f, "unsupported digest type {}", digest_type),
+            ChainError::BadSignature { key_tag } => write!(f, "signature is not verified by key {}", key_tag),
+            ChainError::DigestMismatch { key_tag } => write!(f, "DS digest does not match key {}", key_tag),
+            ChainError::MissingDnskey => write!(f, "no DNSKEY"),
+            ChainError::MissingDs => write!(f, "no DS in the parent zone"),
+            ChainError::NoRecords => write!(f, "no record to validate"),
+            ChainError::DenialNotProven => write!(f, "denial of existence is not proven"),
+            ChainError::QueryFailed { error } => write!(f, "query failed, {}", error)
+        }
+    }
+}
+
+/**
+ * An rrset of the chain and the key which verified it
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChainLink {
+    pub name: String,
+    pub record_type: String,
+    // zone whose key signs the rrset, None if no signature is found
+    pub signer: Option<String>,
+    // key which verified the signature
+    pub key_tag: Option<u16>,
+    pub algorithm: Option<u8>,
+    // DNSKEY is authenticated by a trust anchor instead of DS of the parent
+    pub trust_anchor: bool,
+    pub secure: bool,
+    pub error: Option<ChainError>
+}
+
+impl ChainLink {
+    fn new(name: &str, dns_type: u16, signer: Option<&str>, result: std::result::Result<(u16, u8), ChainError>) -> ChainLink {
+        let (key, error) = match result {
+            Ok(key) => (Some(key), None),
+            Err(e) => (None, Some(e))
+        };
+        ChainLink {
+            name: name.to_string(),
+            record_type: type_name(dns_type),
+            signer: signer.map(|signer| signer.to_string()),
+            key_tag: key.map(|(key_tag, _)| key_tag),
+            algorithm: key.map(|(_, algorithm)| algorithm),
+            trust_anchor: false,
+            secure: error.is_none(),
+            error
+        }
+    }
+}
+
+impl fmt::Display for ChainLink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DNS: {:<24} {:<6} ", self.name, self.record_type)?;
+        match (&self.error, &self.signer, self.key_tag, self.algorithm) {
+            (Some(e @ ChainError::DenialNotProven), _, _, _) => write!(f, "unproven, {}", e),
+            (Some(e), _, _, _) => write!(f, "broken, {}", e),
+            (None, Some(signer), Some(key_tag), Some(algorithm)) => {
+                write!(f, "secure, signed by {} key_tag={} algorithm={}", signer, key_tag, algorithm)?;
+                if self.trust_anchor {
+                    write!(f, " (trust anchor)")?;
+                }
+                Ok(())
+            }
+            _ => write!(f, "secure")
+        }
+    }
+}
+
+/**
+ * Report of dnssec validation
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct DnssecReport {
+    pub name: String,
+    pub record_type: RecordType,
+    pub rcode: Rcode,
+    // every link from the response up to a trust anchor is verified
+    pub secure: bool,
+    // links from the response upward, the last one is broken if the chain is not secure
+    pub links: Vec<ChainLink>,
+    pub answers: Vec<DnsAnswer>,
+    #[serde(rename = "time_ms", serialize_with = "serde_utils::millis")]
+    pub time: Duration
+}
+
+impl fmt::Display for DnssecReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = self.time.as_secs_f64() * 1000.0;
+        match self.links.last() {
+            Some(ChainLink { error: Some(ChainError::DenialNotProven), .. }) => write!(f,
+                "DNS: {} {} is {} with verified signatures, but denial of existence is not proven, time {:.3} ms",
+                self.name, self.record_type, self.rcode, time)?,
+            Some(link) if !self.secure => write!(f, "DNS: {} {} is bogus, broken at {} {}, {}, time {:.3} ms",
+                self.name, self.record_type, link.name, link.record_type, link.error.as_ref().unwrap_or(&ChainError::NoRecords), time)?,
+            _ => write!(f, "DNS: {} {} is secure, {} links up to trust anchor, time {:.3} ms", self.name, self.record_type, self.links.len(), time)?
+        }
+        for link in &self.links {
+            write!(f, "\n{}", link)?;
+        }
+        for a in &self.answers {
+            write!(f, "\nDNS: answer name={} type={} ttl={} data={}", a.name, type_name(a.dns_type), a.time, a.data)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Query the name with DO and CD bits and validate the chain of trust of the response up to a trust anchor.
+ * The name is absolute, search domains are not used.
+ */
+pub fn dnssec(config: DnsConfig) -> Result<DnssecReport> {
+    let interface = get_active_interface(&config.interface_name)?;
+    let src_ip = get_ip_from_nic(&interface)?;
+
+    let conf = ResolvConf::load(RESOLV_CONF_PATH)?;
+    let servers = nameservers(&config, &conf)?;
+    let anchors = match &config.trust_anchor {
+        Some(path) => parse_trust_anchors(&fs::read_to_string(path)?)?,
+        None => parse_trust_anchors(ROOT_ANCHORS)?
+    };
+    if anchors.is_empty() {
+        return Err(NetDoctorError::InvalidArgument(format!("trust anchor {} has no DS record", config.trust_anchor.unwrap_or_default())));
+    }
+
+    let mut edns = make_edns(&config.edns)?
+        .ok_or_else(|| NetDoctorError::InvalidArgument("dnssec needs EDNS0 to set DO bit".to_string()))?;
+    edns.dnssec_ok = true;
+    // CD makes the resolver return records which fail its own validation, so that they are checked here
+    let options = QueryOptions { tcp: config.tcp, edns: Some(edns), checking_disabled: true, tls: make_tls(&config.tls)?, ..Default::default() };
+    let timeout = conf.timeout();
+    let fetch = |name: &str, dns_type: u16| {
+        let mut last_error = NetDoctorError::InvalidArgument("no dns server".to_string());
+        for server in &servers {
+            match query(*server, Some(src_ip), name, dns_type, &options, timeout) {
+                Ok((message, _, _)) => return Ok(message),
+                Err(e) => {
+                    debug!("DNS: {} {} from {} failed, {}", name, type_name(dns_type), server, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    };
+
+    let (name, record_type) = query_name(&config)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or_default();
+    let mut validator = Validator { fetch, anchors, now, cache: HashMap::new(), secure_zones: HashSet::new() };
+    validator.validate(&name, record_type)
+}
+
+/**
+ * Fetcher of records and the state of validation
+ */
+struct Validator<F: FnMut(&str, u16) -> Result<DnsMessage>> {
+    fetch: F,
+    // DS records of trust anchors
+    anchors: Vec<DnsRecord>,
+    // seconds since unix epoch to check validity period of signatures
+    now: u32,
+    // responses by lowercase name and type, DNSKEY is used by rrsets and the chain
+    cache: HashMap<(String, u16), DnsMessage>,
+    // lowercase zones whose keys are validated up to a trust anchor
+    secure_zones: HashSet<String>
+}
+
+impl<F: FnMut(&str, u16) -> Result<DnsMessage>> Validator<F> {
+    /**
+     * Validate rrsets of the response, and keys of their signers up to a trust anchor.
+     * Validation stops at the first broken link.
+     */
+    fn validate(&mut self, name: &str, record_type: RecordType) -> Result<DnssecReport> {
+        let start = Instant::now();
+        let name = zone_name(name);
+        info!("DNS: validate {} {} with {} trust anchors", name, record_type, self.anchors.len());
+        let message = (self.fetch)(&name, record_type.code())?;
+        // signatures of SOA and NSEC in authority section of negative response are verified, but they do not prove the denial
+        let section = if message.answers.is_empty() { &message.authorities } else { &message.answers };
+
+        let mut links: Vec<ChainLink> = Vec::new();
+        let rrsets = rrset_keys(section);
+        if rrsets.is_empty() {
+            links.push(ChainLink::new(&name, record_type.code(), None, Err(ChainError::NoRecords)));
+        }
+        for (owner, dns_type) in rrsets {
+            let (records, signatures) = select(section, &owner, dns_type);
+            let link = self.verify_rrset(&owner, dns_type, &records, &signatures);
+            let (secure, signer) = (link.secure, link.signer.clone());
+            links.push(link);
+            if !secure || !signer.is_some_and(|signer| self.validate_zone(&signer, &mut links)) {
+                break;
+            }
+        }
+        if message.answers.is_empty() && links.iter().all(|link| link.secure) {
+            links.push(ChainLink::new(&name, record_type.code(), None, Err(ChainError::DenialNotProven)));
+        }
+
+        Ok(DnssecReport {
+            name,
+            record_type,
+            rcode: message.rcode(),
+            secure: !links.is_empty() && links.iter().all(|link| link.secure),
+            links,
+            answers: message.answers.iter().filter(|record| record.dns_type != RecordType::Rrsig.code()).map(DnsAnswer::from).collect(),
+            time: start.elapsed()
+        })
+    }
+
+    /**
+     * Walk from the zone up to a trust anchor, each zone adds its DNSKEY link and DS link.
+     * Returns false when a link is broken.
+     */
+    fn validate_zone(&mut self, zone: &str, links: &mut Vec<ChainLink>) -> bool {
+        let mut zone = zone.to_string();
+        let mut walked: Vec<String> = Vec::new();
+        for _ in 0..MAX_CHAIN_ZONES {
+            if self.secure_zones.contains(&zone.to_ascii_lowercase()) {
+                break;
+            }
+            let link = self.verify_keys(&zone);
+            let (secure, trust_anchor) = (link.secure, link.trust_anchor);
+            links.push(link);
+            if !secure {
+                return false;
+            }
+            walked.push(zone.to_ascii_lowercase());
+            if trust_anchor {
+                break;
+            }
+
+            // DS is already fetched by verify_keys
+            let ds_type = RecordType::Ds.code();
+            let (ds, signatures) = self.rrset(&zone, ds_type).unwrap_or_default();
+            let link = self.verify_rrset(&zone, ds_type, &ds, &signatures);
+            let (secure, signer) = (link.secure, link.signer.clone());
+            links.push(link);
+            match signer {
+                Some(parent) if secure => zone = parent,
+                _ => return false
+            }
+        }
+        self.secure_zones.extend(walked);
+        true
+    }
+
+    /**
+     * DNSKEY link, the key set has to be signed by a key which DS of the parent or a trust anchor refers
+     */
+    fn verify_keys(&mut self, zone: &str) -> ChainLink {
+        let (dnskey_type, ds_type) = (RecordType::Dnskey.code(), RecordType::Ds.code());
+        let (keys, signatures) = match self.rrset(zone, dnskey_type) {
+            Ok(rrset) if rrset.0.is_empty() => return ChainLink::new(zone, dnskey_type, None, Err(ChainError::MissingDnskey)),
+            Ok(rrset) => rrset,
+            Err(e) => return ChainLink::new(zone, dnskey_type, None, Err(e))
+        };
+        let anchors: Vec<DnsRecord> = self.anchors.iter().filter(|anchor| same_name(&anchor.name, zone)).cloned().collect();
+        let trust_anchor = !anchors.is_empty();
+        let ds = match trust_anchor {
+            true => anchors,
+            false => match self.rrset(zone, ds_type) {
+                Ok((ds, _)) => ds,
+                Err(e) => return ChainLink::new(zone, ds_type, None, Err(e))
+            }
+        };
+        if ds.is_empty() {
+            return ChainLink::new(zone, ds_type, None, Err(ChainError::MissingDs));
+        }
+        let result = match_ds(zone, &ds, &keys).and_then(|entry_keys| self.verify_signatures(&keys, &signatures, &entry_keys));
+        ChainLink { trust_anchor, ..ChainLink::new(zone, dnskey_type, Some(zone), result) }
+    }
+
+    /**
+     * Verify an rrset by the keys of its signer, DS is signed by the parent and others by the zone of the owner
+     */
+    fn verify_rrset(&mut self, owner: &str, dns_type: u16, records: &[DnsRecord], signatures: &[DnsRecord]) -> ChainLink {
+        let signer = signatures.iter().find_map(|record| match &record.rdata {
+            RData::Rrsig { signer, .. } => Some(zone_name(signer)),
+            _ => None
+        });
+        let Some(signer) = signer else {
+            return ChainLink::new(owner, dns_type, None, Err(ChainError::MissingSignature));
+        };
+        if !is_subdomain(owner, &signer) || (dns_type == RecordType::Ds.code() && same_name(owner, &signer)) {
+            return ChainLink::new(owner, dns_type, None, Err(ChainError::SignerMismatch { signer }));
+        }
+        let keys = match self.rrset(&signer, RecordType::Dnskey.code()) {
+            Ok((keys, _)) => keys,
+            Err(e) => return ChainLink::new(owner, dns_type, Some(&signer), Err(e))
+        };
+        let signatures: Vec<DnsRecord> = signatures.iter()
+            .filter(|record| matches!(&record.rdata, RData::Rrsig { signer: name, .. } if same_name(name, &signer)))
+            .cloned().collect();
+        let result = self.verify_signatures(records, &signatures, &keys);
+        ChainLink::new(owner, dns_type, Some(&signer), result)
+    }
+
+    /**
+     * Any of the signatures is verified by any of the keys, returns key tag and algorithm of the key
+     */
+    fn verify_signatures(&self, records: &[DnsRecord], signatures: &[DnsRecord], keys: &[DnsRecord]) -> std::result::Result<(u16, u8), ChainError> {
+        let mut error: Option<ChainError> = None;
+        for signature in signatures {
+            match self.verify_signature(records, signature, keys) {
+                Ok(key) => return Ok(key),
+                Err(e) => keep_specific(&mut error, e)
+            }
+        }
+        Err(error.unwrap_or(ChainError::MissingSignature))
+    }
+
+    fn verify_signature(&self, records: &[DnsRecord], rrsig: &DnsRecord, keys: &[DnsRecord]) -> std::result::Result<(u16, u8), ChainError> {
+        let RData::Rrsig { algorithm, expiration, inception, key_tag: tag, signature, .. } = &rrsig.rdata else {
+            return Err(ChainError::MissingSignature);
+        };
+        if serial_before(*expiration, self.now) {
+            return Err(ChainError::SignatureExpired { expiration: *expiration });
+        }
+        if serial_before(self.now, *inception) {
+            return Err(ChainError::SignatureNotYetValid { inception: *inception });
+        }
+
+        let data = signed_data(rrsig, records);
+        let mut error = ChainError::MissingKey { key_tag: *tag };
+        for key in keys.iter().filter(|key| key_tag(&key.data) == *tag) {
+            let RData::Dnskey { flags, algorithm: key_algorithm, public_key, .. } = &key.rdata else {
+                continue;
+            };
+            if flags & FLAG_ZONE_KEY == 0 || flags & FLAG_REVOKE != 0 {
+                continue;
+            }
+            if key_algorithm != algorithm {
+                error = ChainError::AlgorithmMismatch { key_tag: *tag, expected: *algorithm, actual: *key_algorithm };
+                continue;
+            }
+            match verify(*algorithm, public_key, &data, signature) {
+                Some(true) => return Ok((*tag, *algorithm)),
+                Some(false) => error = ChainError::BadSignature { key_tag: *tag },
+                None => return Err(ChainError::UnsupportedAlgorithm { algorithm: *algorithm })
+            }
+        }
+        Err(error)
+    }
+
+    /**
+     * Records of the name and type in answer section and RRSIG covering them, the response is cached
+     */
+    fn rrset(&mut self, name: &str, dns_type: u16) -> std::result::Result<(Vec<DnsRecord>, Vec<DnsRecord>), ChainError> {
+        let key = (zone_name(name).to_ascii_lowercase(), dns_type);
+        if !self.cache.contains_key(&key) {
+            let message = (self.fetch)(name, dns_type).map_err(|e| ChainError::QueryFailed { error: e.to_string() })?;
+            self.cache.insert(key.clone(), message);
+        }
+        let message = &self.cache[&key];
+        match message.rcode() {
+            Rcode::NoError => Ok(select(&message.answers, name, dns_type)),
+            rcode => Err(ChainError::QueryFailed { error: format!("{} {} is {}", name, type_name(dns_type), rcode) })
+        }
+    }
+}
+
+/**
+ * Keep the most specific error, missing key only tells the signature was made by another key
+ */
+fn keep_specific(error: &mut Option<ChainError>, e: ChainError) {
+    if error.is_none() || matches!(error, Some(ChainError::MissingKey { .. })) {
+        *error = Some(e);
+    }
+}
+
+/**
+ * Owner and type of rrsets in the section, in order of appearance
+ */
+fn rrset_keys(records: &[DnsRecord]) -> Vec<(String, u16)> {
+    let mut keys: Vec<(String, u16)> = Vec::new();
+    for record in records.iter().filter(|record| record.dns_type != RecordType::Rrsig.code()) {
+        if !keys.iter().any(|(owner, dns_type)| *dns_type == record.dns_type && same_name(owner, &record.name)) {
+            keys.push((record.name.clone(), record.dns_type));
+        }
+    }
+    keys
+}
+
+/**
+ * Records of the owner and type, and RRSIG which covers them
+ */
+fn select(records: &[DnsRecord], owner: &str, dns_type: u16) -> (Vec<DnsRecord>, Vec<DnsRecord>) {
+    let owned = records.iter().filter(|record| same_name(&record.name, owner));
+    let rrset = owned.clone().filter(|record| record.dns_type == dns_type).cloned().collect();
+    let signatures = owned.filter(|record| matches!(record.rdata, RData::Rrsig { type_covered, .. } if type_covered == dns_type)).cloned().collect();
+    (rrset, signatures)
+}
+
+/**
+ * DNSKEY which DS records refer by key tag, algorithm and digest
+ */
+fn match_ds(zone: &str, ds: &[DnsRecord], keys: &[DnsRecord]) -> std::result::Result<Vec<DnsRecord>, ChainError> {
+    let mut entry_keys: Vec<DnsRecord> = Vec::new();
+    let mut error: Option<ChainError> = None;
+    for record in ds {
+        let RData::Ds { key_tag: tag, algorithm, digest_type, digest } = &record.rdata else {
+            continue;
+        };
+        let tagged: Vec<&DnsRecord> = keys.iter().filter(|key| key_tag(&key.data) == *tag).collect();
+        if tagged.is_empty() {
+            keep_specific(&mut error, ChainError::MissingKey { key_tag: *tag });
+        }
+        for key in tagged {
+            let RData::Dnskey { algorithm: key_algorithm, .. } = &key.rdata else {
+                continue;
+            };
+            if key_algorithm != algorithm {
+                keep_specific(&mut error, ChainError::AlgorithmMismatch { key_tag: *tag, expected: *algorithm, actual: *key_algorithm });
+                continue;
+            }
+            match ds_digest(zone, &key.data, *digest_type) {
+                Some(computed) if computed == *digest => entry_keys.push(key.clone()),
+                Some(_) => keep_specific(&mut error, ChainError::DigestMismatch { key_tag: *tag }),
+                None => keep_specific(&mut error, ChainError::UnsupportedDigest { digest_type: *digest_type })
+            }
+        }
+    }
+    match entry_keys.is_empty() {
+        true => Err(error.unwrap_or(ChainError::MissingDs)),
+        false => Ok(entry_keys)
+    }
+}
+
+/**
+ * Key tag of DNSKEY rdata (RFC 4034 appendix B)
+ */
+pub fn key_tag(rdata: &[u8]) -> u16 {
+    let mut sum: u32 = rdata.iter().enumerate()
+        .map(|(i, b)| if i % 2 == 0 { (*b as u32) << 8 } else { *b as u32 })
+        .sum();
+    sum += (sum >> 16) & 0xffff;
+    (sum & 0xffff) as u16
+}
+
+/**
+ * Digest of DS over owner name and DNSKEY rdata (RFC 4034 5.1.4), None if the digest type is not supported
+ */
+pub fn ds_digest(owner: &str, rdata: &[u8], digest_type: u8) -> Option<Vec<u8>> {
+    let algorithm = match digest_type {
+        1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+        2 => &digest::SHA256,
+        4 => &digest::SHA384,
+        _ => return None
+    };
+    let mut context = digest::Context::new(algorithm);
+    context.update(&canonical_name(owner));
+    context.update(rdata);
+    Some(context.finish().as_ref().to_vec())
+}
+
+/**
+ * Verify signature by DNSKEY public key, None if the algorithm is not supported.
+ * RSA/SHA-1, RSA/SHA-256, RSA/SHA-512, ECDSA P-256, ECDSA P-384 and Ed25519 are supported.
+ */
+pub fn verify(algorithm: u8, public_key: &[u8], message: &[u8], signature: &[u8]) -> Option<bool> {
+    use ring::signature as ring_signature;
+
+    let verified = match algorithm {
+        5 | 7 | 8 | 10 => {
+            // zone signing keys of 1024 bits are still common
+            let parameters = match algorithm {
+                8 => &ring_signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY,
+                10 => &ring_signature::RSA_PKCS1_1024_8192_SHA512_FOR_LEGACY_USE_ONLY,
+                _ => &ring_signature::RSA_PKCS1_1024_8192_SHA1_FOR_LEGACY_USE_ONLY
+            };
+            rsa_components(public_key).is_some_and(|key| key.verify(parameters, message, signature).is_ok())
+        }
+        // public key is x and y, the uncompressed point is prefixed by 4
+        13 => UnparsedPublicKey::new(&ring_signature::ECDSA_P256_SHA256_FIXED, [&[4], public_key].concat()).verify(message, signature).is_ok(),
+        14 => UnparsedPublicKey::new(&ring_signature::ECDSA_P384_SHA384_FIXED, [&[4], public_key].concat()).verify(message, signature).is_ok(),
+        15 => UnparsedPublicKey::new(&ring_signature::ED25519, public_key).verify(message, signature).is_ok(),
+        _ => return None
+    };
+    Some(verified)
+}
+
+/**
+ * Exponent and modulus of RSA public key (RFC 3110 2), leading zeros are removed
+ */
+fn rsa_components(public_key: &[u8]) -> Option<RsaPublicKeyComponents<&[u8]>> {
+    let (&size, rest) = public_key.split_first()?;
+    // zero length is followed by 2 bytes length of long exponent
+    let (size, rest) = match size {
+        0 => {
+            let (size, rest) = rest.split_first_chunk::<2>()?;
+            (u16::from_be_bytes(*size) as usize, rest)
+        }
+        _ => (size as usize, rest)
+    };
+    if rest.len() <= size {
+        return None;
+    }
+    let (e, n) = rest.split_at(size);
+    Some(RsaPublicKeyComponents { n: trim_zeros(n), e: trim_zeros(e) })
+}
+
+fn trim_zeros(bytes: &[u8]) -> &[u8] {
+    &bytes[bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len())..]
+}
+
+/**
+ * Data which RRSIG signs, RRSIG rdata without signature and the rrset in canonical form (RFC 4034 3.1.8.1)
+ */
+fn signed_data(rrsig: &DnsRecord, records: &[DnsRecord]) -> Vec<u8> {
+    let RData::Rrsig { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, .. } = &rrsig.rdata else {
+        return Vec::new();
+    };
+    let mut data: Vec<u8> = Vec::new();
+    data.extend_from_slice(&type_covered.to_be_bytes());
+    data.push(*algorithm);
+    data.push(*labels);
+    data.extend_from_slice(&original_ttl.to_be_bytes());
+    data.extend_from_slice(&expiration.to_be_bytes());
+    data.extend_from_slice(&inception.to_be_bytes());
+    data.extend_from_slice(&key_tag.to_be_bytes());
+    data.extend_from_slice(&canonical_name(signer));
+
+    let Some(first) = records.first() else {
+        return data;
+    };
+    let owner = canonical_owner(&first.name, *labels);
+    let mut rdatas: Vec<Vec<u8>> = records.iter().map(canonical_rdata).collect();
+    rdatas.sort();
+    rdatas.dedup();
+    for rdata in rdatas {
+        data.extend_from_slice(&owner);
+        data.extend_from_slice(&first.dns_type.to_be_bytes());
+        data.extend_from_slice(&first.dns_class.to_be_bytes());
+        data.extend_from_slice(&original_ttl.to_be_bytes());
+        data.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        data.extend_from_slice(&rdata);
+    }
+    data
+}
+
+/**
+ * Owner in canonical form, owner of wildcard expansion is *. and the labels which RRSIG counts (RFC 4035 5.3.2)
+ */
+fn canonical_owner(name: &str, labels: u8) -> Vec<u8> {
+    let wire = canonical_name(name);
+    let mut offsets: Vec<usize> = Vec::new();
+    let mut pos = 0;
+    while wire.get(pos).is_some_and(|size| *size != 0) {
+        offsets.push(pos);
+        pos += wire[pos] as usize + 1;
+    }
+    match offsets.len().checked_sub(labels as usize) {
+        Some(skipped) if skipped > 0 => [&[1, b'*'], &wire[offsets[skipped]..]].concat(),
+        _ => wire
+    }
+}
+
+/**
+ * Rdata in canonical form, names in rdata are lowercase and uncompressed (RFC 4034 6.2)
+ */
+fn canonical_rdata(record: &DnsRecord) -> Vec<u8> {
+    match &record.rdata {
+        RData::Cname(name) | RData::Ns(name) | RData::Ptr(name) => canonical_name(name),
+        RData::Mx { preference, exchange } => [&preference.to_be_bytes()[..], &canonical_name(exchange)].concat(),
+        RData::Soa { mname, rname, serial, refresh, retry, expire, minimum } => {
+            let mut rdata = [canonical_name(mname), canonical_name(rname)].concat();
+            [serial, refresh, retry, expire, minimum].iter().for_each(|n| rdata.extend_from_slice(&n.to_be_bytes()));
+            rdata
+        }
+        RData::Srv { priority, weight, port, target } =>
+            [&priority.to_be_bytes()[..], &weight.to_be_bytes(), &port.to_be_bytes(), &canonical_name(target)].concat(),
+        _ => record.data.clone()
+    }
+}
+
+/**
+ * Lowercase name in wire format, labels never contain uppercase as their length.
+ * Names are decoded from messages within the limits, or already queried, so that they are encoded again.
+ */
+fn canonical_name(name: &str) -> Vec<u8> {
+    write_name(name).unwrap_or_default().to_ascii_lowercase()
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    canonical_name(a) == canonical_name(b)
+}
+
+/**
+ * Name without trailing dot, "." for root
+ */
+fn zone_name(name: &str) -> String {
+    match name.trim_end_matches('.') {
+        "" => ".".to_string(),
+        name => name.to_string()
+    }
+}
+
+/**
+ * a is before b in serial number arithmetic (RFC 1982), signature times wrap around in 2106
+ */
+fn serial_before(a: u32, b: u32) -> bool {
+    (b.wrapping_sub(a) as i32) > 0
+}
+
+/**
+ * Parse DS records of trust anchors in zone file format, other lines are ignored
+ */
+pub fn parse_trust_anchors(text: &str) -> Result<Vec<DnsRecord>> {
+    let mut anchors: Vec<DnsRecord> = Vec::new();
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap_or_default();
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some(position) = words.iter().position(|word| word.eq_ignore_ascii_case("DS")) else {
+            continue;
+        };
+        let invalid = || NetDoctorError::InvalidArgument(
+            format!("trust anchor {}, expected like . IN DS <key tag> <algorithm> <digest type> <digest>", line.trim()));
+        if position == 0 || words.len() < position + 5 {
+            return Err(invalid());
+        }
+        let key_tag: u16 = words[position + 1].parse().map_err(|_| invalid())?;
+        let algorithm: u8 = words[position + 2].parse().map_err(|_| invalid())?;
+        let digest_type: u8 = words[position + 3].parse().map_err(|_| invalid())?;
+        // digest may be split by spaces
+        let digest = from_hex(&words[position + 4..].concat()).ok_or_else(invalid)?;
+
+        let mut data = key_tag.to_be_bytes().to_vec();
+        data.push(algorithm);
+        data.push(digest_type);
+        data.extend_from_slice(&digest);
+        anchors.push(DnsRecord::new(&zone_name(words[0]), RecordType::Ds.code(), CLASS_IN, 0, data)?);
+    }
+    Ok(anchors)
+}
+
+#[cfg(test)]
+mod tests {
+    use ring::{rand::SystemRandom, signature::{EcdsaKeyPair, Ed25519KeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING}};
+
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use crate::network::types::dns_packet::DnsHeader;
+
+    use super::*;
+
+    // signatures are valid for a day around this time
+    const NOW: u32 = 1_760_000_000;
+
+    /**
+     * Signing key of a stand-in zone
+     */
+    enum TestKey {
+        Ed25519(Ed25519KeyPair),
+        Ecdsa(EcdsaKeyPair)
+    }
+
+    impl TestKey {
+        fn ed25519() -> TestKey {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            TestKey::Ed25519(Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap())
+        }
+
+        fn ecdsa() -> TestKey {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            TestKey::Ecdsa(EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap())
+        }
+
+        fn algorithm(&self) -> u8 {
+            match self {
+                TestKey::Ed25519(_) => 15,
+                TestKey::Ecdsa(_) => 13
+            }
+        }
+
+        // key signing key of the zone
+        fn dnskey(&self, zone: &str) -> DnsRecord {
+            let public_key = match self {
+                TestKey::Ed25519(key) => key.public_key().as_ref().to_vec(),
+                TestKey::Ecdsa(key) => key.public_key().as_ref()[1..].to_vec()
+            };
+            let data = [&[1, 1, 3, self.algorithm()], &public_key[..]].concat();
+            DnsRecord::new(zone, RecordType::Dnskey.code(), CLASS_IN, 3600, data).unwrap()
+        }
+
+        fn ds(&self, zone: &str, algorithm: u8) -> DnsRecord {
+            let dnskey = self.dnskey(zone);
+            let digest = ds_digest(zone, &dnskey.data, 2).unwrap();
+            let data = [&key_tag(&dnskey.data).to_be_bytes()[..], &[algorithm, 2], &digest].concat();
+            DnsRecord::new(zone, RecordType::Ds.code(), CLASS_IN, 3600, data).unwrap()
+        }
+
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            match self {
+                TestKey::Ed25519(key) => key.sign(message).as_ref().to_vec(),
+                TestKey::Ecdsa(key) => key.sign(&SystemRandom::new(), message).unwrap().as_ref().to_vec()
+            }
+        }
+
+        // RRSIG of the rrset valid until expiration
+        fn rrsig(&self, records: &[DnsRecord], signer: &str, expiration: u32) -> DnsRecord {
+            let first = &records[0];
+            let labels = first.name.split('.').filter(|label| !label.is_empty()).count() as u8;
+            let mut data = first.dns_type.to_be_bytes().to_vec();
+            data.extend_from_slice(&[self.algorithm(), labels]);
+            data.extend_from_slice(&first.ttl.to_be_bytes());
+            data.extend_from_slice(&expiration.to_be_bytes());
+            data.extend_from_slice(&(expiration - 2 * 86400).to_be_bytes());
+            data.extend_from_slice(&key_tag(&self.dnskey(signer).data).to_be_bytes());
+            data.extend_from_slice(&write_name(signer).unwrap());
+            let unsigned = DnsRecord::new(&first.name, RecordType::Rrsig.code(), CLASS_IN, first.ttl, data.clone()).unwrap();
+            data.extend_from_slice(&self.sign(&signed_data(&unsigned, records)));
+            DnsRecord::new(&first.name, RecordType::Rrsig.code(), CLASS_IN, first.ttl, data).unwrap()
+        }
+    }
+
+    /**
+     * Signed root zone and example zone, www.example A is signed by example key
+     */
+    struct TestChain {
+        root: TestKey,
+        example: TestKey,
+        records: HashMap<(String, u16), Vec<DnsRecord>>
+    }
+
+    impl TestChain {
+        fn new() -> TestChain {
+            let mut chain = TestChain { root: TestKey::ed25519(), example: TestKey::ecdsa(), records: HashMap::new() };
+            let expiration = NOW + 86400;
+            chain.set(vec![chain.root.dnskey(".")], ".", expiration);
+            chain.set(vec![chain.example.ds("example", 13)], ".", expiration);
+            chain.set(vec![chain.example.dnskey("example")], "example", expiration);
+            let a = DnsRecord::new("www.example", RecordType::A.code(), CLASS_IN, 300, vec![192, 0, 2, 1]).unwrap();
+            chain.set(vec![a], "example", expiration);
+            chain
+        }
+
+        // records of an rrset and its RRSIG by the key of signer
+        fn set(&mut self, records: Vec<DnsRecord>, signer: &str, expiration: u32) {
+            let key = if signer == "." { &self.root } else { &self.example };
+            let rrsig = key.rrsig(&records, signer, expiration);
+            let name = (records[0].name.to_ascii_lowercase(), records[0].dns_type);
+            self.records.insert(name, [records, vec![rrsig]].concat());
+        }
+
+        fn validate(&self, anchor: DnsRecord) -> DnssecReport {
+            let records = self.records.clone();
+            let fetch = move |name: &str, dns_type: u16| {
+                let answers = records.get(&(name.to_ascii_lowercase(), dns_type)).cloned().unwrap_or_default();
+                // negative response has SOA of the zone in authority section
+                let authorities = match answers.is_empty() {
+                    true => records.get(&("example".to_string(), RecordType::Soa.code())).cloned().unwrap_or_default(),
+                    false => Vec::new()
+                };
+                let header = DnsHeader { response: true, ..Default::default() };
+                Ok(DnsMessage { id: 0, header, questions: Vec::new(), answers, authorities, additionals: Vec::new() })
+            };
+            let mut validator = Validator { fetch, anchors: vec![anchor], now: NOW, cache: HashMap::new(), secure_zones: HashSet::new() };
+            validator.validate("www.example.", RecordType::A).unwrap()
+        }
+    }
+
+    #[test]
+    fn key_tag_test() {
+        // dskey.example.com of RFC 4034 5.4
+        let public_key = STANDARD.decode("AQOeiiR0GOMYkDshWoSKz9XzfwJr1AYtsmx3TGkJaNXVbfi/2pHm822aJ5iI9BMzNXxeYCmZDRD99WYwYqUSdjMmmAphXdvx\
+egXd/M5+X7OrzKBaMbCVdFLUUh6DhweJBjEVv5f2wwjM9XzcnOf+EPbtG9DMBmADjFDc2w/rljwvFw==").unwrap();
+        let rdata = [&[1, 0, 3, 5], &public_key[..]].concat();
+        assert_eq!(key_tag(&rdata), 60485);
+        let digest = ds_digest("dskey.example.com.", &rdata, 1).unwrap();
+        assert_eq!(digest, from_hex("2BB183AF5F22588179A53B0A98631FAD1A292118").unwrap());
+        assert_eq!(ds_digest("DSKEY.Example.com", &rdata, 1).unwrap(), digest);
+        assert_eq!(ds_digest("dskey.example.com", &rdata, 3), None);
+    }
+
+    #[test]
+    fn verify_rsa_test() {
+        // RSA/SHA-256 key of 1024 bits in RFC 3110 format and a signature made by openssl
+        let public_key = STANDARD.decode("AwEAAeaaaJqeJCc9siYnjdKKbZ+SevnC4SDOBXJ+FoE7LnzRi0CtGux5+ExsnslMw110ydzRVRl5ZSeBK7/bPHaHZBVjEGbqxCfnmdHss\
+435trRL0ev29nRWRFYCQlmG6/blS+Iq3meDY5UMu/6VBVwoXHWXVhCHQ2Jpci7SFh3Fy2x1").unwrap();
+        let signature = STANDARD.decode("QZpNd0DTsVmceZQWPGli6sjJmsqvVlTo0g6JdOZJ51tao/GOdp798l06P4U4Nh0XL4E29qJyNsv19Qfvq/nLd8u42OidK7VbZtj0wt6br\
++ZMcVMFL4l6f6TckFISvRIZnFrPoKoy11oovnBnkxlVW2i2X1UkZwZgGulgekki/hM=").unwrap();
+        assert_eq!(verify(8, &public_key, b"net-doctor dnssec", &signature), Some(true));
+        assert_eq!(verify(8, &public_key, b"net-doctor dnssed", &signature), Some(false));
+        assert_eq!(verify(10, &public_key, b"net-doctor dnssec", &signature), Some(false));
+        assert_eq!(verify(8, &public_key[..1], b"net-doctor dnssec", &signature), Some(false));
+        // ED448 is not supported by ring
+        assert_eq!(verify(16, &public_key, b"net-doctor dnssec", &signature), None);
+    }
+
+    #[test]
+    fn canonical_form_test() {
+        assert_eq!(canonical_owner("a.b.Example", 2), write_name("*.b.example").unwrap());
+        assert_eq!(canonical_owner("b.example", 2), write_name("b.example").unwrap());
+        let mx = DnsRecord::new("example", RecordType::Mx.code(), CLASS_IN, 300, [&[0, 10], &write_name("Mail.Example").unwrap()[..]].concat()).unwrap();
+        assert_eq!(canonical_rdata(&mx), [&[0, 10], &write_name("mail.example").unwrap()[..]].concat());
+        assert!(serial_before(u32::MAX, 1));
+        assert!(!serial_before(NOW, NOW));
+    }
+
+    #[test]
+    fn validate_secure_test() {
+        let chain = TestChain::new();
+        let report = chain.validate(chain.root.ds(".", 15));
+        assert!(report.secure, "{}", report);
+        let links: Vec<(&str, &str, Option<&str>)> = report.links.iter()
+            .map(|link| (link.name.as_str(), link.record_type.as_str(), link.signer.as_deref()))
+            .collect();
+        assert_eq!(links, vec![
+            ("www.example", "A", Some("example")),
+            ("example", "DNSKEY", Some("example")),
+            ("example", "DS", Some(".")),
+            (".", "DNSKEY", Some("."))
+        ]);
+        assert_eq!((report.links[0].algorithm, report.links[3].algorithm), (Some(13), Some(15)));
+        assert!(report.links[3].trust_anchor);
+        assert_eq!(report.answers.len(), 1);
+    }
+
+    #[test]
+    fn validate_broken_test() {
+        // expired signature of the answer
+        let mut chain = TestChain::new();
+        let a = chain.records[&("www.example".to_string(), 1)][0].clone();
+        chain.set(vec![a.clone()], "example", NOW - 60);
+        let report = chain.validate(chain.root.ds(".", 15));
+        assert!(!report.secure);
+        assert_eq!(report.links.len(), 1);
+        assert_eq!(report.links[0].error, Some(ChainError::SignatureExpired { expiration: NOW - 60 }));
+
+        // answer is changed after signing
+        let mut chain = TestChain::new();
+        let rrset = chain.records.get_mut(&("www.example".to_string(), 1)).unwrap();
+        rrset[0] = DnsRecord { data: vec![192, 0, 2, 66], rdata: RData::A([192, 0, 2, 66].into()), ..a };
+        let report = chain.validate(chain.root.ds(".", 15));
+        assert!(matches!(report.links[0].error, Some(ChainError::BadSignature { .. })));
+
+        // delegation to example is not signed
+        let mut chain = TestChain::new();
+        chain.records.remove(&("example".to_string(), RecordType::Ds.code()));
+        let report = chain.validate(chain.root.ds(".", 15));
+        assert_eq!(report.links.len(), 2);
+        assert_eq!((report.links[1].name.as_str(), report.links[1].record_type.as_str()), ("example", "DS"));
+        assert_eq!(report.links[1].error, Some(ChainError::MissingDs));
+
+        // DS refers RSA/SHA-256 but the key is ECDSA
+        let mut chain = TestChain::new();
+        chain.set(vec![chain.example.ds("example", 8)], ".", NOW + 86400);
+        let report = chain.validate(chain.root.ds(".", 15));
+        let tag = key_tag(&chain.example.dnskey("example").data);
+        assert_eq!(report.links[1].error, Some(ChainError::AlgorithmMismatch { key_tag: tag, expected: 8, actual: 13 }));
+
+        // root key is not the trust anchor
+        let chain = TestChain::new();
+        let report = chain.validate(TestKey::ed25519().ds(".", 15));
+        assert_eq!(report.links.len(), 4);
+        assert!(matches!(report.links[3].error, Some(ChainError::MissingKey { .. })));
+        let anchor = chain.root.ds(".", 15);
+        let anchor = match anchor.rdata {
+            RData::Ds { key_tag, algorithm, digest_type, .. } => {
+                let data = [&key_tag.to_be_bytes()[..], &[algorithm, digest_type], &[0; 32]].concat();
+                DnsRecord::new(".", RecordType::Ds.code(), CLASS_IN, 0, data).unwrap()
+            }
+            _ => unreachable!()
+        };
+        let report = chain.validate(anchor);
+        assert!(matches!(report.links[3].error, Some(ChainError::DigestMismatch { .. })));
+        let json = serde_json::to_value(&report.links[3]).unwrap();
+        assert_eq!(json["error"]["reason"], "digest_mismatch");
+    }
+
+    #[test]
+    fn validate_negative_test() {
+        // signed SOA of the response without answer does not prove that www.example has no A
+        let mut chain = TestChain::new();
+        chain.records.remove(&("www.example".to_string(), RecordType::A.code()));
+        let data = [&write_name("ns.example").unwrap()[..], &write_name("admin.example").unwrap(), &[0; 20]].concat();
+        chain.set(vec![DnsRecord::new("example", RecordType::Soa.code(), CLASS_IN, 300, data).unwrap()], "example", NOW + 86400);
+        let report = chain.validate(chain.root.ds(".", 15));
+        assert!(!report.secure);
+        assert_eq!((report.links[0].record_type.as_str(), report.links[0].secure), ("SOA", true));
+        assert_eq!(report.links.len(), 5);
+        assert_eq!(report.links[4].error, Some(ChainError::DenialNotProven));
+        assert!(report.to_string().contains("denial of existence is not proven"), "{}", report);
+    }
+
+    #[test]
+    fn parse_trust_anchors_test() {
+        let anchors = parse_trust_anchors(ROOT_ANCHORS).unwrap();
+        assert_eq!(anchors.len(), 2);
+        assert_eq!(anchors[0].name, ".");
+        assert_eq!(anchors[0].rdata.to_string(), "20326 8 2 E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D");
+
+        let anchors = parse_trust_anchors("; lab zone\nexample. 3600 IN DS 4242 13 2 0011 2233\nexample. IN DNSKEY 257 3 13 AAAA\n").unwrap();
+        assert_eq!(anchors.len(), 1);
+        assert_eq!((anchors[0].name.as_str(), anchors[0].rdata.to_string()), ("example", "4242 13 2 00112233".to_string()));
+
+        assert!(parse_trust_anchors("example. IN DS 4242 13 2 zz").is_err());
+        assert!(parse_trust_anchors("DS 4242 13 2 0011").is_err());
+    }
+}
@@ -1,7 +1,6 @@
 extern crate pnet;
 
 use log::debug;
-use log::error;
 use log::info;
 use pnet::packet::Packet;
 use pnet::packet::icmp::IcmpPacket;
@@ -9,122 +8,679 @@ use pnet::packet::icmp::IcmpTypes;
 use pnet::packet::icmp::IcmpCode;
 use pnet::packet::icmp::checksum;
 use pnet::packet::icmp::echo_reply::EchoReplyPacket;
+use pnet::packet::icmp::echo_request::EchoRequestPacket;
 use pnet::packet::icmp::echo_request::MutableEchoRequestPacket;
-use pnet::packet::icmp::time_exceeded;
 use pnet::packet::ip::IpNextHeaderProtocols;
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::ipv4::MutableIpv4Packet;
+use pnet::packet::icmpv6::Icmpv6Code;
+use pnet::packet::icmpv6::Icmpv6Packet;
+use pnet::packet::icmpv6::Icmpv6Types;
+use pnet::packet::icmpv6::echo_reply::EchoReplyPacket as Icmpv6EchoReplyPacket;
+use pnet::packet::icmpv6::echo_request::MutableEchoRequestPacket as MutableIcmpv6EchoRequestPacket;
+use pnet::transport::icmpv6_packet_iter;
 use pnet::transport::ipv4_packet_iter;
 use pnet::transport::transport_channel;
 use pnet::transport::TransportChannelType;
+use pnet::transport::TransportProtocol;
+use pnet::transport::TransportReceiver;
+use pnet::transport::TransportSender;
 use rand::Rng;
+use serde::Serialize;
+use serde::ser::SerializeStruct;
+use socket2::Domain;
+use socket2::Protocol;
+use socket2::SockAddr;
+use socket2::SockRef;
+use socket2::Socket;
+use socket2::Type;
+use std::fmt;
+use std::io;
 use std::net::IpAddr;
 use std::net::Ipv4Addr;
-use std::str::FromStr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+use std::net::UdpSocket;
+use std::os::fd::AsRawFd;
+use std::thread;
 use std::time::Duration;
+use std::time::Instant;
 
-use crate::types::BaseConfig;
+use crate::error::Result;
+use crate::types::PingConfig;
+use crate::utils::serde_utils;
+
+use super::dns::resolve_target;
+use super::types::icmp_error::IcmpError;
+use super::types::icmp_error::IcmpErrorKind;
 
 const MAX_PACKET_SIZE: usize = 44;
+const IPV4_HEADER_SIZE: usize = 20;
 const PING_TIME_OUT: u64 = 3000;
 
 /**
- * Send icmp packet
+ * Echo reply matched with a sent echo request
  */
-pub fn ping(config: BaseConfig) {
+#[derive(Debug, Clone, Serialize)]
+pub struct PingReply {
+    pub source: IpAddr,
+    pub ttl: Option<u8>,
+    pub size: usize
+}
 
-    // Create icmp packet
-    let mut icmp_buf: Vec<u8> = vec![0; MAX_PACKET_SIZE];
-    let mut icmp_packet: MutableEchoRequestPacket = MutableEchoRequestPacket::new(&mut icmp_buf).unwrap();
-    make_icmp_packet(&mut icmp_packet);
+/**
+ * Response of an echo request
+ */
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PingResponse {
+    Reply(PingReply),
+    // Icmp error triggered by the echo request
+    Error(IcmpError)
+}
 
-    // Send icmp packet, and receive packet
-    let (mut tx, mut rx) = transport_channel(512,
-             TransportChannelType::Layer3(IpNextHeaderProtocols::Icmp)).unwrap();
-    let mut rx = ipv4_packet_iter(&mut rx);
+/**
+ * Round trip statistics of ping
+ */
+#[derive(Debug, Clone, Default)]
+pub struct PingStatistics {
+    pub transmitted: u32,
+    pub received: u32,
+    pub errors: u32,
+    pub min: f64,
+    pub max: f64,
+    sum: f64,
+    sum_square: f64
+}
+
+impl PingStatistics {
+    /**
+     * Add round trip time (ms) of received reply
+     */
+    pub fn add(&mut self, rtt: f64) {
+        if self.received == 0 || rtt < self.min {
+            self.min = rtt;
+        }
+        if rtt > self.max {
+            self.max = rtt;
+        }
+        self.sum += rtt;
+        self.sum_square += rtt * rtt;
+        self.received += 1;
+    }
 
-    // Receive packet
-    let mut is_reach = false;
+    pub fn avg(&self) -> f64 {
+        if self.received == 0 {
+            return 0.0;
+        }
+        self.sum / self.received as f64
+    }
 
-    // Create Ipv4 packet
-    let mut ip_buf: Vec<u8> = vec![0; MAX_PACKET_SIZE * 2];
-    let mut ip_packet: MutableIpv4Packet = MutableIpv4Packet::new(&mut ip_buf).unwrap();
-    make_ip_packet(&mut ip_packet, &config);
-    ip_packet.set_payload(&icmp_packet.packet());
+    /**
+     * Mean deviation, calculated the same way as iputils ping
+     */
+    pub fn mdev(&self) -> f64 {
+        if self.received == 0 {
+            return 0.0;
+        }
+        let avg = self.avg();
+        (self.sum_square / self.received as f64 - avg * avg).max(0.0).sqrt()
+    }
 
-    loop {
+    pub fn loss(&self) -> f64 {
+        if self.transmitted == 0 {
+            return 0.0;
+        }
+        (self.transmitted - self.received) as f64 * 100.0 / self.transmitted as f64
+    }
+}
 
-        ip_packet.set_ttl(ip_packet.get_ttl() + 1);
-        let src_ip = ip_packet.get_source();
-        let dest_ip = ip_packet.get_destination();
-
-        let send_packet = Ipv4Packet::new(&ip_packet.packet()).unwrap();
-        tx.send_to(send_packet, IpAddr::V4(dest_ip)).unwrap();
-
-        match rx.next_with_timeout(Duration::from_millis(PING_TIME_OUT)) {
-            Ok(ip_response) => {
-                // Check icmp response
-                let tmp_p = ip_response.unwrap_or_else(|| {
-                    error!("Icmp: send Timeout");
-                    panic!();
-                });
-
-                let res_packet = Ipv4Packet::new(tmp_p.0.packet()).unwrap();
-                debug!("Icmp: res_packet {:?}", &res_packet);
-                match res_packet.get_next_level_protocol() {
-                    IpNextHeaderProtocols::Icmp => {
-                        let res_icmp_packet = EchoReplyPacket::new(&mut res_packet.payload()).unwrap();
-                        debug!("Icmp: res_icmp_packet {:?}", &res_icmp_packet);
-                        match res_icmp_packet.get_icmp_type() {
-                            IcmpTypes::EchoReply => {
-                                debug!("Icmp: Reach from {} to {}", res_packet.get_source(), res_packet.get_destination());
-                                is_reach = true;
-                            }
-                            IcmpTypes::TimeExceeded => match res_icmp_packet.get_icmp_code() {
-                                time_exceeded::IcmpCodes::TimeToLiveExceededInTransit => {
-                                    debug!("Icmp: Timeout from {} to {}", res_packet.get_source(), res_packet.get_destination());
-                                }
-                                _ => {
-                                    error!("Icmp: TimeExceeded");
-                                    panic!();
-                                } 
-                            }
-                            _ => {
-                            }
+impl Serialize for PingStatistics {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("PingStatistics", 8)?;
+        state.serialize_field("transmitted", &self.transmitted)?;
+        state.serialize_field("received", &self.received)?;
+        state.serialize_field("errors", &self.errors)?;
+        state.serialize_field("loss", &self.loss())?;
+        state.serialize_field("min", &self.min)?;
+        state.serialize_field("avg", &self.avg())?;
+        state.serialize_field("max", &self.max)?;
+        state.serialize_field("mdev", &self.mdev())?;
+        state.end()
+    }
+}
+
+/**
+ * Result of an echo request
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct PingProbe {
+    pub sequence: u16,
+    // round trip time (ms) of echo reply
+    pub rtt: Option<f64>,
+    // None if timeout
+    pub response: Option<PingResponse>
+}
+
+/**
+ * Socket mode of the icmp channel
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PingMode {
+    // unprivileged icmp datagram socket
+    Datagram,
+    Raw,
+    RawV6
+}
+
+impl fmt::Display for PingMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PingMode::Datagram => write!(f, "unprivileged icmp datagram socket"),
+            PingMode::Raw => write!(f, "raw socket"),
+            PingMode::RawV6 => write!(f, "raw icmpv6 socket")
+        }
+    }
+}
+
+/**
+ * Report of ping
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct PingReport {
+    pub destination: IpAddr,
+    pub mode: PingMode,
+    pub probes: Vec<PingProbe>,
+    pub statistics: PingStatistics,
+    #[serde(rename = "time_ms", serialize_with = "serde_utils::millis")]
+    pub time: Duration
+}
+
+impl fmt::Display for PingReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let statistics = &self.statistics;
+        writeln!(f, "Ping: --- {} ping statistics, {} ---", self.destination, self.mode)?;
+        write!(f, "Ping: {} packets transmitted, {} received, ", statistics.transmitted, statistics.received)?;
+        if statistics.errors > 0 {
+            write!(f, "+{} errors, ", statistics.errors)?;
+        }
+        write!(f, "{:.1}% packet loss, time {}ms", statistics.loss(), self.time.as_millis())?;
+        if statistics.received > 0 {
+            write!(f, "\nPing: rtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms",
+                statistics.min, statistics.avg(), statistics.max, statistics.mdev())?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Icmp channel of each ip version
+ */
+pub enum IcmpChannel {
+    // Ipv4 header is made by ourselves
+    V4 { tx: TransportSender, rx: TransportReceiver },
+    // Icmpv6 only, kernel makes ipv6 header and checksum
+    V6 { tx: TransportSender, rx: TransportReceiver },
+    // Unprivileged icmp datagram socket, kernel rewrites identifier and checksum
+    Datagram { socket: UdpSocket }
+}
+
+impl IcmpChannel {
+    /**
+     * Open unprivileged icmp datagram socket, and fall back to raw socket
+     */
+    pub fn new(dest_ip: IpAddr) -> io::Result<Self> {
+        match IcmpChannel::new_datagram(dest_ip) {
+            Ok(channel) => Ok(channel),
+            Err(e) => {
+                debug!("Icmp: icmp datagram socket is not allowed, {:?}", e);
+                IcmpChannel::new_raw(dest_ip)
+            }
+        }
+    }
+
+    /**
+     * Open icmp datagram socket, allowed for users in net.ipv4.ping_group_range
+     */
+    pub fn new_datagram(dest_ip: IpAddr) -> io::Result<Self> {
+        let socket = match dest_ip {
+            IpAddr::V4(_) => Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4))?,
+            IpAddr::V6(_) => Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::ICMPV6))?
+        };
+        // icmp errors are delivered to datagram socket only with RECVERR
+        match dest_ip {
+            IpAddr::V4(_) => set_recv_err(&socket, libc::SOL_IP, libc::IP_RECVERR)?,
+            IpAddr::V6(_) => set_recv_err(&socket, libc::SOL_IPV6, libc::IPV6_RECVERR)?
+        }
+        Ok(IcmpChannel::Datagram { socket: socket.into() })
+    }
+
+    /**
+     * Open raw socket, root or CAP_NET_RAW is required
+     */
+    pub fn new_raw(dest_ip: IpAddr) -> io::Result<Self> {
+        match dest_ip {
+            IpAddr::V4(_) => {
+                let (tx, rx) = transport_channel(512,
+                    TransportChannelType::Layer3(IpNextHeaderProtocols::Icmp))?;
+                Ok(IcmpChannel::V4 { tx, rx })
+            }
+            IpAddr::V6(_) => {
+                let (tx, rx) = transport_channel(512,
+                    TransportChannelType::Layer4(TransportProtocol::Ipv6(IpNextHeaderProtocols::Icmpv6)))?;
+                Ok(IcmpChannel::V6 { tx, rx })
+            }
+        }
+    }
+
+    /**
+     * Send echo request to target
+     */
+    pub fn send_echo_request(&mut self, dest_ip: IpAddr, ttl: u8, identifier: u16, sequence: u16) -> io::Result<()> {
+        match (self, dest_ip) {
+            (IcmpChannel::V4 { tx, .. }, IpAddr::V4(dest_ip)) => {
+                let ip_buf = make_echo_request(dest_ip, ttl, identifier, sequence);
+                let ip_packet = Ipv4Packet::new(&ip_buf).unwrap();
+                debug!("Icmp: send_packet {:?}", &ip_packet);
+                tx.send_to(ip_packet, IpAddr::V4(dest_ip))?;
+            }
+            (IcmpChannel::V6 { tx, .. }, IpAddr::V6(dest_ip)) => {
+                let icmp_buf = make_echo_request_v6(identifier, sequence);
+                let icmp_packet = Icmpv6Packet::new(&icmp_buf).unwrap();
+                debug!("Icmpv6: send_packet {:?}", &icmp_packet);
+                tx.set_ttl(ttl)?;
+                tx.send_to(icmp_packet, IpAddr::V6(dest_ip))?;
+            }
+            (IcmpChannel::Datagram { socket }, IpAddr::V4(dest_ip)) => {
+                let icmp_buf = make_icmp_echo_request(identifier, sequence);
+                debug!("Icmp: send_packet {:?}", EchoRequestPacket::new(&icmp_buf).unwrap());
+                socket.set_ttl(ttl as u32)?;
+                socket.send_to(&icmp_buf, SocketAddr::new(IpAddr::V4(dest_ip), 0))?;
+            }
+            (IcmpChannel::Datagram { socket }, IpAddr::V6(dest_ip)) => {
+                let icmp_buf = make_echo_request_v6(identifier, sequence);
+                debug!("Icmpv6: send_packet {:?}", Icmpv6Packet::new(&icmp_buf).unwrap());
+                SockRef::from(&*socket).set_unicast_hops_v6(ttl as u32)?;
+                socket.send_to(&icmp_buf, SocketAddr::new(IpAddr::V6(dest_ip), 0))?;
+            }
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "ip version is not matched to channel"));
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Socket mode of the channel
+     */
+    pub fn mode(&self) -> PingMode {
+        match self {
+            IcmpChannel::Datagram { .. } => PingMode::Datagram,
+            IcmpChannel::V4 { .. } => PingMode::Raw,
+            IcmpChannel::V6 { .. } => PingMode::RawV6
+        }
+    }
+
+    /**
+     * Wait echo reply or icmp error matched by identifier and sequence number.
+     */
+    pub fn wait_response(&mut self, identifier: u16, sequence: u16, timeout: Duration) -> io::Result<Option<PingResponse>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+
+            let response = match self {
+                IcmpChannel::V4 { rx, .. } => {
+                    match ipv4_packet_iter(rx).next_with_timeout(remaining)? {
+                        Some((res_packet, _)) => {
+                            debug!("Icmp: res_packet {:?}", &res_packet);
+                            get_response(&res_packet, identifier, sequence)
                         }
+                        None => return Ok(None)
                     }
-                    _ => {
-                        error!("Protocol: {:?}", res_packet.get_next_level_protocol());
+                }
+                IcmpChannel::V6 { rx, .. } => {
+                    match icmpv6_packet_iter(rx).next_with_timeout(remaining)? {
+                        Some((res_packet, source)) => {
+                            debug!("Icmpv6: res_packet {:?}", &res_packet);
+                            get_response_v6(&res_packet, source, identifier, sequence)
+                        }
+                        None => return Ok(None)
                     }
                 }
+                IcmpChannel::Datagram { socket } => {
+                    socket.set_read_timeout(Some(remaining))?;
+                    let mut buf: Vec<u8> = vec![0; 512];
+                    match socket.recv_from(&mut buf) {
+                        Ok((size, source)) => {
+                            debug!("Icmp: res_packet {:?}", &buf[..size]);
+                            get_datagram_echo_reply(&buf[..size], source.ip(), sequence).map(PingResponse::Reply)
+                        }
+                        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                            return Ok(None);
+                        }
+                        // recv fails by pending icmp error, and the error itself is in the error queue
+                        Err(e) => {
+                            let icmp_errors = recv_queued_errors(socket)?;
+                            if icmp_errors.is_empty() {
+                                return Err(e);
+                            }
+                            // kernel uses the local port as identifier
+                            let identifier = socket.local_addr()?.port();
+                            icmp_errors.into_iter()
+                                .find_map(|icmp_error| get_icmp_error(icmp_error, identifier, sequence))
+                        }
+                    }
+                }
+            };
+            if response.is_some() {
+                return Ok(response);
             }
-            Err(e) => {
-                error!("Icmp: {:?}", e);
-                panic!("{:?}", e);
+        }
+    }
+}
+
+/**
+ * Send icmp echo requests, and report round trip time
+ */
+pub fn ping(config: PingConfig) -> Result<PingReport> {
+    ping_with(config, |_| {})
+}
+
+/**
+ * Ping and call on_probe with each probe as soon as it is answered or timed out
+ */
+pub fn ping_with(config: PingConfig, mut on_probe: impl FnMut(&PingProbe)) -> Result<PingReport> {
+    let dest_ip = resolve_target(&config.ip, &config.resolve)?;
+    let identifier: u16 = rand::thread_rng().gen();
+
+    // Send icmp packet, and receive packet
+    let mut channel = IcmpChannel::new(dest_ip)?;
+
+    info!("Ping: {} {} bytes of data, using {}", dest_ip, MAX_PACKET_SIZE, channel.mode());
+
+    let mut statistics = PingStatistics::default();
+    let mut probes: Vec<PingProbe> = Vec::new();
+    let start = Instant::now();
+    for sequence in 0..config.count {
+        let send_time = Instant::now();
+
+        channel.send_echo_request(dest_ip, config.ttl, identifier, sequence)?;
+        statistics.transmitted += 1;
+
+        let mut probe = PingProbe { sequence, rtt: None, response: None };
+        match channel.wait_response(identifier, sequence, Duration::from_millis(PING_TIME_OUT))? {
+            Some(PingResponse::Reply(reply)) => {
+                let rtt = send_time.elapsed().as_secs_f64() * 1000.0;
+                statistics.add(rtt);
+                match reply.ttl {
+                    Some(ttl) => info!("Ping: {} bytes from {}: icmp_seq={} ttl={} time={:.3} ms",
+                        reply.size, reply.source, sequence, ttl, rtt),
+                    None => info!("Ping: {} bytes from {}: icmp_seq={} time={:.3} ms",
+                        reply.size, reply.source, sequence, rtt)
+                }
+                probe.rtt = Some(rtt);
+                probe.response = Some(PingResponse::Reply(reply));
+            }
+            Some(PingResponse::Error(icmp_error)) => {
+                statistics.errors += 1;
+                info!("Ping: icmp_seq={} {}", sequence, icmp_error);
+                probe.response = Some(PingResponse::Error(icmp_error));
+            }
+            None => {
+                info!("Ping: Request timeout for icmp_seq={}", sequence);
             }
         }
+        on_probe(&probe);
+        probes.push(probe);
 
-        if is_reach {
-            info!("Ping: Reach from {} to {}", src_ip, dest_ip);
-            break;
-        } else {
-            icmp_packet.set_sequence_number(icmp_packet.get_sequence_number() + 1);
+        // Wait until next interval
+        if sequence + 1 < config.count {
+            let interval = Duration::from_millis(config.interval);
+            thread::sleep(interval.saturating_sub(send_time.elapsed()));
         }
     }
 
+    Ok(PingReport {
+        destination: dest_ip,
+        mode: channel.mode(),
+        probes,
+        statistics,
+        time: start.elapsed()
+    })
+}
+
+/**
+ * Check the ipv4 response is an echo reply or icmp error of the request
+ */
+fn get_response(res_packet: &Ipv4Packet, identifier: u16, sequence: u16) -> Option<PingResponse> {
+    if res_packet.get_next_level_protocol() != IpNextHeaderProtocols::Icmp {
+        return None;
+    }
+    let res_icmp_packet = EchoReplyPacket::new(res_packet.payload())?;
+    debug!("Icmp: res_icmp_packet {:?}", &res_icmp_packet);
+    if res_icmp_packet.get_icmp_type() == IcmpTypes::EchoReply {
+        if res_icmp_packet.get_identifier() == identifier && res_icmp_packet.get_sequence_number() == sequence {
+            return Some(PingResponse::Reply(PingReply {
+                source: IpAddr::V4(res_packet.get_source()),
+                ttl: Some(res_packet.get_ttl()),
+                size: res_packet.payload().len()
+            }));
+        }
+        return None;
+    }
+    get_icmp_error(IcmpError::from_ipv4(res_packet)?, identifier, sequence)
+}
 
+/**
+ * Check the icmp error is triggered by the request.
+ * Redirect is only logged because the request is still forwarded.
+ */
+fn get_icmp_error(icmp_error: IcmpError, identifier: u16, sequence: u16) -> Option<PingResponse> {
+    if !icmp_error.is_echo_of(identifier, sequence) {
+        return None;
+    }
+    if let IcmpErrorKind::Redirect(_) = icmp_error.kind {
+        info!("Ping: icmp_seq={} {}", sequence, icmp_error);
+        return None;
+    }
+    Some(PingResponse::Error(icmp_error))
+}
 
+/**
+ * Enable RECVERR option of the socket
+ */
+fn set_recv_err(socket: &Socket, level: libc::c_int, name: libc::c_int) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(socket.as_raw_fd(), level, name, (&enable as *const libc::c_int).cast(),
+                         std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/**
+ * Read icmp errors queued on the datagram socket by RECVERR
+ */
+fn recv_queued_errors(socket: &UdpSocket) -> io::Result<Vec<IcmpError>> {
+    let local = socket.local_addr()?.ip();
+    let mut icmp_errors: Vec<IcmpError> = vec![];
+    loop {
+        let mut buf: Vec<u8> = vec![0; 512];
+        // u64 keeps control messages aligned
+        let mut control: [u64; 64] = [0; 64];
+        // name of the message is the destination of the request
+        let res = unsafe {
+            SockAddr::try_init(|storage, len| {
+                let mut iov = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+                let mut msg: libc::msghdr = std::mem::zeroed();
+                msg.msg_name = storage.cast();
+                msg.msg_namelen = *len;
+                msg.msg_iov = &mut iov;
+                msg.msg_iovlen = 1;
+                msg.msg_control = control.as_mut_ptr().cast();
+                msg.msg_controllen = std::mem::size_of_val(&control) as _;
+                let size = libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT);
+                if size < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                *len = msg.msg_namelen;
+                Ok((size as usize, get_extended_err(&msg)))
+            })
+        };
+        let ((size, extended_err), destination) = match res {
+            Ok(res) => res,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(icmp_errors),
+            Err(e) => return Err(e)
+        };
+        debug!("Icmp: queued error {:?} of {:?}", extended_err, &buf[..size]);
+        // errors of local origin have no icmp error
+        let Some((ee_type, ee_code, ee_info, offender)) = extended_err else {
+            continue;
+        };
+        let Some(destination) = destination.as_socket() else {
+            continue;
+        };
+        if let Some(icmp_error) = IcmpError::from_queued(offender, ee_type, ee_code, ee_info,
+                                                         local, destination.ip(), &buf[..size]) {
+            icmp_errors.push(icmp_error);
+        }
+    }
+}
+
+/**
+ * Type, code, info and sender of the icmp error in control messages of the error queue
+ */
+unsafe fn get_extended_err(msg: &libc::msghdr) -> Option<(u8, u8, u32, IpAddr)> {
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+    while !cmsg.is_null() {
+        let level_type = ((*cmsg).cmsg_level, (*cmsg).cmsg_type);
+        if level_type == (libc::SOL_IP, libc::IP_RECVERR) || level_type == (libc::SOL_IPV6, libc::IPV6_RECVERR) {
+            let ee_ptr = libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err;
+            let ee = std::ptr::read_unaligned(ee_ptr);
+            if ee.ee_origin == libc::SO_EE_ORIGIN_ICMP || ee.ee_origin == libc::SO_EE_ORIGIN_ICMP6 {
+                let offender = get_offender(libc::SO_EE_OFFENDER(ee_ptr))?;
+                return Some((ee.ee_type, ee.ee_code, ee.ee_info, offender));
+            }
+        }
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+    None
+}
+
+/**
+ * Address of the router or host which sent the icmp error
+ */
+unsafe fn get_offender(offender: *const libc::sockaddr) -> Option<IpAddr> {
+    match std::ptr::read_unaligned(offender).sa_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = std::ptr::read_unaligned(offender as *const libc::sockaddr_in);
+            Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))))
+        }
+        libc::AF_INET6 => {
+            let addr = std::ptr::read_unaligned(offender as *const libc::sockaddr_in6);
+            Some(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)))
+        }
+        _ => None
+    }
+}
+
+/**
+ * Check the icmp datagram response is an echo reply of the request.
+ * Identifier is rewritten by kernel, and replies are already filtered per socket.
+ */
+fn get_datagram_echo_reply(res_buf: &[u8], source: IpAddr, sequence: u16) -> Option<PingReply> {
+    let is_reply = match source {
+        IpAddr::V4(_) => IcmpPacket::new(res_buf)?.get_icmp_type() == IcmpTypes::EchoReply,
+        IpAddr::V6(_) => Icmpv6Packet::new(res_buf)?.get_icmpv6_type() == Icmpv6Types::EchoReply
+    };
+    // echo reply of both ip versions has identifier and sequence number at the same position
+    let res_icmp_packet = EchoReplyPacket::new(res_buf)?;
+    if is_reply && res_icmp_packet.get_sequence_number() == sequence {
+        return Some(PingReply {
+            source,
+            // ttl is not given without ip header
+            ttl: None,
+            size: res_buf.len()
+        });
+    }
+    None
+}
+
+/**
+ * Check the icmpv6 response is an echo reply or icmpv6 error of the request
+ */
+fn get_response_v6(res_packet: &Icmpv6Packet, source: IpAddr, identifier: u16, sequence: u16) -> Option<PingResponse> {
+    if res_packet.get_icmpv6_type() != Icmpv6Types::EchoReply {
+        let IpAddr::V6(source) = source else {
+            return None;
+        };
+        return get_icmp_error(IcmpError::from_icmpv6(res_packet.packet(), source)?, identifier, sequence);
+    }
+    let res_icmp_packet = Icmpv6EchoReplyPacket::new(res_packet.packet())?;
+    if res_icmp_packet.get_identifier() == identifier && res_icmp_packet.get_sequence_number() == sequence {
+        return Some(PingResponse::Reply(PingReply {
+            source,
+            // hop limit is not given without ipv6 header
+            ttl: None,
+            size: res_packet.packet().len()
+        }));
+    }
+    None
+}
+
+/**
+ * Create ipv4 packet of icmp echo request
+ */
+pub fn make_echo_request(dest_ip: Ipv4Addr, ttl: u8, identifier: u16, sequence: u16) -> Vec<u8> {
+    let icmp_buf = make_icmp_echo_request(identifier, sequence);
+
+    // Create Ipv4 packet
+    let mut ip_buf: Vec<u8> = vec![0; IPV4_HEADER_SIZE + MAX_PACKET_SIZE];
+    let mut ip_packet = MutableIpv4Packet::new(&mut ip_buf).unwrap();
+    make_ip_packet(&mut ip_packet, dest_ip, ttl);
+    ip_packet.set_payload(&icmp_buf);
+    ip_buf
+}
+
+/**
+ * Create icmp echo request without ip header
+ */
+pub fn make_icmp_echo_request(identifier: u16, sequence: u16) -> Vec<u8> {
+    let mut icmp_buf: Vec<u8> = vec![0; MAX_PACKET_SIZE];
+    let mut icmp_packet = MutableEchoRequestPacket::new(&mut icmp_buf).unwrap();
+    make_icmp_packet(&mut icmp_packet, identifier, sequence);
+    icmp_buf
+}
+
+/**
+ * Create icmpv6 echo request, checksum is filled by kernel
+ */
+pub fn make_echo_request_v6(identifier: u16, sequence: u16) -> Vec<u8> {
+    let mut icmp_buf: Vec<u8> = vec![0; MAX_PACKET_SIZE];
+    let mut icmp_packet = MutableIcmpv6EchoRequestPacket::new(&mut icmp_buf).unwrap();
+    icmp_packet.set_icmpv6_type(Icmpv6Types::EchoRequest);
+    icmp_packet.set_icmpv6_code(Icmpv6Code::new(0));
+    icmp_packet.set_identifier(identifier);
+    icmp_packet.set_sequence_number(sequence);
+    icmp_buf
 }
 
 /**
  * Set param to icmp packet
  */
-fn make_icmp_packet(packet: &mut MutableEchoRequestPacket) {
-    let id = rand::thread_rng().gen();
+pub fn make_icmp_packet(packet: &mut MutableEchoRequestPacket, identifier: u16, sequence: u16) {
     packet.set_icmp_type(IcmpTypes::EchoRequest);
     packet.set_icmp_code(IcmpCode::new(0));
-    packet.set_identifier(id);
-    packet.set_sequence_number(0);
+    packet.set_identifier(identifier);
+    packet.set_sequence_number(sequence);
     let sum = checksum(&IcmpPacket::new(packet.packet()).unwrap());
     packet.set_checksum(sum);
 }
@@ -132,13 +688,154 @@ fn make_icmp_packet(packet: &mut MutableEchoRequestPacket) {
 /**
  * Set param to ip packet
  */
-fn make_ip_packet(packet: &mut MutableIpv4Packet, config: &BaseConfig) {
+pub fn make_ip_packet(packet: &mut MutableIpv4Packet, dest_ip: Ipv4Addr, ttl: u8) {
     let id = rand::thread_rng().gen();
     packet.set_version(4);
     packet.set_header_length(5);
     packet.set_total_length(packet.packet().len() as u16);
     packet.set_identification(id);
-    packet.set_ttl(1);
+    packet.set_ttl(ttl);
     packet.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
-    packet.set_destination(Ipv4Addr::from_str(config.ip.as_str()).unwrap());
-}
\ No newline at end of file
+    packet.set_destination(dest_ip);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn ping_report_display_test() {
+        let mut statistics = PingStatistics { transmitted: 3, ..Default::default() };
+        statistics.add(1.0);
+        statistics.add(3.0);
+        let report = PingReport {
+            destination: IpAddr::from_str("192.0.2.1").unwrap(),
+            mode: PingMode::Datagram,
+            probes: Vec::new(),
+            statistics,
+            time: Duration::from_millis(2003)
+        };
+        let lines: Vec<String> = report.to_string().lines().map(String::from).collect();
+        assert_eq!(lines, vec![
+            "Ping: --- 192.0.2.1 ping statistics, unprivileged icmp datagram socket ---",
+            "Ping: 3 packets transmitted, 2 received, 33.3% packet loss, time 2003ms",
+            "Ping: rtt min/avg/max/mdev = 1.000/2.000/3.000/1.000 ms"
+        ]);
+        assert_eq!(serde_json::to_value(&report).unwrap()["mode"], "datagram");
+        assert_eq!(serde_json::to_value(PingMode::RawV6).unwrap(), "raw_v6");
+    }
+
+    #[test]
+    fn ping_statistics_test() {
+        let mut statistics = PingStatistics { transmitted: 4, ..Default::default() };
+        statistics.add(10.0);
+        statistics.add(20.0);
+        statistics.add(30.0);
+        assert_eq!(statistics.min, 10.0);
+        assert_eq!(statistics.max, 30.0);
+        assert_eq!(statistics.avg(), 20.0);
+        assert!((statistics.mdev() - 8.165).abs() < 0.001);
+        assert_eq!(statistics.loss(), 25.0);
+    }
+
+    #[test]
+    fn ping_statistics_no_reply_test() {
+        let statistics = PingStatistics { transmitted: 3, ..Default::default() };
+        assert_eq!(statistics.avg(), 0.0);
+        assert_eq!(statistics.mdev(), 0.0);
+        assert_eq!(statistics.loss(), 100.0);
+    }
+
+    #[test]
+    fn make_echo_request_test() {
+        let buf = make_echo_request(Ipv4Addr::new(192, 0, 2, 1), 5, 0x1234, 3);
+        let packet = Ipv4Packet::new(&buf).unwrap();
+        assert_eq!(packet.get_ttl(), 5);
+        assert_eq!(packet.get_destination(), Ipv4Addr::new(192, 0, 2, 1));
+        assert_eq!(packet.get_total_length() as usize, IPV4_HEADER_SIZE + MAX_PACKET_SIZE);
+        let icmp_packet = EchoReplyPacket::new(packet.payload()).unwrap();
+        assert_eq!(icmp_packet.get_icmp_type(), IcmpTypes::EchoRequest);
+        assert_eq!(icmp_packet.get_identifier(), 0x1234);
+        assert_eq!(icmp_packet.get_sequence_number(), 3);
+    }
+
+    #[test]
+    fn make_echo_request_v6_test() {
+        let buf = make_echo_request_v6(0x1234, 3);
+        let packet = Icmpv6Packet::new(&buf).unwrap();
+        assert_eq!(packet.get_icmpv6_type(), Icmpv6Types::EchoRequest);
+        let echo_packet = Icmpv6EchoReplyPacket::new(&buf).unwrap();
+        assert_eq!(echo_packet.get_identifier(), 0x1234);
+        assert_eq!(echo_packet.get_sequence_number(), 3);
+    }
+
+    #[test]
+    fn get_response_v6_test() {
+        let mut buf = make_echo_request_v6(0x1234, 3);
+        let source = IpAddr::from_str("2001:db8::1").unwrap();
+        // echo request is not a reply
+        assert!(get_response_v6(&Icmpv6Packet::new(&buf).unwrap(), source, 0x1234, 3).is_none());
+
+        buf[0] = Icmpv6Types::EchoReply.0;
+        let packet = Icmpv6Packet::new(&buf).unwrap();
+        match get_response_v6(&packet, source, 0x1234, 3) {
+            Some(PingResponse::Reply(reply)) => {
+                assert_eq!(reply.source, source);
+                assert_eq!(reply.size, MAX_PACKET_SIZE);
+            }
+            _ => panic!("expected echo reply")
+        }
+        assert!(get_response_v6(&packet, source, 0x1234, 4).is_none());
+    }
+
+    #[test]
+    fn get_response_unreachable_test() {
+        let router = Ipv4Addr::new(10, 0, 0, 1);
+        let probe = make_echo_request(Ipv4Addr::new(192, 0, 2, 1), 64, 0x1234, 3);
+        // host unreachable carries original ip header and 8 bytes of echo request
+        let mut icmp_buf = vec![IcmpTypes::DestinationUnreachable.0, 1, 0, 0, 0, 0, 0, 0];
+        icmp_buf.extend_from_slice(&probe[..IPV4_HEADER_SIZE + 8]);
+        let mut ip_buf: Vec<u8> = vec![0; IPV4_HEADER_SIZE + icmp_buf.len()];
+        let mut ip_packet = MutableIpv4Packet::new(&mut ip_buf).unwrap();
+        make_ip_packet(&mut ip_packet, Ipv4Addr::new(192, 0, 2, 2), 64);
+        ip_packet.set_source(router);
+        ip_packet.set_payload(&icmp_buf);
+
+        let res_packet = Ipv4Packet::new(&ip_buf).unwrap();
+        match get_response(&res_packet, 0x1234, 3) {
+            Some(PingResponse::Error(icmp_error)) => {
+                assert_eq!(icmp_error.kind, IcmpErrorKind::HostUnreachable);
+                assert_eq!(icmp_error.source, IpAddr::V4(router));
+            }
+            _ => panic!("expected icmp error")
+        }
+        assert!(get_response(&res_packet, 0x1234, 4).is_none());
+    }
+
+    #[test]
+    fn get_datagram_echo_reply_test() {
+        let source = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let mut buf = make_icmp_echo_request(0x1234, 3);
+        assert!(get_datagram_echo_reply(&buf, source, 3).is_none());
+
+        // identifier is ignored because kernel rewrites it
+        buf[0] = IcmpTypes::EchoReply.0;
+        let reply = get_datagram_echo_reply(&buf, source, 3).unwrap();
+        assert_eq!(reply.source, source);
+        assert!(reply.ttl.is_none());
+        assert!(get_datagram_echo_reply(&buf, source, 4).is_none());
+    }
+
+    #[test]
+    fn make_icmp_packet_test() {
+        let mut buf: Vec<u8> = vec![0; MAX_PACKET_SIZE];
+        let mut packet = MutableEchoRequestPacket::new(&mut buf).unwrap();
+        make_icmp_packet(&mut packet, 0x1234, 7);
+        assert_eq!(packet.get_identifier(), 0x1234);
+        assert_eq!(packet.get_sequence_number(), 7);
+        let sum = checksum(&IcmpPacket::new(packet.packet()).unwrap());
+        assert_eq!(packet.get_checksum(), sum);
+    }
+}
@@ -0,0 +1,240 @@
+use std::{fmt, io, net::{IpAddr, Ipv4Addr}, time::{Duration, Instant}};
+use log::{debug, info};
+
+use pnet::{packet::{Packet, ip::IpNextHeaderProtocols, ipv4::{Ipv4Flags, Ipv4Packet, MutableIpv4Packet}, icmp::{IcmpTypes, echo_reply::EchoReplyPacket, echo_request::MutableEchoRequestPacket}}, transport::{transport_channel, TransportChannelType, ipv4_packet_iter, Ipv4TransportChannelIterator}};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, types::PmtuConfig};
+
+use super::{dns::resolve_target_v4, ping::{make_icmp_packet, make_ip_packet}, types::icmp_error::{IcmpError, IcmpErrorKind}};
+
+const PMTU_TIME_OUT: u64 = 2000;
+const PMTU_TTL: u8 = 64;
+const PMTU_RETRY: u8 = 2;
+const IPV4_HEADER_SIZE: usize = 20;
+const ICMP_HEADER_SIZE: usize = 8;
+
+/**
+ * Result of a DF-flagged probe
+ */
+#[derive(Debug, PartialEq)]
+enum ProbeResult {
+    // Echo reply is received, the size passes the path
+    Fit,
+    // Router answered Fragmentation Needed with next-hop mtu (0 if not given)
+    FragmentationNeeded(Ipv4Addr, u16),
+    // Local interface mtu is smaller than the size
+    LocalTooBig,
+    // Probe is lost, possibly dropped by mtu black hole
+    NoResponse
+}
+
+/**
+ * Report of path mtu discovery
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct PmtuReport {
+    pub destination: Ipv4Addr,
+    pub mtu: u16,
+    // larger probes were dropped without Fragmentation Needed
+    pub black_hole: bool
+}
+
+impl fmt::Display for PmtuReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pmtu: path mtu to {} is {} bytes", self.destination, self.mtu)?;
+        if self.black_hole {
+            write!(f, "\nPmtu: larger probes were silently dropped without Fragmentation Needed, possible mtu black hole")?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Discover path mtu by binary search of DF-flagged icmp echo size
+ */
+pub fn pmtu(config: PmtuConfig) -> Result<PmtuReport> {
+    let dest_ip = resolve_target_v4(&config.ip, &config.resolve)?;
+    if (config.min_mtu as usize) < IPV4_HEADER_SIZE + ICMP_HEADER_SIZE || config.min_mtu > config.max_mtu {
+        return Err(NetDoctorError::InvalidArgument(format!("search range {} - {}", config.min_mtu, config.max_mtu)));
+    }
+    let identifier: u16 = rand::thread_rng().gen();
+
+    let (mut tx, mut rx) = transport_channel(4096,
+             TransportChannelType::Layer3(IpNextHeaderProtocols::Icmp))?;
+    let mut rx = ipv4_packet_iter(&mut rx);
+
+    info!("Pmtu: to {}, search between {} and {} bytes", dest_ip, config.min_mtu, config.max_mtu);
+
+    let mut sequence: u16 = 0;
+    let mut probe = |size: u16| -> io::Result<ProbeResult> {
+        for _ in 0..PMTU_RETRY {
+            sequence = sequence.wrapping_add(1);
+            let ip_buf = make_probe(dest_ip, identifier, sequence, size as usize);
+            let send_packet = Ipv4Packet::new(&ip_buf).unwrap();
+            match tx.send_to(send_packet, IpAddr::V4(dest_ip)) {
+                Ok(_) => {}
+                // "Message too long", the size is over the mtu of the local interface
+                Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => {
+                    return Ok(ProbeResult::LocalTooBig);
+                }
+                Err(e) => return Err(e)
+            }
+            let result = wait_probe_reply(&mut rx, identifier, sequence)?;
+            if result != ProbeResult::NoResponse {
+                return Ok(result);
+            }
+        }
+        Ok(ProbeResult::NoResponse)
+    };
+
+    // smallest size has to pass at first
+    match probe(config.min_mtu)? {
+        ProbeResult::Fit => {}
+        ProbeResult::NoResponse => {
+            return Err(NetDoctorError::Timeout(format!("echo reply of {} bytes probe from {}", config.min_mtu, dest_ip)));
+        }
+        result => {
+            return Err(NetDoctorError::InvalidArgument(format!("{} bytes probe does not pass, {:?}", config.min_mtu, result)));
+        }
+    }
+
+    let mut low = config.min_mtu;
+    let mut high = config.max_mtu;
+    let mut is_black_hole = false;
+    while low < high {
+        let size = low + (high - low).div_ceil(2);
+        match probe(size)? {
+            ProbeResult::Fit => {
+                info!("Pmtu: {} bytes passed", size);
+                low = size;
+            }
+            ProbeResult::FragmentationNeeded(router, mtu) => {
+                info!("Pmtu: {} bytes needs fragmentation at {}, next-hop mtu={}", size, router, mtu);
+                // trust next-hop mtu when it is in the search range
+                if mtu > low && mtu < size {
+                    high = mtu;
+                    if probe(mtu)? == ProbeResult::Fit {
+                        low = mtu;
+                    } else {
+                        high = mtu - 1;
+                    }
+                } else {
+                    high = size - 1;
+                }
+            }
+            ProbeResult::LocalTooBig => {
+                info!("Pmtu: {} bytes exceeds local interface mtu", size);
+                high = size - 1;
+            }
+            ProbeResult::NoResponse => {
+                info!("Pmtu: {} bytes has no response", size);
+                is_black_hole = true;
+                high = size - 1;
+            }
+        }
+    }
+
+    Ok(PmtuReport {
+        destination: dest_ip,
+        mtu: low,
+        black_hole: is_black_hole && low < config.max_mtu
+    })
+}
+
+/**
+ * Create DF-flagged ipv4 packet of icmp echo request, size is total length of ip packet
+ */
+fn make_probe(dest_ip: Ipv4Addr, identifier: u16, sequence: u16, size: usize) -> Vec<u8> {
+    let mut icmp_buf: Vec<u8> = vec![0; size - IPV4_HEADER_SIZE];
+    let mut icmp_packet = MutableEchoRequestPacket::new(&mut icmp_buf).unwrap();
+    make_icmp_packet(&mut icmp_packet, identifier, sequence);
+
+    let mut ip_buf: Vec<u8> = vec![0; size];
+    let mut ip_packet = MutableIpv4Packet::new(&mut ip_buf).unwrap();
+    make_ip_packet(&mut ip_packet, dest_ip, PMTU_TTL);
+    ip_packet.set_flags(Ipv4Flags::DontFragment);
+    ip_packet.set_payload(&icmp_buf);
+    ip_buf
+}
+
+/**
+ * Wait echo reply or Fragmentation Needed for the probe
+ */
+fn wait_probe_reply(rx: &mut Ipv4TransportChannelIterator, identifier: u16, sequence: u16) -> io::Result<ProbeResult> {
+    let deadline = Instant::now() + Duration::from_millis(PMTU_TIME_OUT);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(ProbeResult::NoResponse);
+        }
+
+        match rx.next_with_timeout(remaining)? {
+            Some((res_packet, _)) => {
+                debug!("Pmtu: res_packet {:?}", &res_packet);
+                if let Some(result) = get_probe_result(&res_packet, identifier, sequence) {
+                    return Ok(result);
+                }
+            }
+            None => {
+                return Ok(ProbeResult::NoResponse);
+            }
+        }
+    }
+}
+
+/**
+ * Check the response is a reply of the probe
+ */
+fn get_probe_result(res_packet: &Ipv4Packet, identifier: u16, sequence: u16) -> Option<ProbeResult> {
+    if res_packet.get_next_level_protocol() != IpNextHeaderProtocols::Icmp {
+        return None;
+    }
+    let echo_packet = EchoReplyPacket::new(res_packet.payload())?;
+    if echo_packet.get_icmp_type() == IcmpTypes::EchoReply {
+        if echo_packet.get_identifier() == identifier && echo_packet.get_sequence_number() == sequence {
+            return Some(ProbeResult::Fit);
+        }
+        return None;
+    }
+    let icmp_error = IcmpError::from_ipv4(res_packet)?;
+    if !icmp_error.is_echo_of(identifier, sequence) {
+        return None;
+    }
+    match icmp_error.kind {
+        IcmpErrorKind::FragmentationNeeded(mtu) => Some(ProbeResult::FragmentationNeeded(res_packet.get_source(), mtu)),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::types::icmp_error::make_icmp_error;
+
+    use super::*;
+
+    #[test]
+    fn make_probe_test() {
+        let buf = make_probe(Ipv4Addr::new(192, 0, 2, 1), 1, 2, 1400);
+        let packet = Ipv4Packet::new(&buf).unwrap();
+        assert_eq!(buf.len(), 1400);
+        assert_eq!(packet.get_total_length(), 1400);
+        assert_eq!(packet.get_flags(), Ipv4Flags::DontFragment);
+        assert_eq!(packet.payload().len(), 1400 - IPV4_HEADER_SIZE);
+        assert!(packet.payload().len() > ICMP_HEADER_SIZE);
+    }
+
+    #[test]
+    fn get_probe_result_fragmentation_needed_test() {
+        let router = Ipv4Addr::new(10, 0, 0, 1);
+        let probe = make_probe(Ipv4Addr::new(192, 0, 2, 1), 7, 3, 1500);
+
+        // original ip header and first 8 bytes of original icmp
+        let original = &probe[..IPV4_HEADER_SIZE + ICMP_HEADER_SIZE];
+        let ip_buf = make_icmp_error(router, IcmpTypes::DestinationUnreachable.0, 4, 1400, original);
+        let res_packet = Ipv4Packet::new(&ip_buf).unwrap();
+        assert_eq!(get_probe_result(&res_packet, 7, 3), Some(ProbeResult::FragmentationNeeded(router, 1400)));
+        assert_eq!(get_probe_result(&res_packet, 7, 4), None);
+    }
+}
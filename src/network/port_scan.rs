@@ -1,7 +1,220 @@
+use std::{fmt, io::{self, ErrorKind}, net::{IpAddr, SocketAddr, TcpStream}, time::{Duration, Instant}};
+use log::{debug, info};
+
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, types::{PortScanConfig, ScanMode}, utils::serde_utils};
+
+use super::{banner::{grab_banners, ServiceBanner}, dns::resolve_target, syn_scan::tcp_syn_scan, udp_scan::udp_scan};
+
+/**
+ * State of scanned port
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortState {
+    // Connection is established
+    Open,
+    // Connection is refused by RST
+    Closed,
+    // No response, probably dropped by firewall
+    Filtered,
+    // No udp response nor icmp error, open port may ignore the payload
+    #[serde(rename = "open|filtered")]
+    OpenFiltered
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortState::Open => write!(f, "open"),
+            PortState::Closed => write!(f, "closed"),
+            PortState::Filtered => write!(f, "filtered"),
+            PortState::OpenFiltered => write!(f, "open|filtered")
+        }
+    }
+}
+
+/**
+ * Result of a scanned port
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct PortResult {
+    pub port: u16,
+    pub state: PortState,
+    // service of open port when banners are grabbed
+    pub banner: Option<ServiceBanner>
+}
+
+/**
+ * Report of port scan
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct PortScanReport {
+    pub destination: IpAddr,
+    pub mode: ScanMode,
+    // results ordered by port
+    pub ports: Vec<PortResult>,
+    #[serde(rename = "time_ms", serialize_with = "serde_utils::millis")]
+    pub time: Duration
+}
+
+impl PortScanReport {
+    pub fn count(&self, state: PortState) -> usize {
+        self.ports.iter().filter(|p| p.state == state).count()
+    }
+}
+
+impl fmt::Display for PortScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let protocol = if self.mode == ScanMode::Udp { "udp" } else { "tcp" };
+        for result in self.ports.iter().filter(|p| p.state == PortState::Open) {
+            write!(f, "PortScan: {:>5}/{}  {}", result.port, protocol, result.state)?;
+            if let Some(banner) = &result.banner {
+                write!(f, "  {}  {}", banner.service, banner.version.as_deref().unwrap_or(&banner.banner))?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "PortScan: {} open, {} closed, {} filtered, {} open|filtered, time {}ms",
+            self.count(PortState::Open), self.count(PortState::Closed), self.count(PortState::Filtered),
+            self.count(PortState::OpenFiltered), self.time.as_millis())
+    }
+}
 
 /**
  * scan target ports
  */
-pub fn port_scan() {
+pub fn port_scan(config: PortScanConfig) -> Result<PortScanReport> {
+    let dest_ip = resolve_target(&config.ip, &config.resolve)?;
+    let ports = parse_ports(&config.ports).ok_or_else(|| NetDoctorError::InvalidArgument(
+        format!("port specification {}, expected like 22,80,8000-8100", config.ports)))?;
+    if config.concurrency == 0 {
+        return Err(NetDoctorError::InvalidArgument("concurrency has to be larger than 0".to_string()));
+    }
+
+    info!("PortScan: {} ports of {}, {:?} scan", ports.len(), dest_ip, config.mode);
+    let start = Instant::now();
+    let timeout = Duration::from_millis(config.timeout);
+    let results = match config.mode {
+        ScanMode::Connect => tcp_connect_scan(dest_ip, &ports, timeout, config.concurrency)?,
+        ScanMode::Syn => tcp_syn_scan(dest_ip, &ports, timeout)?,
+        ScanMode::Udp => udp_scan(dest_ip, &ports, timeout)?
+    };
+
+    let banners = if config.banners && config.mode != ScanMode::Udp {
+        let open_ports: Vec<u16> = results.iter().filter(|(_, s)| *s == PortState::Open).map(|(p, _)| *p).collect();
+        grab_banners(dest_ip, &open_ports, timeout, config.concurrency)?
+    } else {
+        Vec::new()
+    };
+
+    for (port, state) in &results {
+        debug!("PortScan: {} {}", port, state);
+    }
+    let ports = results.into_iter()
+        .map(|(port, state)| PortResult {
+            port,
+            state,
+            banner: banners.iter().find(|b| b.port == port).cloned()
+        })
+        .collect();
+    Ok(PortScanReport {
+        destination: dest_ip,
+        mode: config.mode,
+        ports,
+        time: start.elapsed()
+    })
+}
+
+/**
+ * Connect to ports concurrently and classify them, results are ordered by port
+ */
+pub fn tcp_connect_scan(dest_ip: IpAddr, ports: &[u16], timeout: Duration, concurrency: usize) -> io::Result<Vec<(u16, PortState)>> {
+    // connect blocks the thread, so pool size is the number of connections in flight
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(concurrency).build().map_err(io::Error::other)?;
+    Ok(pool.install(|| {
+        ports.par_iter()
+            .map(|port| (*port, connect_port(SocketAddr::new(dest_ip, *port), timeout)))
+            .collect()
+    }))
+}
+
+/**
+ * Classify port by the result of tcp connect
+ */
+fn connect_port(addr: SocketAddr, timeout: Duration) -> PortState {
+    match TcpStream::connect_timeout(&addr, timeout) {
+        Ok(_) => PortState::Open,
+        Err(e) if e.kind() == ErrorKind::ConnectionRefused => PortState::Closed,
+        Err(e) => {
+            // timeout, or icmp unreachable from firewall
+            debug!("PortScan: {} {:?}", addr, e);
+            PortState::Filtered
+        }
+    }
+}
+
+/**
+ * Parse port specification like 22,80,8000-8100 to sorted unique ports
+ */
+pub fn parse_ports(spec: &str) -> Option<Vec<u16>> {
+    let mut ports: Vec<u16> = Vec::new();
+    for part in spec.split(',') {
+        let part = part.trim();
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let first: u16 = first.trim().parse().ok()?;
+                let last: u16 = last.trim().parse().ok()?;
+                if first == 0 || first > last {
+                    return None;
+                }
+                ports.extend(first..=last);
+            }
+            None => {
+                let port: u16 = part.parse().ok()?;
+                if port == 0 {
+                    return None;
+                }
+                ports.push(port);
+            }
+        }
+    }
+    ports.sort_unstable();
+    ports.dedup();
+    Some(ports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    use super::*;
+
+    #[test]
+    fn parse_ports_test() {
+        assert_eq!(parse_ports("22,80,8000-8003"), Some(vec![22, 80, 8000, 8001, 8002, 8003]));
+        assert_eq!(parse_ports("443, 80,80,1-2"), Some(vec![1, 2, 80, 443]));
+        assert_eq!(parse_ports("65535"), Some(vec![65535]));
+        assert_eq!(parse_ports("0"), None);
+        assert_eq!(parse_ports("100-10"), None);
+        assert_eq!(parse_ports("65536"), None);
+        assert_eq!(parse_ports("22,"), None);
+        assert_eq!(parse_ports("ssh"), None);
+    }
+
+    #[test]
+    fn tcp_connect_scan_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        // port of dropped listener is closed
+        let closed_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
 
-}
\ No newline at end of file
+        let mut ports = vec![open_port, closed_port];
+        ports.sort_unstable();
+        let results = tcp_connect_scan(IpAddr::V4(Ipv4Addr::LOCALHOST), &ports, Duration::from_millis(1000), 2).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.contains(&(open_port, PortState::Open)));
+        assert!(results.contains(&(closed_port, PortState::Closed)));
+    }
+}
@@ -0,0 +1,212 @@
+use std::{fs, net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV6}, str::FromStr, time::Duration};
+use log::debug;
+
+use pnet::datalink::interfaces;
+
+use crate::error::Result;
+
+pub const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+pub const DNS_PORT: u16 = 53;
+// defaults and limits of glibc resolv.conf(5)
+const DEFAULT_NDOTS: u8 = 1;
+const DEFAULT_TIMEOUT: u64 = 5;
+const DEFAULT_ATTEMPTS: u8 = 2;
+const MAX_NAMESERVERS: usize = 3;
+const MAX_NDOTS: u8 = 15;
+const MAX_TIMEOUT: u64 = 30;
+const MAX_ATTEMPTS: u8 = 5;
+
+/**
+ * Resolver configuration of the host
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvConf {
+    pub nameservers: Vec<SocketAddr>,
+    // search domains without trailing dot
+    pub search: Vec<String>,
+    // names with fewer dots than ndots are tried with search domains first
+    pub ndots: u8,
+    // seconds to wait a response of a nameserver
+    pub timeout: u64,
+    // rounds of querying all nameservers
+    pub attempts: u8
+}
+
+impl Default for ResolvConf {
+    fn default() -> Self {
+        ResolvConf {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: DEFAULT_NDOTS,
+            timeout: DEFAULT_TIMEOUT,
+            attempts: DEFAULT_ATTEMPTS
+        }
+    }
+}
+
+impl ResolvConf {
+    /**
+     * Read resolv.conf, missing file is the same as empty file
+     */
+    pub fn load(path: &str) -> Result<ResolvConf> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(ResolvConf::parse(&text)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("Resolve: {} is not found", path);
+                Ok(ResolvConf::parse(""))
+            }
+            Err(e) => Err(e.into())
+        }
+    }
+
+    /**
+     * Parse nameserver, search, domain and options lines.
+     * Local nameserver is used when nameserver is not given.
+     */
+    pub fn parse(text: &str) -> ResolvConf {
+        let mut conf = ResolvConf::default();
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("nameserver") => {
+                    match words.next().and_then(parse_server) {
+                        Some(server) if conf.nameservers.len() < MAX_NAMESERVERS => conf.nameservers.push(server),
+                        Some(server) => debug!("Resolve: nameserver {} exceeds {} servers", server, MAX_NAMESERVERS),
+                        None => debug!("Resolve: invalid line {}", line)
+                    }
+                }
+                // the last one of domain and search wins
+                Some("domain") | Some("search") => {
+                    conf.search = words.map(|domain| domain.trim_end_matches('.').to_string()).collect();
+                }
+                Some("options") => {
+                    for option in words {
+                        let (name, value) = option.split_once(':').unwrap_or((option, ""));
+                        match name {
+                            "ndots" => conf.ndots = value.parse().map(|n: u8| n.min(MAX_NDOTS)).unwrap_or(conf.ndots),
+                            "timeout" => conf.timeout = value.parse().map(|n: u64| n.clamp(1, MAX_TIMEOUT)).unwrap_or(conf.timeout),
+                            "attempts" => conf.attempts = value.parse().map(|n: u8| n.clamp(1, MAX_ATTEMPTS)).unwrap_or(conf.attempts),
+                            _ => debug!("Resolve: option {} is ignored", option)
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if conf.nameservers.is_empty() {
+            conf.nameservers.push(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DNS_PORT));
+        }
+        conf
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    /**
+     * Names to query in order, the same order as glibc.
+     * Absolute name (trailing dot) is not expanded by search domains.
+     */
+    pub fn candidates(&self, name: &str) -> Vec<String> {
+        if let Some(name) = name.strip_suffix('.') {
+            return vec![name.to_string()];
+        }
+        let searched = self.search.iter().map(|domain| format!("{}.{}", name, domain));
+        let dots = name.matches('.').count();
+        if dots >= self.ndots as usize {
+            std::iter::once(name.to_string()).chain(searched).collect()
+        } else {
+            searched.chain(std::iter::once(name.to_string())).collect()
+        }
+    }
+}
+
+/**
+ * Parse server address like 192.0.2.53, 192.0.2.53:5353, 2001:db8::53, [2001:db8::53]:5353 or fe80::1%eth0.
+ * Port is 53 if it is not given.
+ */
+pub fn parse_server(server: &str) -> Option<SocketAddr> {
+    parse_server_with_port(server, DNS_PORT)
+}
+
+/**
+ * Parse server address, port is the default port if it is not given
+ */
+pub fn parse_server_with_port(server: &str, port: u16) -> Option<SocketAddr> {
+    if let Ok(addr) = SocketAddr::from_str(server) {
+        return Some(addr);
+    }
+    // bracket is needed to give port to ipv6 address
+    let server = server.strip_prefix('[').and_then(|s| s.strip_suffix(']')).unwrap_or(server);
+    let (ip, scope) = match server.split_once('%') {
+        Some((ip, scope)) => (ip, Some(scope)),
+        None => (server, None)
+    };
+    match (IpAddr::from_str(ip).ok()?, scope) {
+        (ip, None) => Some(SocketAddr::new(ip, port)),
+        (IpAddr::V6(ip), Some(scope)) => {
+            // scope is interface index or name
+            let scope_id = scope.parse().ok().or_else(|| {
+                interfaces().into_iter().find(|nic| nic.name == scope).map(|nic| nic.index)
+            })?;
+            Some(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope_id)))
+        }
+        (IpAddr::V4(_), Some(_)) => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_server_test() {
+        assert_eq!(parse_server("192.0.2.53"), Some(SocketAddr::from(([192, 0, 2, 53], 53))));
+        assert_eq!(parse_server("192.0.2.53:5353"), Some(SocketAddr::from(([192, 0, 2, 53], 5353))));
+        assert_eq!(parse_server("2001:db8::53").map(|s| s.to_string()).as_deref(), Some("[2001:db8::53]:53"));
+        assert_eq!(parse_server("[2001:db8::53]:5353").map(|s| s.to_string()).as_deref(), Some("[2001:db8::53]:5353"));
+        assert_eq!(parse_server("[2001:db8::53]").map(|s| s.port()), Some(53));
+        match parse_server("fe80::1%2") {
+            Some(SocketAddr::V6(addr)) => assert_eq!((addr.scope_id(), addr.port()), (2, 53)),
+            other => panic!("unexpected {:?}", other)
+        }
+        assert_eq!(parse_server("192.0.2.53%2"), None);
+        assert_eq!(parse_server("dns.example"), None);
+        assert_eq!(parse_server("192.0.2.53:99999"), None);
+        assert_eq!(parse_server_with_port("192.0.2.53", 853), Some(SocketAddr::from(([192, 0, 2, 53], 853))));
+        assert_eq!(parse_server_with_port("[2001:db8::53]", 853).map(|s| s.port()), Some(853));
+        assert_eq!(parse_server_with_port("192.0.2.53:8853", 853).map(|s| s.port()), Some(8853));
+    }
+
+    #[test]
+    fn parse_resolv_conf_test() {
+        let conf = ResolvConf::parse("\
+# comment
+nameserver 192.0.2.53
+nameserver 2001:db8::53
+nameserver not-address
+domain ignored.example
+search corp.example. example.com
+options ndots:2 timeout:1 attempts:9 rotate edns0
+");
+        assert_eq!(conf.nameservers.len(), 2);
+        assert_eq!(conf.nameservers[1].ip(), IpAddr::from_str("2001:db8::53").unwrap());
+        assert_eq!(conf.search, vec!["corp.example", "example.com"]);
+        assert_eq!((conf.ndots, conf.timeout, conf.attempts), (2, 1, MAX_ATTEMPTS));
+
+        let conf = ResolvConf::parse("");
+        assert_eq!(conf.nameservers, vec![SocketAddr::from(([127, 0, 0, 1], 53))]);
+        assert_eq!((conf.ndots, conf.timeout, conf.attempts), (1, 5, 2));
+    }
+
+    #[test]
+    fn candidates_test() {
+        let conf = ResolvConf { search: vec!["corp.example".to_string(), "example.com".to_string()], ..Default::default() };
+        assert_eq!(conf.candidates("www"), vec!["www.corp.example", "www.example.com", "www"]);
+        assert_eq!(conf.candidates("www.example.org"), vec!["www.example.org", "www.example.org.corp.example", "www.example.org.example.com"]);
+        assert_eq!(conf.candidates("www.example.org."), vec!["www.example.org"]);
+
+        let conf = ResolvConf { ndots: 3, ..conf };
+        assert_eq!(conf.candidates("a.b")[0], "a.b.corp.example");
+    }
+}
@@ -0,0 +1,273 @@
+use std::{fmt, io, net::{IpAddr, Ipv4Addr}, str::FromStr, sync::{Mutex, atomic::{AtomicBool, AtomicU32, Ordering}}, thread, time::{Duration, Instant}};
+use log::{debug, info};
+
+use pnet::{packet::{Packet, ip::IpNextHeaderProtocols, ipv4::Ipv4Packet, icmp::{IcmpTypes, echo_reply::EchoReplyPacket}}, transport::{transport_channel, TransportChannelType, ipv4_packet_iter}};
+use rand::Rng;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, types::SweepConfig, utils::serde_utils};
+
+use super::{dns::annotate, ping::make_echo_request};
+
+const SWEEP_TTL: u8 = 64;
+// poll interval of receive channel to check the end of sending
+const SWEEP_POLL: u64 = 100;
+// sequence number is index of address
+const MAX_SWEEP_HOSTS: usize = u16::MAX as usize + 1;
+
+/**
+ * Alive host of sweep
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepHost {
+    pub address: Ipv4Addr,
+    // round trip time (ms)
+    pub rtt: f64,
+    // name by reverse dns, None unless names are looked up
+    pub name: Option<String>
+}
+
+/**
+ * Report of ping sweep
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct SweepReport {
+    pub target: String,
+    // number of swept addresses
+    pub total: usize,
+    // alive hosts ordered by address
+    pub hosts: Vec<SweepHost>,
+    #[serde(rename = "time_ms", serialize_with = "serde_utils::millis")]
+    pub time: Duration
+}
+
+impl fmt::Display for SweepReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for host in &self.hosts {
+            write!(f, "Sweep: {:<15}  {:.3} ms", host.address, host.rtt)?;
+            if let Some(name) = &host.name {
+                write!(f, "  {}", name)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "Sweep: {} of {} hosts are alive, time {}ms", self.hosts.len(), self.total, self.time.as_millis())
+    }
+}
+
+/**
+ * Send echo requests to all addresses of subnet and report alive hosts
+ */
+pub fn sweep(config: SweepConfig) -> Result<SweepReport> {
+    let targets: Vec<Ipv4Addr> = match parse_targets(&config.target) {
+        Some((first, last)) if ((u32::from(last) - u32::from(first)) as usize) < MAX_SWEEP_HOSTS => {
+            (u32::from(first)..=u32::from(last)).map(Ipv4Addr::from).collect()
+        }
+        Some((first, last)) => {
+            return Err(NetDoctorError::InvalidArgument(
+                format!("{} - {} has too many addresses, max {}", first, last, MAX_SWEEP_HOSTS)));
+        }
+        None => {
+            return Err(NetDoctorError::InvalidArgument(
+                format!("target {}, expected cidr (192.0.2.0/24) or range (192.0.2.1-192.0.2.50)", config.target)));
+        }
+    };
+    if config.rate == 0 {
+        return Err(NetDoctorError::InvalidArgument("rate has to be larger than 0".to_string()));
+    }
+    let identifier: u16 = rand::thread_rng().gen();
+
+    let (tx, mut rx) = transport_channel(4096,
+             TransportChannelType::Layer3(IpNextHeaderProtocols::Icmp))?;
+    // each worker sends by its own socket, replies are received by the first one
+    let workers = rayon::current_num_threads().min(targets.len());
+    let mut senders = vec![tx];
+    for _ in 1..workers {
+        senders.push(transport_channel(4096, TransportChannelType::Layer3(IpNextHeaderProtocols::Icmp))?.0);
+    }
+
+    info!("Sweep: {} addresses of {}, {} packets/s", targets.len(), config.target, config.rate);
+
+    let start = Instant::now();
+    let send_times: Vec<Mutex<Option<Instant>>> = targets.iter().map(|_| Mutex::new(None)).collect();
+    let is_sent = AtomicBool::new(false);
+
+    let rtts = thread::scope(|s| {
+        let receiver = s.spawn(|| -> io::Result<Vec<Option<f64>>> {
+            let mut rx = ipv4_packet_iter(&mut rx);
+            let mut rtts: Vec<Option<f64>> = vec![None; targets.len()];
+            let mut deadline: Option<Instant> = None;
+            loop {
+                if deadline.is_none() && is_sent.load(Ordering::Acquire) {
+                    deadline = Some(Instant::now() + Duration::from_millis(config.timeout));
+                }
+                let mut wait = Duration::from_millis(SWEEP_POLL);
+                if let Some(deadline) = deadline {
+                    wait = deadline.saturating_duration_since(Instant::now());
+                    if wait.is_zero() {
+                        return Ok(rtts);
+                    }
+                }
+
+                if let Some((res_packet, _)) = rx.next_with_timeout(wait)? {
+                    let receive_time = Instant::now();
+                    let Some(index) = get_sweep_reply(&res_packet, &targets, identifier) else {
+                        continue;
+                    };
+                    let send_time = *send_times[index].lock().unwrap();
+                    if let (Some(send_time), None) = (send_time, rtts[index]) {
+                        rtts[index] = Some((receive_time - send_time).as_secs_f64() * 1000.0);
+                    }
+                }
+            }
+        });
+
+        // workers send concurrently, each request takes the next slot of the rate and waits it without a lock
+        let interval = Duration::from_secs_f64(1.0 / config.rate as f64);
+        let send_start = Instant::now();
+        let next_slot = AtomicU32::new(0);
+        let chunk_size = targets.len().div_ceil(senders.len());
+        targets.par_chunks(chunk_size).zip(senders.par_iter_mut()).enumerate().for_each(|(chunk, (chunk_targets, tx))| {
+            for (offset, dest_ip) in chunk_targets.iter().enumerate() {
+                let index = chunk * chunk_size + offset;
+                let slot = send_start + interval * next_slot.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(slot.saturating_duration_since(Instant::now()));
+
+                let ip_buf = make_echo_request(*dest_ip, SWEEP_TTL, identifier, index as u16);
+                let send_packet = Ipv4Packet::new(&ip_buf).unwrap();
+                *send_times[index].lock().unwrap() = Some(Instant::now());
+                if let Err(e) = tx.send_to(send_packet, IpAddr::V4(*dest_ip)) {
+                    debug!("Sweep: failed send to {}, {:?}", dest_ip, e);
+                }
+            }
+        });
+        is_sent.store(true, Ordering::Release);
+
+        receiver.join().unwrap()
+    })?;
+
+    let time = start.elapsed();
+
+    let mut hosts: Vec<SweepHost> = targets.iter().zip(rtts)
+        .filter_map(|(address, rtt)| Some(SweepHost { address: *address, rtt: rtt?, name: None }))
+        .collect();
+    if config.rdns {
+        info!("Sweep: looking up names of {} hosts", hosts.len());
+        hosts.par_iter_mut().for_each(|host| host.name = annotate(&IpAddr::V4(host.address)));
+    }
+    Ok(SweepReport {
+        target: config.target,
+        total: targets.len(),
+        hosts,
+        time
+    })
+}
+
+/**
+ * Find index of target answered the echo request
+ */
+fn get_sweep_reply(res_packet: &Ipv4Packet, targets: &[Ipv4Addr], identifier: u16) -> Option<usize> {
+    if res_packet.get_next_level_protocol() != IpNextHeaderProtocols::Icmp {
+        return None;
+    }
+    let echo_packet = EchoReplyPacket::new(res_packet.payload())?;
+    if echo_packet.get_icmp_type() != IcmpTypes::EchoReply || echo_packet.get_identifier() != identifier {
+        return None;
+    }
+    let index = echo_packet.get_sequence_number() as usize;
+    // reply has to come from the address of the sequence
+    if targets.get(index) != Some(&res_packet.get_source()) {
+        return None;
+    }
+    Some(index)
+}
+
+/**
+ * Parse cidr (192.0.2.0/24), range (192.0.2.1-192.0.2.50 or 192.0.2.1-50) or single address
+ * to first and last address. Network and broadcast addresses are excluded from cidr.
+ */
+pub fn parse_targets(target: &str) -> Option<(Ipv4Addr, Ipv4Addr)> {
+    if let Some((addr, prefix)) = target.split_once('/') {
+        let addr = u32::from(Ipv4Addr::from_str(addr).ok()?);
+        let prefix: u32 = prefix.parse().ok()?;
+        if prefix > 32 {
+            return None;
+        }
+        let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+        let network = addr & mask;
+        let broadcast = network | !mask;
+        // /31 and /32 have no network and broadcast address (RFC 3021)
+        if prefix >= 31 {
+            return Some((Ipv4Addr::from(network), Ipv4Addr::from(broadcast)));
+        }
+        return Some((Ipv4Addr::from(network + 1), Ipv4Addr::from(broadcast - 1)));
+    }
+
+    if let Some((first, last)) = target.split_once('-') {
+        let first = Ipv4Addr::from_str(first).ok()?;
+        let last = match Ipv4Addr::from_str(last) {
+            Ok(last) => last,
+            Err(_) => {
+                // only last octet is given
+                let octets = first.octets();
+                Ipv4Addr::new(octets[0], octets[1], octets[2], last.parse().ok()?)
+            }
+        };
+        if first > last {
+            return None;
+        }
+        return Some((first, last));
+    }
+
+    let addr = Ipv4Addr::from_str(target).ok()?;
+    Some((addr, addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::ipv4::MutableIpv4Packet;
+
+    use super::*;
+
+    const IPV4_HEADER_SIZE: usize = 20;
+
+    fn addr(last: u8) -> Ipv4Addr {
+        Ipv4Addr::new(192, 0, 2, last)
+    }
+
+    #[test]
+    fn parse_targets_test() {
+        assert_eq!(parse_targets("192.0.2.0/24"), Some((addr(1), addr(254))));
+        // host bits are ignored
+        assert_eq!(parse_targets("192.0.2.77/30"), Some((addr(77), addr(78))));
+        assert_eq!(parse_targets("192.0.2.0/31"), Some((addr(0), addr(1))));
+        assert_eq!(parse_targets("192.0.2.1/32"), Some((addr(1), addr(1))));
+        assert_eq!(parse_targets("0.0.0.0/0"), Some((Ipv4Addr::new(0, 0, 0, 1), Ipv4Addr::new(255, 255, 255, 254))));
+
+        assert_eq!(parse_targets("192.0.2.10-192.0.2.12"), Some((addr(10), addr(12))));
+        assert_eq!(parse_targets("192.0.2.10-20"), Some((addr(10), addr(20))));
+        assert_eq!(parse_targets("192.0.2.5"), Some((addr(5), addr(5))));
+
+        assert_eq!(parse_targets("192.0.2.250-3.0.0.0"), None);
+        assert_eq!(parse_targets("192.0.2.0/33"), None);
+        assert_eq!(parse_targets("192.0.2.10-300"), None);
+        assert_eq!(parse_targets("example.com"), None);
+    }
+
+    #[test]
+    fn get_sweep_reply_test() {
+        let targets = vec![addr(1), addr(2), addr(3)];
+        let mut buf = make_echo_request(Ipv4Addr::new(192, 0, 2, 2), SWEEP_TTL, 0x5555, 1);
+        let mut packet = MutableIpv4Packet::new(&mut buf).unwrap();
+        packet.set_source(Ipv4Addr::new(192, 0, 2, 2));
+        // echo request is not a reply
+        assert_eq!(get_sweep_reply(&Ipv4Packet::new(&buf).unwrap(), &targets, 0x5555), None);
+
+        buf[IPV4_HEADER_SIZE] = IcmpTypes::EchoReply.0;
+        let res_packet = Ipv4Packet::new(&buf).unwrap();
+        assert_eq!(get_sweep_reply(&res_packet, &targets, 0x5555), Some(1));
+        assert_eq!(get_sweep_reply(&res_packet, &targets, 0x5556), None);
+        // source does not match the address of the sequence
+        assert_eq!(get_sweep_reply(&res_packet, &targets[1..], 0x5555), None);
+    }
+}
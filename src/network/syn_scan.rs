@@ -0,0 +1,227 @@
+use std::{collections::HashMap, io, net::IpAddr, sync::{Mutex, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+use log::{debug, error};
+
+use pnet::{packet::{ip::IpNextHeaderProtocols, tcp::{self, MutableTcpPacket, TcpFlags, TcpPacket}}, transport::{transport_channel, TransportChannelType, TransportProtocol, TransportSender, tcp_packet_iter}};
+use rand::Rng;
+
+use crate::utils::ip_utils::get_source_ip;
+
+use super::port_scan::PortState;
+
+const TCP_HEADER_SIZE: usize = 20;
+const SYN_WINDOW: u16 = 1024;
+// interval between syn segments (ms)
+const SYN_INTERVAL: u64 = 1;
+// poll interval of receive channel to check the end of sending
+const SYN_POLL: u64 = 100;
+// unanswered ports are probed again, syn or response may be lost
+const SYN_RETRY: u8 = 2;
+
+/**
+ * Tcp segment addresses of the scan
+ */
+#[derive(Clone, Copy)]
+struct SynProbe {
+    source_ip: IpAddr,
+    dest_ip: IpAddr,
+    source_port: u16,
+    // sequence number of port p is isn + p
+    isn: u32
+}
+
+impl SynProbe {
+    fn sequence(&self, port: u16) -> u32 {
+        self.isn.wrapping_add(port as u32)
+    }
+}
+
+/**
+ * Scan ports by half-open connections, ports without response are filtered.
+ * Results are ordered as ports.
+ */
+pub fn tcp_syn_scan(dest_ip: IpAddr, ports: &[u16], timeout: Duration) -> io::Result<Vec<(u16, PortState)>> {
+    let probe = SynProbe {
+        source_ip: get_source_ip(dest_ip)?,
+        dest_ip,
+        // port out of linux ephemeral range (32768-60999) is hardly used by kernel
+        source_port: rand::thread_rng().gen_range(61000..65000),
+        isn: rand::thread_rng().gen()
+    };
+
+    let protocol = match dest_ip {
+        IpAddr::V4(_) => TransportProtocol::Ipv4(IpNextHeaderProtocols::Tcp),
+        IpAddr::V6(_) => TransportProtocol::Ipv6(IpNextHeaderProtocols::Tcp)
+    };
+    let (tx, mut rx) = transport_channel(4096, TransportChannelType::Layer4(protocol))?;
+    let tx = Mutex::new(tx);
+    debug!("SynScan: {}:{} -> {}", probe.source_ip, probe.source_port, dest_ip);
+
+    let mut states: HashMap<u16, PortState> = HashMap::new();
+    for _ in 0..SYN_RETRY {
+        let unanswered: Vec<u16> = ports.iter().copied().filter(|port| !states.contains_key(port)).collect();
+        if unanswered.is_empty() {
+            break;
+        }
+        let is_sent = AtomicBool::new(false);
+
+        let answered = thread::scope(|s| {
+            let receiver = s.spawn(|| -> io::Result<Vec<(u16, PortState)>> {
+                let mut rx = tcp_packet_iter(&mut rx);
+                let mut answered: Vec<(u16, PortState)> = Vec::new();
+                let mut deadline: Option<Instant> = None;
+                loop {
+                    if deadline.is_none() && is_sent.load(Ordering::Acquire) {
+                        deadline = Some(Instant::now() + timeout);
+                    }
+                    let mut wait = Duration::from_millis(SYN_POLL);
+                    if let Some(deadline) = deadline {
+                        wait = deadline.saturating_duration_since(Instant::now());
+                        if wait.is_zero() {
+                            return Ok(answered);
+                        }
+                    }
+
+                    let Some((res_packet, res_ip)) = rx.next_with_timeout(wait)? else {
+                        continue;
+                    };
+                    if res_ip != dest_ip {
+                        continue;
+                    }
+                    let Some((port, state)) = get_syn_response(&res_packet, &probe) else {
+                        continue;
+                    };
+                    debug!("SynScan: {} {}", port, state);
+                    if state == PortState::Open {
+                        // tear down half-open connection
+                        let ack = res_packet.get_sequence().wrapping_add(1);
+                        let segment = make_segment(&probe, port, probe.sequence(port).wrapping_add(1), ack, TcpFlags::RST);
+                        send_segment(&mut tx.lock().unwrap(), &segment, dest_ip);
+                    }
+                    answered.push((port, state));
+                }
+            });
+
+            for port in &unanswered {
+                let segment = make_segment(&probe, *port, probe.sequence(*port), 0, TcpFlags::SYN);
+                send_segment(&mut tx.lock().unwrap(), &segment, dest_ip);
+                thread::sleep(Duration::from_millis(SYN_INTERVAL));
+            }
+            is_sent.store(true, Ordering::Release);
+
+            receiver.join().unwrap()
+        })?;
+        states.extend(answered);
+    }
+
+    Ok(ports.iter().map(|port| (*port, *states.get(port).unwrap_or(&PortState::Filtered))).collect())
+}
+
+/**
+ * Send tcp segment, failure of a segment does not stop the scan
+ */
+fn send_segment(tx: &mut TransportSender, segment: &[u8], dest_ip: IpAddr) {
+    if let Err(e) = tx.send_to(TcpPacket::new(segment).unwrap(), dest_ip) {
+        error!("SynScan: {:?}", e);
+    }
+}
+
+/**
+ * Check the segment is a response of the probe, SYN/ACK is open and RST is closed
+ */
+fn get_syn_response(res_packet: &TcpPacket, probe: &SynProbe) -> Option<(u16, PortState)> {
+    if res_packet.get_destination() != probe.source_port {
+        return None;
+    }
+    let port = res_packet.get_source();
+    // response acknowledges our syn
+    if res_packet.get_acknowledgement() != probe.sequence(port).wrapping_add(1) {
+        return None;
+    }
+    let flags = res_packet.get_flags();
+    if flags & TcpFlags::RST != 0 {
+        return Some((port, PortState::Closed));
+    }
+    if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK {
+        return Some((port, PortState::Open));
+    }
+    None
+}
+
+/**
+ * Create tcp segment with checksum of pseudo header
+ */
+fn make_segment(probe: &SynProbe, dest_port: u16, sequence: u32, acknowledgement: u32, flags: u16) -> Vec<u8> {
+    let mut tcp_buf: Vec<u8> = vec![0; TCP_HEADER_SIZE];
+    let mut tcp_packet = MutableTcpPacket::new(&mut tcp_buf).unwrap();
+    tcp_packet.set_source(probe.source_port);
+    tcp_packet.set_destination(dest_port);
+    tcp_packet.set_sequence(sequence);
+    tcp_packet.set_acknowledgement(acknowledgement);
+    tcp_packet.set_data_offset((TCP_HEADER_SIZE / 4) as u8);
+    tcp_packet.set_flags(flags);
+    tcp_packet.set_window(if flags & TcpFlags::RST != 0 { 0 } else { SYN_WINDOW });
+    let sum = match (probe.source_ip, probe.dest_ip) {
+        (IpAddr::V4(source), IpAddr::V4(dest)) => tcp::ipv4_checksum(&tcp_packet.to_immutable(), &source, &dest),
+        (IpAddr::V6(source), IpAddr::V6(dest)) => tcp::ipv6_checksum(&tcp_packet.to_immutable(), &source, &dest),
+        _ => 0
+    };
+    tcp_packet.set_checksum(sum);
+    tcp_buf
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    fn make_probe() -> SynProbe {
+        SynProbe {
+            source_ip: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+            dest_ip: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            source_port: 61234,
+            isn: u32::MAX - 10
+        }
+    }
+
+    // response of the target to the probe
+    fn make_response(probe: &SynProbe, port: u16, flags: u16) -> Vec<u8> {
+        let reverse = SynProbe { source_port: port, ..*probe };
+        make_segment(&reverse, probe.source_port, 777, probe.sequence(port).wrapping_add(1), flags)
+    }
+
+    #[test]
+    fn make_segment_test() {
+        let probe = make_probe();
+        let buf = make_segment(&probe, 22, probe.sequence(22), 0, TcpFlags::SYN);
+        let packet = TcpPacket::new(&buf).unwrap();
+        assert_eq!(packet.get_source(), 61234);
+        assert_eq!(packet.get_destination(), 22);
+        // sequence wraps around
+        assert_eq!(packet.get_sequence(), 11);
+        assert_eq!(packet.get_flags(), TcpFlags::SYN);
+        assert_eq!(packet.get_data_offset(), 5);
+        let (IpAddr::V4(source), IpAddr::V4(dest)) = (probe.source_ip, probe.dest_ip) else { unreachable!() };
+        assert_eq!(packet.get_checksum(), tcp::ipv4_checksum(&packet, &source, &dest));
+    }
+
+    #[test]
+    fn get_syn_response_test() {
+        let probe = make_probe();
+        let buf = make_response(&probe, 22, TcpFlags::SYN | TcpFlags::ACK);
+        assert_eq!(get_syn_response(&TcpPacket::new(&buf).unwrap(), &probe), Some((22, PortState::Open)));
+
+        let buf = make_response(&probe, 23, TcpFlags::RST | TcpFlags::ACK);
+        assert_eq!(get_syn_response(&TcpPacket::new(&buf).unwrap(), &probe), Some((23, PortState::Closed)));
+
+        // acknowledgement of other port is not the response
+        let mut buf = make_response(&probe, 22, TcpFlags::SYN | TcpFlags::ACK);
+        MutableTcpPacket::new(&mut buf).unwrap().set_source(80);
+        assert_eq!(get_syn_response(&TcpPacket::new(&buf).unwrap(), &probe), None);
+
+        // response to other scanner
+        let mut buf = make_response(&probe, 22, TcpFlags::SYN | TcpFlags::ACK);
+        MutableTcpPacket::new(&mut buf).unwrap().set_destination(50000);
+        assert_eq!(get_syn_response(&TcpPacket::new(&buf).unwrap(), &probe), None);
+    }
+}
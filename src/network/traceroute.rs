@@ -0,0 +1,267 @@
+use std::{collections::BTreeMap, fmt, io, net::{IpAddr, Ipv4Addr}, time::{Duration, Instant}};
+use log::{debug, info};
+
+use pnet::{packet::{Packet, ip::IpNextHeaderProtocols, ipv4::Ipv4Packet, icmp::{IcmpTypes, echo_reply::EchoReplyPacket}}, transport::{transport_channel, TransportChannelType, ipv4_packet_iter, Ipv4TransportChannelIterator}};
+use rand::Rng;
+use serde::Serialize;
+
+use crate::{error::Result, types::TracerouteConfig};
+
+use super::{dns::{annotate, resolve_target_v4}, ping::make_echo_request, types::icmp_error::{IcmpError, IcmpErrorKind}};
+
+const TRACEROUTE_TIME_OUT: u64 = 3000;
+
+/**
+ * Reply of a traceroute probe
+ */
+enum HopReply {
+    // Router on the path answered TimeExceeded
+    Router(Ipv4Addr),
+    // Target answered EchoReply
+    Reached(Ipv4Addr),
+    // Router or target answered the probe can not be delivered
+    Unreachable(Ipv4Addr, IcmpError)
+}
+
+/**
+ * Probes of a hop
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct TracerouteHop {
+    pub ttl: u8,
+    // router and round trip time (ms) of each probe, None if timeout
+    pub probes: Vec<Option<(Ipv4Addr, f64)>>,
+    // names of routers by reverse dns, empty unless names are looked up
+    pub names: BTreeMap<Ipv4Addr, String>,
+    // destination unreachable which ended the trace
+    pub error: Option<IcmpError>
+}
+
+/**
+ * Report of traceroute
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct TracerouteReport {
+    pub destination: Ipv4Addr,
+    pub hops: Vec<TracerouteHop>,
+    pub reached: bool
+}
+
+impl fmt::Display for TracerouteReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.reached {
+            write!(f, "Traceroute: {} is reached in {} hops", self.destination, self.hops.len())
+        } else if let Some(error) = self.hops.last().and_then(|hop| hop.error.as_ref()) {
+            write!(f, "Traceroute: {} is unreachable at hop {}, {}", self.destination, self.hops.len(), error)
+        } else {
+            write!(f, "Traceroute: {} is not reached in {} hops", self.destination, self.hops.len())
+        }
+    }
+}
+
+/**
+ * Trace route to target by incrementing ttl
+ */
+pub fn traceroute(config: TracerouteConfig) -> Result<TracerouteReport> {
+    traceroute_with(config, |_| {})
+}
+
+/**
+ * Trace route and call on_hop with each hop as soon as its probes are done
+ */
+pub fn traceroute_with(config: TracerouteConfig, mut on_hop: impl FnMut(&TracerouteHop)) -> Result<TracerouteReport> {
+    let dest_ip = resolve_target_v4(&config.ip, &config.resolve)?;
+    let identifier: u16 = rand::thread_rng().gen();
+
+    let (mut tx, mut rx) = transport_channel(512,
+             TransportChannelType::Layer3(IpNextHeaderProtocols::Icmp))?;
+    let mut rx = ipv4_packet_iter(&mut rx);
+
+    info!("Traceroute: to {}, {} hops max", dest_ip, config.max_hops);
+
+    let mut report = TracerouteReport { destination: dest_ip, hops: Vec::new(), reached: false };
+    let mut sequence: u16 = 0;
+    // routers are looked up once even if they appear in many hops
+    let mut names: BTreeMap<Ipv4Addr, Option<String>> = BTreeMap::new();
+    for ttl in 1..=config.max_hops {
+        let mut is_reach = false;
+        let mut hop = TracerouteHop { ttl, probes: Vec::new(), names: BTreeMap::new(), error: None };
+        let mut hop_line = String::new();
+        let mut last_router: Option<Ipv4Addr> = None;
+
+        for _ in 0..config.probes {
+            sequence = sequence.wrapping_add(1);
+            let send_time = Instant::now();
+            let ip_buf = make_echo_request(dest_ip, ttl, identifier, sequence);
+            let send_packet = Ipv4Packet::new(&ip_buf).unwrap();
+            tx.send_to(send_packet, IpAddr::V4(dest_ip))?;
+
+            let mut error_mark: Option<&str> = None;
+            let router = match wait_hop_reply(&mut rx, identifier, sequence)? {
+                Some(HopReply::Router(router)) => router,
+                Some(HopReply::Reached(router)) => {
+                    is_reach = true;
+                    router
+                }
+                Some(HopReply::Unreachable(router, error)) => {
+                    error_mark = Some(unreachable_mark(&error.kind));
+                    hop.error = Some(error);
+                    router
+                }
+                None => {
+                    hop_line += "  *";
+                    hop.probes.push(None);
+                    continue;
+                }
+            };
+            let rtt = send_time.elapsed().as_secs_f64() * 1000.0;
+            hop.probes.push(Some((router, rtt)));
+
+            // show router address when it is changed in the same hop
+            if last_router != Some(router) {
+                let name = if config.rdns {
+                    names.entry(router).or_insert_with(|| annotate(&IpAddr::V4(router))).clone()
+                } else {
+                    None
+                };
+                match name {
+                    Some(name) => {
+                        hop_line += format!("  {} ({})", name, router).as_str();
+                        hop.names.insert(router, name);
+                    }
+                    None => hop_line += format!("  {}", router).as_str()
+                }
+                last_router = Some(router);
+            }
+            hop_line += format!("  {:.3} ms", rtt).as_str();
+            if let Some(mark) = error_mark {
+                hop_line += format!(" {}", mark).as_str();
+            }
+        }
+
+        info!("Traceroute: {:>2}{}", ttl, hop_line);
+        if let Some(error) = &hop.error {
+            info!("Traceroute: {}", error);
+        }
+        on_hop(&hop);
+        let is_unreachable = hop.error.is_some();
+        report.hops.push(hop);
+        if is_reach {
+            report.reached = true;
+            break;
+        }
+        if is_unreachable {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+/**
+ * Wait TimeExceeded or EchoReply for the probe
+ */
+fn wait_hop_reply(rx: &mut Ipv4TransportChannelIterator, identifier: u16, sequence: u16) -> io::Result<Option<HopReply>> {
+    let deadline = Instant::now() + Duration::from_millis(TRACEROUTE_TIME_OUT);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+
+        match rx.next_with_timeout(remaining)? {
+            Some((res_packet, _)) => {
+                debug!("Traceroute: res_packet {:?}", &res_packet);
+                if let Some(reply) = get_hop_reply(&res_packet, identifier, sequence) {
+                    return Ok(Some(reply));
+                }
+            }
+            None => {
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/**
+ * Check the response is a reply of the probe.
+ * Redirect is ignored because the probe is still forwarded.
+ */
+fn get_hop_reply(res_packet: &Ipv4Packet, identifier: u16, sequence: u16) -> Option<HopReply> {
+    if res_packet.get_next_level_protocol() != IpNextHeaderProtocols::Icmp {
+        return None;
+    }
+    let echo_packet = EchoReplyPacket::new(res_packet.payload())?;
+    if echo_packet.get_icmp_type() == IcmpTypes::EchoReply {
+        if echo_packet.get_identifier() == identifier && echo_packet.get_sequence_number() == sequence {
+            return Some(HopReply::Reached(res_packet.get_source()));
+        }
+        return None;
+    }
+    let icmp_error = IcmpError::from_ipv4(res_packet)?;
+    if !icmp_error.is_echo_of(identifier, sequence) {
+        return None;
+    }
+    match icmp_error.kind {
+        IcmpErrorKind::TtlExceeded => Some(HopReply::Router(res_packet.get_source())),
+        IcmpErrorKind::Redirect(_) => None,
+        _ => Some(HopReply::Unreachable(res_packet.get_source(), icmp_error))
+    }
+}
+
+/**
+ * Annotation of the probe like traceroute(8), e.g. !H of host unreachable
+ */
+fn unreachable_mark(kind: &IcmpErrorKind) -> &'static str {
+    match kind {
+        IcmpErrorKind::NetworkUnreachable | IcmpErrorKind::NetworkUnknown => "!N",
+        IcmpErrorKind::HostUnreachable | IcmpErrorKind::HostUnknown => "!H",
+        IcmpErrorKind::ProtocolUnreachable | IcmpErrorKind::PortUnreachable => "!P",
+        IcmpErrorKind::FragmentationNeeded(_) => "!F",
+        IcmpErrorKind::SourceRouteFailed => "!S",
+        IcmpErrorKind::AdminProhibited => "!X",
+        _ => "!"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::types::icmp_error::make_icmp_error;
+
+    use super::*;
+
+    #[test]
+    fn get_hop_reply_time_exceeded_test() {
+        let router = Ipv4Addr::new(10, 0, 0, 1);
+        let probe = make_echo_request(Ipv4Addr::new(192, 0, 2, 1), 1, 0x4242, 9);
+        let res_buf = make_icmp_error(router, IcmpTypes::TimeExceeded.0, 0, 0, &probe[..28]);
+        let res_packet = Ipv4Packet::new(&res_buf).unwrap();
+
+        match get_hop_reply(&res_packet, 0x4242, 9) {
+            Some(HopReply::Router(addr)) => assert_eq!(addr, router),
+            _ => panic!("expected router reply")
+        }
+        // Other probe's reply is ignored
+        assert!(get_hop_reply(&res_packet, 0x4242, 10).is_none());
+        assert!(get_hop_reply(&res_packet, 0x4343, 9).is_none());
+    }
+
+    #[test]
+    fn get_hop_reply_unreachable_test() {
+        let router = Ipv4Addr::new(10, 0, 0, 1);
+        let probe = make_echo_request(Ipv4Addr::new(192, 0, 2, 1), 5, 0x4242, 9);
+        let res_buf = make_icmp_error(router, IcmpTypes::DestinationUnreachable.0, 13, 0, &probe[..28]);
+        let res_packet = Ipv4Packet::new(&res_buf).unwrap();
+
+        match get_hop_reply(&res_packet, 0x4242, 9) {
+            Some(HopReply::Unreachable(addr, error)) => {
+                assert_eq!((addr, error.kind), (router, IcmpErrorKind::AdminProhibited));
+                assert_eq!(unreachable_mark(&error.kind), "!X");
+            }
+            _ => panic!("expected unreachable reply")
+        }
+        assert!(get_hop_reply(&res_packet, 0x4242, 10).is_none());
+        // redirect does not end the trace
+        let res_buf = make_icmp_error(router, IcmpTypes::RedirectMessage.0, 1, 0, &probe[..28]);
+        assert!(get_hop_reply(&Ipv4Packet::new(&res_buf).unwrap(), 0x4242, 9).is_none());
+    }
+}
@@ -1 +1,3 @@
-pub mod dns_packet;
\ No newline at end of file
+pub mod dns_packet;
+pub mod edns;
+pub mod icmp_error;
\ No newline at end of file
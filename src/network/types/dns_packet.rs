@@ -1,13 +1,28 @@
-use std::ops::Deref;
-use log::error;
-
-use clap::builder::Str;
+use std::{fmt, net::{Ipv4Addr, Ipv6Addr}};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use log::debug;
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, types::RecordType, utils::{byte_utils::to_hex, serde_utils}};
 
-use crate::utils::byte_utils::range_byte_replace;
+use super::edns::{read_options, Edns, EdnsOption, TYPE_OPT};
 
-const MAX_PACKET_SIZE: usize = 128;
-const DNS_OFFSET: u8 = 192;
+// upper two bits of label length, 11 is compression pointer
+const LABEL_TYPE_MASK: u8 = 0xc0;
+// RFC 1035 2.3.4
+const MAX_LABEL_SIZE: usize = 63;
+const MAX_NAME_SIZE: usize = 255;
+// bits of header flags (RFC 1035 4.1.1, RFC 4035 3.2)
+const FLAG_QR: u16 = 0x8000;
+const OPCODE_SHIFT: u16 = 11;
+const FLAG_AA: u16 = 0x0400;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_RD: u16 = 0x0100;
+const FLAG_RA: u16 = 0x0080;
+const FLAG_Z: u16 = 0x0040;
+const FLAG_AD: u16 = 0x0020;
+const FLAG_CD: u16 = 0x0010;
+const RCODE_MASK: u16 = 0x000f;
 
 /**
  * Dns Paket
@@ -15,13 +30,15 @@ const DNS_OFFSET: u8 = 192;
 #[derive(Debug)]
 pub struct DnsPacket {
     pub transaction_id: u16,
-    // qr, ope code, ...etc
+    // qr, opcode, ...etc
     pub flags: DnsHeader,
     pub question_count: u16,
     pub answer_count: u16,
     pub authority_count: u16,
     pub additional_count: u16,
     pub query: DnsQueryData,
+    // OPT record in additional section
+    pub edns: Option<Edns>,
     _packet: Vec<u8>
 }
 
@@ -37,159 +54,669 @@ pub struct DnsQueryData {
 }
 
 impl DnsQueryData {
-    pub fn get_query(&self) -> Vec<u8> {
-        let mut buff: Vec<u8> = Vec::new();
-        let name_sp = self.name.split(".");
-        name_sp.for_each(|name| {
-            // domain size
-            buff.push(name.len() as u8);
-            buff.append(&mut name.as_bytes().to_vec());
-        });
-        // domain end
-        buff.push(0);
-
+    pub fn get_query(&self) -> Result<Vec<u8>> {
+        let mut buff = write_name(&self.name)?;
         buff.append(&mut self.dns_type.to_be_bytes().to_vec());
         buff.append(&mut self.dns_class.to_be_bytes().to_vec());
-        buff
+        Ok(buff)
     }
 }
 
 /**
- * DnsHeader
+ * Flags of dns header
  */
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
 pub struct DnsHeader {
-    pub qr_code: u8,
-    pub ope_code: u8,
-    pub trunc: u8,
-    pub recursion: u8,
-    pub z_code: u8,
-    pub dns_sec: u8,
-    pub auth_code: u8
+    // QR, message is a response
+    pub response: bool,
+    // 0 is standard query
+    pub opcode: u8,
+    // AA, answer is from the authoritative server of the zone
+    pub authoritative: bool,
+    // TC, message is truncated to the size of the transport
+    pub truncated: bool,
+    // RD
+    pub recursion_desired: bool,
+    // RA
+    pub recursion_available: bool,
+    // reserved, has to be zero
+    pub z: bool,
+    // AD, all records are validated by DNSSEC
+    pub authentic_data: bool,
+    // CD, resolver should not validate DNSSEC
+    pub checking_disabled: bool,
+    // lower 4 bits of rcode, the rest is in OPT record
+    pub rcode: u8
+}
+
+impl DnsHeader {
+    pub fn from_flags(flags: u16) -> Self {
+        DnsHeader {
+            response: flags & FLAG_QR != 0,
+            opcode: ((flags >> OPCODE_SHIFT) & 0x0f) as u8,
+            authoritative: flags & FLAG_AA != 0,
+            truncated: flags & FLAG_TC != 0,
+            recursion_desired: flags & FLAG_RD != 0,
+            recursion_available: flags & FLAG_RA != 0,
+            z: flags & FLAG_Z != 0,
+            authentic_data: flags & FLAG_AD != 0,
+            checking_disabled: flags & FLAG_CD != 0,
+            rcode: (flags & RCODE_MASK) as u8
+        }
+    }
+
+    pub fn to_flags(&self) -> u16 {
+        let mut flags = ((self.opcode as u16 & 0x0f) << OPCODE_SHIFT) | (self.rcode as u16 & RCODE_MASK);
+        let bits = [
+            (self.response, FLAG_QR),
+            (self.authoritative, FLAG_AA),
+            (self.truncated, FLAG_TC),
+            (self.recursion_desired, FLAG_RD),
+            (self.recursion_available, FLAG_RA),
+            (self.z, FLAG_Z),
+            (self.authentic_data, FLAG_AD),
+            (self.checking_disabled, FLAG_CD)
+        ];
+        for (set, bit) in bits {
+            if set {
+                flags |= bit;
+            }
+        }
+        flags
+    }
+}
+
+impl fmt::Display for DnsHeader {
+    /**
+     * Set flags like dig, e.g. "qr rd ra"
+     */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags: Vec<&str> = [
+            (self.response, "qr"),
+            (self.authoritative, "aa"),
+            (self.truncated, "tc"),
+            (self.recursion_desired, "rd"),
+            (self.recursion_available, "ra"),
+            (self.z, "z"),
+            (self.authentic_data, "ad"),
+            (self.checking_disabled, "cd")
+        ].iter().filter(|(set, _)| *set).map(|(_, name)| *name).collect();
+        write!(f, "{}", flags.join(" "))
+    }
 }
 
 /**
- * DnsHeader
+ * Response code, 12 bits with the extended rcode of EDNS0
  */
-#[allow(unused)]
-impl DnsHeader {
-    fn get_flags(&self) -> Vec<u8> {
-        let mut buff: Vec<u8> = Vec::new();
-        buff.push(self.qr_code + self.ope_code + self.trunc + self.recursion);
-        buff.push(self.z_code + self.auth_code);
-        buff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rcode {
+    NoError,
+    // server could not interpret the query
+    FormErr,
+    ServFail,
+    // domain name does not exist
+    NxDomain,
+    NotImp,
+    Refused,
+    YxDomain,
+    YxRrset,
+    NxRrset,
+    NotAuth,
+    NotZone,
+    // EDNS version is not supported
+    BadVers,
+    BadCookie,
+    Unknown(u16)
+}
+
+impl Rcode {
+    pub fn from_code(code: u16) -> Rcode {
+        match code {
+            0 => Rcode::NoError,
+            1 => Rcode::FormErr,
+            2 => Rcode::ServFail,
+            3 => Rcode::NxDomain,
+            4 => Rcode::NotImp,
+            5 => Rcode::Refused,
+            6 => Rcode::YxDomain,
+            7 => Rcode::YxRrset,
+            8 => Rcode::NxRrset,
+            9 => Rcode::NotAuth,
+            10 => Rcode::NotZone,
+            16 => Rcode::BadVers,
+            23 => Rcode::BadCookie,
+            _ => Rcode::Unknown(code)
+        }
+    }
+}
+
+impl fmt::Display for Rcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rcode::NoError => write!(f, "NOERROR"),
+            Rcode::FormErr => write!(f, "FORMERR"),
+            Rcode::ServFail => write!(f, "SERVFAIL"),
+            Rcode::NxDomain => write!(f, "NXDOMAIN"),
+            Rcode::NotImp => write!(f, "NOTIMP"),
+            Rcode::Refused => write!(f, "REFUSED"),
+            Rcode::YxDomain => write!(f, "YXDOMAIN"),
+            Rcode::YxRrset => write!(f, "YXRRSET"),
+            Rcode::NxRrset => write!(f, "NXRRSET"),
+            Rcode::NotAuth => write!(f, "NOTAUTH"),
+            Rcode::NotZone => write!(f, "NOTZONE"),
+            Rcode::BadVers => write!(f, "BADVERS"),
+            Rcode::BadCookie => write!(f, "BADCOOKIE"),
+            Rcode::Unknown(code) => write!(f, "RCODE{}", code)
+        }
     }
 }
 
-#[derive(Default)]
+impl Serialize for Rcode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct DnsAnswer {
     // dns
     pub name: String,
     pub dns_type: u16,
     pub dns_class: u16,
+    #[serde(rename = "ttl")]
     pub time: u32,
+    #[serde(rename = "data_size")]
     pub date_size: u16,
-    pub address: String
-}
-
-impl DnsAnswer {
-    pub fn get_answer(buff: &Vec<u8>) -> Vec<DnsAnswer> {
-        let buff_size = buff.len();
-        let mut i = 0;
-        let mut answers: Vec<DnsAnswer> = Vec::new();
-        debug!("DNS: dns packet size={} buff={:?}", buff.len(), &buff);
-
-        // check answer section start position
-        i = DnsAnswer::get_answer_position(buff);
-        debug!("DNS: aws pos={} buff_size={}", i, buff_size);
-        while i < buff_size {
-            let mut ans = DnsAnswer {..Default::default()};
-            // name
-            let mut name = String::new();
-            let answer_offset = buff[i];
-            debug!("DNS: answer offset={}", answer_offset);
-            if answer_offset != DNS_OFFSET {
-                break;
-            }
-            i += 1;
-            let mut offset = buff[i] as usize;
-            loop {
-                // get name from query section
-                // TODO: shoud be refactor
-                if offset == 0 {
-                    break;
-                }
+    // address of A and AAAA, empty for other types
+    pub address: String,
+    pub data: RData
+}
 
-                let name_size: usize = buff[offset] as usize;
-                debug!("DNS: name_size={} name_offset={}", name_size, offset);
-                if name_size == 0 {
-                    // domain end
-                    i += 1;
-                    break;
-                } else {
-                    if name.len() != 0 {
-                        name += ".";
-                    }
-                }
-                let name_part_end = offset + name_size as usize;
-                let name_bytes = &buff[ (offset + 1)..(name_part_end + 1)];
-                debug!("DNS: name bytes={:?} name_part_end={}", name_bytes, name_part_end);
-                // name load
-                name += std::str::from_utf8(name_bytes).unwrap();
-                offset = name_part_end + 1;
+impl From<&DnsRecord> for DnsAnswer {
+    fn from(record: &DnsRecord) -> Self {
+        let address = match &record.rdata {
+            RData::A(ip) => ip.to_string(),
+            RData::Aaaa(ip) => ip.to_string(),
+            _ => String::new()
+        };
+        DnsAnswer {
+            name: record.name.clone(),
+            dns_type: record.dns_type,
+            dns_class: record.dns_class,
+            time: record.ttl,
+            date_size: record.data.len() as u16,
+            address,
+            data: record.rdata.clone()
+        }
+    }
+}
+
+/**
+ * Type name of the record, TYPE<n> for unknown type (RFC 3597)
+ */
+pub fn type_name(dns_type: u16) -> String {
+    match RecordType::from_code(dns_type) {
+        Some(record_type) => record_type.to_string(),
+        None if dns_type == TYPE_OPT => "OPT".to_string(),
+        None => format!("TYPE{}", dns_type)
+    }
+}
+
+/**
+ * Decoded RDATA of the record
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ns(String),
+    Ptr(String),
+    Mx { preference: u16, exchange: String },
+    // character strings
+    Txt(Vec<String>),
+    Soa { mname: String, rname: String, serial: u32, refresh: u32, retry: u32, expire: u32, minimum: u32 },
+    Srv { priority: u16, weight: u16, port: u16, target: String },
+    // certification authority authorization (RFC 8659)
+    Caa { flags: u8, tag: String, value: String },
+    // delegation signer of child zone (RFC 4034 5)
+    Ds { key_tag: u16, algorithm: u8, digest_type: u8, #[serde(serialize_with = "serde_utils::hex")] digest: Vec<u8> },
+    // signature of rrset, times are seconds since unix epoch (RFC 4034 3)
+    Rrsig {
+        type_covered: u16,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: String,
+        #[serde(serialize_with = "serde_utils::base64")]
+        signature: Vec<u8>
+    },
+    // public key of zone (RFC 4034 2)
+    Dnskey { flags: u16, protocol: u8, algorithm: u8, #[serde(serialize_with = "serde_utils::base64")] public_key: Vec<u8> },
+    // options of EDNS0 pseudo record
+    Opt(Vec<EdnsOption>),
+    // rdata of other types as is
+    Unknown(Vec<u8>)
+}
+
+impl fmt::Display for RData {
+    /**
+     * Presentation format of zone file
+     */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RData::A(ip) => write!(f, "{}", ip),
+            RData::Aaaa(ip) => write!(f, "{}", ip),
+            RData::Cname(name) | RData::Ns(name) | RData::Ptr(name) => write!(f, "{}", name),
+            RData::Mx { preference, exchange } => write!(f, "{} {}", preference, exchange),
+            RData::Txt(texts) => {
+                let texts: Vec<String> = texts.iter().map(|text| quote(text)).collect();
+                write!(f, "{}", texts.join(" "))
             }
-            // answer code end
-            if name.len() == 0 {
-                break;
+            RData::Soa { mname, rname, serial, refresh, retry, expire, minimum } =>
+                write!(f, "{} {} {} {} {} {} {}", mname, rname, serial, refresh, retry, expire, minimum),
+            RData::Srv { priority, weight, port, target } => write!(f, "{} {} {} {}", priority, weight, port, target),
+            RData::Caa { flags, tag, value } => write!(f, "{} {} {}", flags, tag, quote(value)),
+            RData::Ds { key_tag, algorithm, digest_type, digest } =>
+                write!(f, "{} {} {} {}", key_tag, algorithm, digest_type, to_hex(digest).to_uppercase()),
+            RData::Rrsig { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature } =>
+                write!(f, "{} {} {} {} {} {} {} {} {}", type_name(*type_covered), algorithm, labels, original_ttl,
+                    expiration, inception, key_tag, signer, STANDARD.encode(signature)),
+            RData::Dnskey { flags, protocol, algorithm, public_key } =>
+                write!(f, "{} {} {} {}", flags, protocol, algorithm, STANDARD.encode(public_key)),
+            RData::Opt(options) => {
+                let options: Vec<String> = options.iter().map(|option| option.to_string()).collect();
+                write!(f, "{}", options.join("; "))
+            }
+            RData::Unknown(data) => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " ")?;
+                }
+                data.iter().try_for_each(|b| write!(f, "{:02x}", b))
             }
-            // name end
-            debug!("DNS: name={}", name);
-            ans.name = name;
+        }
+    }
+}
+
+/**
+ * Quote character string, double quote and backslash are escaped
+ */
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/**
+ * Question of dns message
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsQuestion {
+    pub name: String,
+    pub dns_type: u16,
+    pub dns_class: u16
+}
+
+/**
+ * Resource record of answer, authority and additional section
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+    pub name: String,
+    pub dns_type: u16,
+    pub dns_class: u16,
+    pub ttl: u32,
+    // raw rdata
+    pub data: Vec<u8>,
+    pub rdata: RData
+}
+
+impl DnsRecord {
+    /**
+     * Record of uncompressed rdata, e.g. trust anchor
+     */
+    pub fn new(name: &str, dns_type: u16, dns_class: u16, ttl: u32, data: Vec<u8>) -> Result<DnsRecord> {
+        let rdata = read_rdata(&data, dns_type, 0, data.len())?;
+        Ok(DnsRecord { name: name.to_string(), dns_type, dns_class, ttl, data, rdata })
+    }
+}
+
+/**
+ * Dns message decoded by RFC 1035 section 4
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsMessage {
+    pub id: u16,
+    pub header: DnsHeader,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub additionals: Vec<DnsRecord>
+}
+
+impl DnsMessage {
+    /**
+     * Decode header and all sections, bytes after the last record are ignored
+     */
+    pub fn parse(buf: &[u8]) -> Result<DnsMessage> {
+        let mut reader = DnsReader { buf, pos: 0 };
+        let id = reader.read_u16()?;
+        let header = DnsHeader::from_flags(reader.read_u16()?);
+        let question_count = reader.read_u16()?;
+        let answer_count = reader.read_u16()?;
+        let authority_count = reader.read_u16()?;
+        let additional_count = reader.read_u16()?;
+
+        let mut questions: Vec<DnsQuestion> = Vec::new();
+        for _ in 0..question_count {
+            questions.push(DnsQuestion {
+                name: reader.read_name()?,
+                dns_type: reader.read_u16()?,
+                dns_class: reader.read_u16()?
+            });
+        }
+        let answers = reader.read_records(answer_count)?;
+        let authorities = reader.read_records(authority_count)?;
+        let additionals = reader.read_records(additional_count)?;
+        debug!("DNS: parsed {} bytes of {} bytes message", reader.pos, buf.len());
+
+        Ok(DnsMessage { id, header, questions, answers, authorities, additionals })
+    }
+
+    /**
+     * Rcode of header combined with extended rcode of OPT record
+     */
+    pub fn rcode(&self) -> Rcode {
+        let extended = self.edns().map(|edns| edns.extended_rcode as u16).unwrap_or(0);
+        Rcode::from_code(extended << 4 | self.header.rcode as u16)
+    }
+
+    /**
+     * OPT record of additional section, None if the sender does not support EDNS0
+     */
+    pub fn edns(&self) -> Option<Edns> {
+        self.additionals.iter().find(|record| record.dns_type == TYPE_OPT).map(|record| {
+            let options = match &record.rdata {
+                RData::Opt(options) => options.clone(),
+                _ => Vec::new()
+            };
+            Edns::from_record(record.dns_class, record.ttl, options)
+        })
+    }
+}
+
+/**
+ * Bounds checked reader of dns message
+ */
+struct DnsReader<'a> {
+    buf: &'a [u8],
+    pos: usize
+}
 
-            // dns type
-            ans.dns_type = u16::from_be_bytes([buff[i], buff[i+1]]);
-            i += 2;
+impl<'a> DnsReader<'a> {
+    fn read_bytes(&mut self, size: usize) -> Result<&'a [u8]> {
+        let bytes = self.buf.get(self.pos..self.pos + size).ok_or_else(|| NetDoctorError::MalformedPacket(
+            format!("{} bytes at offset {} exceed dns message of {} bytes", size, self.pos, self.buf.len())))?;
+        self.pos += size;
+        Ok(bytes)
+    }
 
-            // class 
-            ans.dns_class = u16::from_be_bytes([buff[i], buff[i+1]]);
-            i += 2;
+    fn read_u16(&mut self) -> Result<u16> {
+        let bytes = self.read_bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
 
-            // time
-            ans.time = u32::from_be_bytes([buff[i], buff[i+1], buff[i+2], buff[i+3]]);
-            i += 4;
+    fn read_u32(&mut self) -> Result<u32> {
+        let bytes = self.read_bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
 
-            // data length
-            ans.date_size = u16::from_be_bytes([buff[i], buff[i+1]]);
-            i += 2;
+    fn read_name(&mut self) -> Result<String> {
+        let (name, end) = read_name(self.buf, self.pos)?;
+        self.pos = end;
+        Ok(name)
+    }
 
-            // address
-            ans.address = buff[i].to_string() + "." + buff[i+1].to_string().as_str() 
-                + "." + buff[i+2].to_string().as_str() + "." + buff[i+3].to_string().as_str();
-            i += 4;
-            answers.push(ans);
+    fn read_records(&mut self, count: u16) -> Result<Vec<DnsRecord>> {
+        let mut records: Vec<DnsRecord> = Vec::new();
+        for _ in 0..count {
+            let name = self.read_name()?;
+            let dns_type = self.read_u16()?;
+            let dns_class = self.read_u16()?;
+            let ttl = self.read_u32()?;
+            let data_size = self.read_u16()? as usize;
+            let start = self.pos;
+            let data = self.read_bytes(data_size)?.to_vec();
+            let rdata = read_rdata(self.buf, dns_type, start, data_size)?;
+            records.push(DnsRecord { name, dns_type, dns_class, ttl, data, rdata });
+        }
+        Ok(records)
+    }
+}
+
+/**
+ * Decode rdata at start, names in rdata may point anywhere of the message
+ */
+fn read_rdata(buf: &[u8], dns_type: u16, start: usize, size: usize) -> Result<RData> {
+    let end = start + size;
+    let mut reader = DnsReader { buf, pos: start };
+    let rdata = match RecordType::from_code(dns_type) {
+        Some(RecordType::A) => {
+            let octets: [u8; 4] = reader.read_bytes(size)?.try_into()
+                .map_err(|_| NetDoctorError::MalformedPacket(format!("A record of {} bytes", size)))?;
+            RData::A(Ipv4Addr::from(octets))
+        }
+        Some(RecordType::Aaaa) => {
+            let octets: [u8; 16] = reader.read_bytes(size)?.try_into()
+                .map_err(|_| NetDoctorError::MalformedPacket(format!("AAAA record of {} bytes", size)))?;
+            RData::Aaaa(Ipv6Addr::from(octets))
+        }
+        Some(RecordType::Cname) => RData::Cname(reader.read_name()?),
+        Some(RecordType::Ns) => RData::Ns(reader.read_name()?),
+        Some(RecordType::Ptr) => RData::Ptr(reader.read_name()?),
+        Some(RecordType::Mx) => RData::Mx { preference: reader.read_u16()?, exchange: reader.read_name()? },
+        Some(RecordType::Txt) => {
+            let mut texts: Vec<String> = Vec::new();
+            while reader.pos < end {
+                let text_size = reader.read_bytes(1)?[0] as usize;
+                texts.push(String::from_utf8_lossy(reader.read_bytes(text_size)?).to_string());
+            }
+            RData::Txt(texts)
         }
+        Some(RecordType::Soa) => RData::Soa {
+            mname: reader.read_name()?,
+            rname: reader.read_name()?,
+            serial: reader.read_u32()?,
+            refresh: reader.read_u32()?,
+            retry: reader.read_u32()?,
+            expire: reader.read_u32()?,
+            minimum: reader.read_u32()?
+        },
+        Some(RecordType::Srv) => RData::Srv {
+            priority: reader.read_u16()?,
+            weight: reader.read_u16()?,
+            port: reader.read_u16()?,
+            target: reader.read_name()?
+        },
+        Some(RecordType::Caa) => {
+            let flags = reader.read_bytes(1)?[0];
+            let tag_size = reader.read_bytes(1)?[0] as usize;
+            let tag = String::from_utf8_lossy(reader.read_bytes(tag_size)?).to_string();
+            let value_size = end.checked_sub(reader.pos)
+                .ok_or_else(|| NetDoctorError::MalformedPacket(format!("CAA tag of {} bytes exceeds rdata", tag_size)))?;
+            let value = String::from_utf8_lossy(reader.read_bytes(value_size)?).to_string();
+            RData::Caa { flags, tag, value }
+        }
+        Some(RecordType::Ds) => RData::Ds {
+            key_tag: reader.read_u16()?,
+            algorithm: reader.read_bytes(1)?[0],
+            digest_type: reader.read_bytes(1)?[0],
+            digest: reader.read_bytes(end.saturating_sub(start + 4))?.to_vec()
+        },
+        Some(RecordType::Rrsig) => {
+            let type_covered = reader.read_u16()?;
+            let algorithm = reader.read_bytes(1)?[0];
+            let labels = reader.read_bytes(1)?[0];
+            let original_ttl = reader.read_u32()?;
+            let expiration = reader.read_u32()?;
+            let inception = reader.read_u32()?;
+            let key_tag = reader.read_u16()?;
+            let signer = reader.read_name()?;
+            let signature_size = end.checked_sub(reader.pos)
+                .ok_or_else(|| NetDoctorError::MalformedPacket(format!("RRSIG signer {} exceeds rdata", signer)))?;
+            let signature = reader.read_bytes(signature_size)?.to_vec();
+            RData::Rrsig { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer, signature }
+        }
+        Some(RecordType::Dnskey) => RData::Dnskey {
+            flags: reader.read_u16()?,
+            protocol: reader.read_bytes(1)?[0],
+            algorithm: reader.read_bytes(1)?[0],
+            public_key: reader.read_bytes(end.saturating_sub(start + 4))?.to_vec()
+        },
+        None if dns_type == TYPE_OPT => RData::Opt(read_options(reader.read_bytes(size)?)?),
+        None => RData::Unknown(reader.read_bytes(size)?.to_vec())
+    };
+    // fields have to fill rdata exactly
+    if reader.pos != end {
+        return Err(NetDoctorError::MalformedPacket(
+            format!("{} rdata of {} bytes at offset {} is decoded as {} bytes", type_name(dns_type), size, start, reader.pos - start)));
+    }
+    Ok(rdata)
+}
 
-        answers
+/**
+ * Read id and flags without decoding sections, which may be cut in truncated message
+ */
+pub fn read_header(buf: &[u8]) -> Result<(u16, DnsHeader)> {
+    if buf.len() < 12 {
+        return Err(NetDoctorError::MalformedPacket(format!("header needs 12 bytes, got {} bytes", buf.len())));
     }
+    Ok((u16::from_be_bytes([buf[0], buf[1]]), DnsHeader::from_flags(u16::from_be_bytes([buf[2], buf[3]]))))
+}
 
-    fn get_answer_position(buff: &Vec<u8>) -> usize {
-        let mut i: usize = 0;
-        // skip header
-        i += 12;
-        loop {
-            // skip domain
-            let name_size = buff[i];
-            i += 1;
-            if name_size == 0 {
+/**
+ * Read domain name at offset following compression pointers.
+ * Returns the name without trailing dot ("." for root) and the offset after the name.
+ */
+pub fn read_name(buf: &[u8], offset: usize) -> Result<(String, usize)> {
+    let malformed = |message: String| NetDoctorError::MalformedPacket(message);
+    let mut labels: Vec<String> = Vec::new();
+    let mut pos = offset;
+    // offset after the name in place, the first pointer ends it
+    let mut end: Option<usize> = None;
+    let mut pointers = 0;
+    // wire length including the root label
+    let mut size = 1;
+    loop {
+        let label_size = *buf.get(pos).ok_or_else(|| malformed(format!("name at offset {} is not terminated", offset)))?;
+        match label_size & LABEL_TYPE_MASK {
+            0 if label_size == 0 => {
+                end.get_or_insert(pos + 1);
                 break;
             }
+            0 => {
+                let label_size = label_size as usize;
+                let label = buf.get((pos + 1)..(pos + 1 + label_size))
+                    .ok_or_else(|| malformed(format!("label at offset {} exceeds dns message", pos)))?;
+                size += label_size + 1;
+                if size > MAX_NAME_SIZE {
+                    return Err(malformed(format!("name at offset {} is longer than {} bytes", offset, MAX_NAME_SIZE)));
+                }
+                labels.push(escape_label(label));
+                pos += label_size + 1;
+            }
+            LABEL_TYPE_MASK => {
+                let low = *buf.get(pos + 1).ok_or_else(|| malformed(format!("pointer at offset {} is cut", pos)))?;
+                let target = (((label_size & !LABEL_TYPE_MASK) as usize) << 8) | low as usize;
+                end.get_or_insert(pos + 2);
+                // a name has at most 127 labels, more pointers than that never terminate
+                pointers += 1;
+                if pointers > MAX_NAME_SIZE / 2 {
+                    return Err(malformed(format!("compression pointer loop in name at offset {}", offset)));
+                }
+                pos = target;
+            }
+            // 0x40 and 0x80 are extended and reserved label types
+            _ => return Err(malformed(format!("unsupported label type {:#04x} at offset {}", label_size, pos)))
         }
-        debug!("DNS: query domain pos={}", i);
+    }
 
-        // skip type and class 
-        i += 4;
+    let name = if labels.is_empty() { ".".to_string() } else { labels.join(".") };
+    // end is always set when the loop breaks
+    Ok((name, end.unwrap_or(pos + 1)))
+}
 
-        i
+/**
+ * Name in uncompressed wire format, escapes of presentation format are decoded.
+ * Empty labels of root and trailing dot are not written.
+ */
+pub fn write_name(name: &str) -> Result<Vec<u8>> {
+    let mut wire: Vec<u8> = Vec::new();
+    let mut label: Vec<u8> = Vec::new();
+    let mut chars = name.bytes().peekable();
+    while let Some(b) = chars.next() {
+        match b {
+            b'.' => write_label(&mut wire, &mut label, name)?,
+            // \DDD is exactly three decimal digits of a byte (RFC 1035 5.1)
+            b'\\' if chars.peek().is_some_and(u8::is_ascii_digit) => {
+                let digits: Vec<u8> = (0..3).map_while(|_| chars.next_if(u8::is_ascii_digit)).collect();
+                let value = digits.iter().fold(0u16, |n, d| n * 10 + (d - b'0') as u16);
+                if digits.len() != 3 || value > u8::MAX as u16 {
+                    return Err(NetDoctorError::InvalidArgument(format!("name {} has escape \\{}, expected \\DDD up to 255",
+                        name, String::from_utf8_lossy(&digits))));
+                }
+                label.push(value as u8);
+            }
+            b'\\' => label.extend(chars.next()),
+            _ => label.push(b)
+        }
+    }
+    write_label(&mut wire, &mut label, name)?;
+    wire.push(0);
+    if wire.len() > MAX_NAME_SIZE {
+        return Err(NetDoctorError::InvalidArgument(format!("name {} is over {} bytes", name, MAX_NAME_SIZE)));
+    }
+    Ok(wire)
+}
+
+/**
+ * Move the label to the name with its length, upper 2 bits of the length are label type (RFC 1035 4.1.4)
+ */
+fn write_label(wire: &mut Vec<u8>, label: &mut Vec<u8>, name: &str) -> Result<()> {
+    if label.len() > MAX_LABEL_SIZE {
+        return Err(NetDoctorError::InvalidArgument(format!("label of {} is over {} bytes", name, MAX_LABEL_SIZE)));
+    }
+    if !label.is_empty() {
+        wire.push(label.len() as u8);
+        wire.append(label);
+    }
+    Ok(())
+}
+
+/**
+ * Label in presentation format, dot, backslash and non printable bytes are escaped like dig
+ */
+fn escape_label(label: &[u8]) -> String {
+    let mut escaped = String::new();
+    for b in label {
+        match b {
+            b'.' | b'\\' => {
+                escaped.push('\\');
+                escaped.push(*b as char);
+            }
+            0x21..=0x7e => escaped.push(*b as char),
+            _ => escaped += format!("\\{:03}", b).as_str()
+        }
+    }
+    escaped
+}
+
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -200,12 +727,13 @@ impl DnsPacket {
     pub fn new() -> Self{
         DnsPacket {
             transaction_id: 0,
-            flags: DnsHeader { qr_code: 0, ope_code: 0, trunc: 0, recursion: 0, z_code: 0, dns_sec: 0, auth_code: 0 },
+            flags: DnsHeader::default(),
             question_count: 0,
             answer_count: 0,
             authority_count: 0,
             additional_count: 0,
             query: DnsQueryData { name: "".to_string(), dns_type: 0, dns_class: 0 },
+            edns: None,
             _packet: Vec::new()
         }
     }
@@ -213,23 +741,19 @@ impl DnsPacket {
     /**
      * Get answer by different request and response.
      */
-    pub fn get_answar(res: Vec<u8>) -> Option<Vec<DnsAnswer>> {
-        let answers = DnsAnswer::get_answer(&res);
-        if answers.len() == 0 {
-            return None;
-        } else {
-            return Some(answers);
-        }
+    pub fn get_answar(res: Vec<u8>) -> Result<Vec<DnsAnswer>> {
+        let message = DnsMessage::parse(&res)?;
+        Ok(message.answers.iter().map(DnsAnswer::from).collect())
     }
 
-    pub fn make_packet(&mut self) {
+    pub fn make_packet(&mut self) -> Result<()> {
 
-        let mut buff = &mut self._packet;
+        let buff = &mut self._packet;
         // transaction_id
         buff.append(&mut self.transaction_id.to_be_bytes().to_vec());
 
         // flags
-        buff.append(&mut self.flags.get_flags());
+        buff.extend_from_slice(&self.flags.to_flags().to_be_bytes());
 
         // question
         buff.append(&mut self.question_count.to_be_bytes().to_vec());
@@ -244,11 +768,267 @@ impl DnsPacket {
         buff.append(&mut self.additional_count.to_be_bytes().to_vec());
 
         // query
-        buff.append(&mut self.query.get_query());
+        buff.append(&mut self.query.get_query()?);
 
+        // additional
+        if let Some(edns) = &self.edns {
+            buff.append(&mut edns.to_record());
+        }
+        Ok(())
     }
 
     pub fn packet(&self) -> &[u8] {
         &self._packet
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // header of id 0x1234, response with recursion, and section counts
+    fn make_header(counts: [u16; 4]) -> Vec<u8> {
+        let mut buf = vec![0x12, 0x34, 0x81, 0x80];
+        counts.iter().for_each(|count| buf.extend_from_slice(&count.to_be_bytes()));
+        buf
+    }
+
+    #[test]
+    fn parse_sections_test() {
+        let mut buf = make_header([1, 1, 1, 1]);
+        // question www.example.com A IN at offset 12
+        buf.extend_from_slice(b"\x03www\x07example\x03com\x00\x00\x01\x00\x01");
+        // answer pointing the question
+        buf.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4, 192, 0, 2, 1]);
+        // authority ns1 + pointer to example.com (offset 16), rdata is not decoded
+        buf.extend_from_slice(b"\x03ns1\xc0\x10\x00\x02\x00\x01\x00\x00\x00\x3c\x00\x02\xc0\x10");
+        // additional of root name
+        buf.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+
+        let message = DnsMessage::parse(&buf).unwrap();
+        assert_eq!((message.id, message.header.to_flags()), (0x1234, 0x8180));
+        assert_eq!(message.header.to_string(), "qr rd ra");
+        assert_eq!(message.questions, vec![DnsQuestion { name: "www.example.com".to_string(), dns_type: 1, dns_class: 1 }]);
+        assert_eq!(message.answers[0].name, "www.example.com");
+        assert_eq!((message.answers[0].ttl, message.answers[0].data.as_slice()), (3600, &[192, 0, 2, 1][..]));
+        assert_eq!(message.authorities[0].name, "ns1.example.com");
+        assert_eq!(message.authorities[0].data, vec![0xc0, 0x10]);
+        assert_eq!((message.additionals[0].name.as_str(), message.additionals[0].dns_type), (".", 41));
+        assert_eq!(message.edns(), Some(Edns::new(4096)));
+
+        let answer = DnsAnswer::from(&message.answers[0]);
+        assert_eq!((answer.address.as_str(), answer.date_size), ("192.0.2.1", 4));
+    }
+
+    #[test]
+    fn get_query_test() {
+        let query = |name: &str| DnsQueryData { name: name.to_string(), dns_type: 2, dns_class: 1 }.get_query().unwrap();
+        assert_eq!(query("."), vec![0, 0, 2, 0, 1]);
+        assert_eq!(query("com."), vec![3, b'c', b'o', b'm', 0, 0, 2, 0, 1]);
+        assert_eq!(query("com"), query("com."));
+        // escaped dot is in the label as dnssec canonical form
+        assert_eq!(query("a\\.b.com"), [&write_name("a\\.b.com").unwrap()[..], &[0, 2, 0, 1]].concat());
+        assert_eq!(query("a\\.b.com")[0], 3);
+    }
+
+    #[test]
+    fn write_name_test() {
+        assert_eq!(write_name(".").unwrap(), vec![0]);
+        assert_eq!(write_name("a.b").unwrap(), write_name("a.b.").unwrap());
+        assert_eq!(write_name("a\\.b.c").unwrap(), b"\x03a.b\x01c\x00".to_vec());
+        assert_eq!(write_name("\\065\\\\").unwrap(), b"\x02A\\\x00".to_vec());
+        let (name, _) = read_name(&write_name("x\\.y\\000.z").unwrap(), 0).unwrap();
+        assert_eq!(name, "x\\.y\\000.z");
+        assert_eq!(write_name("\\255").unwrap(), vec![1, 255, 0]);
+        for escape in ["\\999", "\\256", "\\65", "\\6a", "a\\1"] {
+            assert!(matches!(write_name(escape), Err(NetDoctorError::InvalidArgument(_))), "{}", escape);
+        }
+
+        // 63 bytes of label and 255 bytes of name in wire format are the limits
+        let label = "a".repeat(MAX_LABEL_SIZE);
+        assert_eq!(write_name(&label).unwrap().len(), MAX_LABEL_SIZE + 2);
+        assert!(matches!(write_name(&format!("{}a.com", label)), Err(NetDoctorError::InvalidArgument(_))));
+        assert!(matches!(write_name(&format!("{}\\097", label)), Err(NetDoctorError::InvalidArgument(_))));
+        let name = [&label[..], &label, &label, &"a".repeat(61)].join(".");
+        assert_eq!(write_name(&name).unwrap().len(), MAX_NAME_SIZE);
+        assert!(matches!(write_name(&format!("a.{}", name)), Err(NetDoctorError::InvalidArgument(_))));
+        let query = DnsQueryData { name: format!("a.{}", name), dns_type: 1, dns_class: 1 };
+        assert!(matches!(query.get_query(), Err(NetDoctorError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn dnssec_rdata_test() {
+        let record = DnsRecord::new("example", 43, 1, 300, vec![0x10, 0x92, 13, 2, 0xab, 0xcd]).unwrap();
+        assert_eq!(record.rdata.to_string(), "4242 13 2 ABCD");
+
+        let record = DnsRecord::new("example", 48, 1, 300, vec![1, 1, 3, 15, 0xfb, 0xff]).unwrap();
+        assert_eq!(record.rdata, RData::Dnskey { flags: 257, protocol: 3, algorithm: 15, public_key: vec![0xfb, 0xff] });
+        assert_eq!(record.rdata.to_string(), "257 3 15 +/8=");
+
+        // RRSIG of A, 2 labels, ttl 300, expiration, inception, key tag 4242, signer example
+        let mut data = vec![0, 1, 13, 2, 0, 0, 1, 44, 0x68, 0xe6, 0x7f, 0x80, 0x68, 0xe3, 0xdc, 0x80, 0x10, 0x92];
+        data.extend_from_slice(b"\x07example\x00\x01\x02");
+        let record = DnsRecord::new("www.example", 46, 1, 300, data).unwrap();
+        assert_eq!(record.rdata.to_string(), "A 13 2 300 1759936384 1759763584 4242 example AQI=");
+        let json = serde_json::to_value(&record.rdata).unwrap();
+        assert_eq!((json["rrsig"]["signer"].as_str(), json["rrsig"]["signature"].as_str()), (Some("example"), Some("AQI=")));
+
+        assert!(DnsRecord::new("example", 43, 1, 300, vec![0x10, 0x92, 13]).is_err());
+    }
+
+    #[test]
+    fn header_flags_test() {
+        let header = DnsHeader::from_flags(0x8180);
+        assert!(header.response && header.recursion_desired && header.recursion_available);
+        assert_eq!((header.opcode, header.rcode, header.authoritative), (0, 0, false));
+
+        // all bits, opcode 15 and rcode 15
+        let header = DnsHeader::from_flags(0xffff);
+        assert_eq!((header.opcode, header.rcode), (15, 15));
+        assert_eq!(header.to_string(), "qr aa tc rd ra z ad cd");
+        for flags in [0x0000, 0x0100, 0x0120, 0x8183, 0x8405, 0x2800, 0xffff] {
+            assert_eq!(DnsHeader::from_flags(flags).to_flags(), flags);
+        }
+        let header = DnsHeader { recursion_desired: true, authentic_data: true, ..Default::default() };
+        assert_eq!(header.to_flags(), 0x0120);
+    }
+
+    #[test]
+    fn rcode_test() {
+        // NXDOMAIN of header only
+        let mut buf = make_header([0, 0, 0, 0]);
+        buf[3] = 0x83;
+        assert_eq!(DnsMessage::parse(&buf).unwrap().rcode(), Rcode::NxDomain);
+
+        // BADVERS is 16, 1 of extended rcode and 0 of header
+        let mut buf = make_header([0, 0, 0, 1]);
+        buf.extend_from_slice(&[0, 0, 41, 0x04, 0xd0, 1, 0, 0, 0, 0, 0]);
+        let message = DnsMessage::parse(&buf).unwrap();
+        assert_eq!((message.rcode(), message.rcode().to_string()), (Rcode::BadVers, "BADVERS".to_string()));
+        assert_eq!(Rcode::from_code(11).to_string(), "RCODE11");
+    }
+
+    #[test]
+    fn make_packet_edns_test() {
+        let mut packet = DnsPacket::new();
+        packet.query = DnsQueryData { name: "example.com".to_string(), dns_type: 1, dns_class: 1 };
+        packet.question_count = 1;
+        packet.additional_count = 1;
+        packet.edns = Some(Edns { options: vec![EdnsOption::Nsid { id: Vec::new() }], ..Edns::new(1232) });
+        packet.make_packet().unwrap();
+
+        let message = DnsMessage::parse(packet.packet()).unwrap();
+        assert_eq!(message.additionals[0].rdata.to_string(), "NSID");
+        assert_eq!(message.edns(), packet.edns);
+    }
+
+    // message of a question example.com at offset 12 and one answer of the rdata
+    fn make_answer(dns_type: u16, rdata: &[u8]) -> Vec<u8> {
+        let mut buf = make_header([1, 1, 0, 0]);
+        buf.extend_from_slice(b"\x07example\x03com\x00");
+        buf.extend_from_slice(&dns_type.to_be_bytes());
+        buf.extend_from_slice(&[0, 1, 0xc0, 12]);
+        buf.extend_from_slice(&dns_type.to_be_bytes());
+        buf.extend_from_slice(&[0, 1, 0, 0, 1, 0x2c]);
+        buf.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        buf.extend_from_slice(rdata);
+        buf
+    }
+
+    fn parse_rdata(record_type: RecordType, rdata: &[u8]) -> Result<RData> {
+        let message = DnsMessage::parse(&make_answer(record_type.code(), rdata))?;
+        Ok(message.answers[0].rdata.clone())
+    }
+
+    #[test]
+    fn read_rdata_test() {
+        let aaaa = parse_rdata(RecordType::Aaaa, &Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets()).unwrap();
+        assert_eq!(aaaa.to_string(), "2001:db8::1");
+        // names are compressed against the question
+        assert_eq!(parse_rdata(RecordType::Cname, b"\x03www\xc0\x0c").unwrap(), RData::Cname("www.example.com".to_string()));
+        assert_eq!(parse_rdata(RecordType::Ns, b"\x03ns1\xc0\x0c").unwrap().to_string(), "ns1.example.com");
+        assert_eq!(parse_rdata(RecordType::Ptr, b"\xc0\x0c").unwrap(), RData::Ptr("example.com".to_string()));
+        assert_eq!(parse_rdata(RecordType::Mx, b"\x00\x0a\x04mail\xc0\x0c").unwrap().to_string(), "10 mail.example.com");
+
+        let txt = parse_rdata(RecordType::Txt, b"\x0cv=spf1 -all \x08say \"hi\"").unwrap();
+        assert_eq!(txt, RData::Txt(vec!["v=spf1 -all ".to_string(), "say \"hi\"".to_string()]));
+        assert_eq!(txt.to_string(), "\"v=spf1 -all \" \"say \\\"hi\\\"\"");
+
+        let mut soa = b"\x03ns1\xc0\x0c\x0ahostmaster\xc0\x0c".to_vec();
+        [2024010101u32, 7200, 3600, 1209600, 300].iter().for_each(|v| soa.extend_from_slice(&v.to_be_bytes()));
+        assert_eq!(parse_rdata(RecordType::Soa, &soa).unwrap().to_string(),
+            "ns1.example.com hostmaster.example.com 2024010101 7200 3600 1209600 300");
+
+        let srv = parse_rdata(RecordType::Srv, b"\x00\x0a\x00\x05\x13\xc4\x03sip\xc0\x0c").unwrap();
+        assert_eq!(srv, RData::Srv { priority: 10, weight: 5, port: 5060, target: "sip.example.com".to_string() });
+
+        let caa = parse_rdata(RecordType::Caa, b"\x80\x05issueletsencrypt.org").unwrap();
+        assert_eq!(caa.to_string(), "128 issue \"letsencrypt.org\"");
+
+        let message = DnsMessage::parse(&make_answer(99, &[0xde, 0xad])).unwrap();
+        assert_eq!(message.answers[0].rdata.to_string(), "\\# 2 dead");
+        assert_eq!(type_name(99), "TYPE99");
+        assert_eq!(type_name(257), "CAA");
+    }
+
+    #[test]
+    fn read_rdata_malformed_test() {
+        assert!(parse_rdata(RecordType::A, &[192, 0, 2]).is_err());
+        // name runs over rdata into nothing
+        assert!(parse_rdata(RecordType::Cname, b"\x03www").is_err());
+        // name ends before rdata
+        assert!(parse_rdata(RecordType::Ns, b"\xc0\x0c\x00").is_err());
+        assert!(parse_rdata(RecordType::Mx, b"\x00").is_err());
+        assert!(parse_rdata(RecordType::Txt, b"\x05abc").is_err());
+        assert!(parse_rdata(RecordType::Caa, b"\x00\x09issue").is_err());
+        assert!(parse_rdata(RecordType::Srv, b"\x00\x0a\x00\x05").is_err());
+    }
+
+    #[test]
+    fn read_name_test() {
+        // pointer beyond one byte offset, and nested pointers
+        let mut buf = vec![0; 300];
+        buf.extend_from_slice(b"\x07example\x03com\x00");
+        buf.extend_from_slice(b"\x03www\xc1\x2c");
+        buf.extend_from_slice(b"\x01a\xc1\x39");
+        assert_eq!(read_name(&buf, 300).unwrap(), ("example.com".to_string(), 313));
+        assert_eq!(read_name(&buf, 313).unwrap(), ("www.example.com".to_string(), 319));
+        assert_eq!(read_name(&buf, 319).unwrap(), ("a.www.example.com".to_string(), 323));
+        assert_eq!(read_name(&[0], 0).unwrap(), (".".to_string(), 1));
+        assert_eq!(read_name(b"\x03a.b\x02\x01\\\x00", 0).unwrap().0, "a\\.b.\\001\\\\");
+    }
+
+    #[test]
+    fn read_name_malformed_test() {
+        // pointer to itself, and pointers to each other
+        assert!(matches!(read_name(&[0xc0, 0], 0), Err(NetDoctorError::MalformedPacket(_))));
+        assert!(matches!(read_name(&[0xc0, 2, 0xc0, 0], 0), Err(NetDoctorError::MalformedPacket(_))));
+        // label loop grows the name until the size limit
+        assert!(matches!(read_name(&[1, b'a', 0xc0, 0], 0), Err(NetDoctorError::MalformedPacket(_))));
+        // pointer, label and terminator out of range
+        assert!(matches!(read_name(&[0xc0, 9], 0), Err(NetDoctorError::MalformedPacket(_))));
+        assert!(matches!(read_name(&[0xc0], 0), Err(NetDoctorError::MalformedPacket(_))));
+        assert!(matches!(read_name(&[5, b'a', b'b'], 0), Err(NetDoctorError::MalformedPacket(_))));
+        assert!(matches!(read_name(&[1, b'a'], 0), Err(NetDoctorError::MalformedPacket(_))));
+        // reserved label type
+        assert!(matches!(read_name(&[0x40, 0], 0), Err(NetDoctorError::MalformedPacket(_))));
+        // name longer than 255 bytes
+        let mut buf: Vec<u8> = Vec::new();
+        (0..5).for_each(|_| { buf.push(63); buf.extend_from_slice(&[b'a'; 63]); });
+        buf.push(0);
+        assert!(matches!(read_name(&buf, 0), Err(NetDoctorError::MalformedPacket(_))));
+    }
+
+    #[test]
+    fn parse_malformed_test() {
+        assert!(DnsMessage::parse(&[0x12, 0x34, 0x81]).is_err());
+        // counts larger than records
+        let mut buf = make_header([0, 2, 0, 0]);
+        buf.extend_from_slice(&[0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+        assert!(DnsMessage::parse(&buf).is_err());
+        // rdata length exceeds the message
+        let mut buf = make_header([0, 1, 0, 0]);
+        buf.extend_from_slice(&[0, 0, 1, 0, 1, 0, 0, 0, 60, 0, 16, 192, 0, 2, 1]);
+        assert!(DnsMessage::parse(&buf).is_err());
+    }
+}
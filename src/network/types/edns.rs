@@ -0,0 +1,301 @@
+use std::{fmt, net::{IpAddr, Ipv4Addr, Ipv6Addr}, str::FromStr};
+
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, utils::{byte_utils::to_hex, serde_utils}};
+
+// type of OPT pseudo record (RFC 6891)
+pub const TYPE_OPT: u16 = 41;
+// udp payload size recommended by DNS flag day 2020
+pub const DEFAULT_UDP_SIZE: u16 = 1232;
+// DNSSEC OK bit of ttl field
+const FLAG_DO: u32 = 0x8000;
+const OPTION_NSID: u16 = 3;
+const OPTION_CLIENT_SUBNET: u16 = 8;
+const OPTION_COOKIE: u16 = 10;
+// address family numbers of IANA
+const FAMILY_IPV4: u16 = 1;
+const FAMILY_IPV6: u16 = 2;
+const CLIENT_COOKIE_SIZE: usize = 8;
+
+/**
+ * EDNS0 OPT pseudo record of query or response
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edns {
+    pub version: u8,
+    // udp payload size the sender can receive
+    pub udp_size: u16,
+    // upper 8 bits of 12 bits rcode
+    pub extended_rcode: u8,
+    pub dnssec_ok: bool,
+    pub options: Vec<EdnsOption>
+}
+
+/**
+ * Option of OPT record
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "option", rename_all = "snake_case")]
+pub enum EdnsOption {
+    // name server identifier (RFC 5001), empty in query
+    Nsid {
+        #[serde(serialize_with = "serde_utils::hex")]
+        id: Vec<u8>
+    },
+    // client subnet (RFC 7871), scope prefix is set by server
+    ClientSubnet { address: IpAddr, source_prefix: u8, scope_prefix: u8 },
+    // client cookie of 8 bytes, and server cookie of 8 to 32 bytes in response (RFC 7873)
+    Cookie {
+        #[serde(serialize_with = "serde_utils::hex")]
+        client: Vec<u8>,
+        #[serde(serialize_with = "serde_utils::hex")]
+        server: Vec<u8>
+    },
+    Unknown {
+        code: u16,
+        #[serde(serialize_with = "serde_utils::hex")]
+        data: Vec<u8>
+    }
+}
+
+impl Edns {
+    pub fn new(udp_size: u16) -> Self {
+        Edns { version: 0, udp_size, extended_rcode: 0, dnssec_ok: false, options: Vec::new() }
+    }
+
+    /**
+     * Decode OPT record from its class, ttl and options
+     */
+    pub fn from_record(udp_size: u16, ttl: u32, options: Vec<EdnsOption>) -> Self {
+        Edns {
+            version: (ttl >> 16) as u8,
+            udp_size,
+            extended_rcode: (ttl >> 24) as u8,
+            dnssec_ok: ttl & FLAG_DO != 0,
+            options
+        }
+    }
+
+    /**
+     * OPT record in wire format, owner is root and class is udp payload size
+     */
+    pub fn to_record(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        self.options.iter().for_each(|option| option.write(&mut data));
+
+        let mut ttl = (self.extended_rcode as u32) << 24 | (self.version as u32) << 16;
+        if self.dnssec_ok {
+            ttl |= FLAG_DO;
+        }
+        let mut buff: Vec<u8> = vec![0];
+        buff.extend_from_slice(&TYPE_OPT.to_be_bytes());
+        buff.extend_from_slice(&self.udp_size.to_be_bytes());
+        buff.extend_from_slice(&ttl.to_be_bytes());
+        buff.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        buff.append(&mut data);
+        buff
+    }
+}
+
+impl fmt::Display for Edns {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "version={} udp={} do={} extended_rcode={}", self.version, self.udp_size, self.dnssec_ok, self.extended_rcode)
+    }
+}
+
+impl EdnsOption {
+    /**
+     * Client subnet option of subnet like 192.0.2.0/24, host bits are cleared.
+     * Address without prefix is the whole address.
+     */
+    pub fn client_subnet(subnet: &str) -> Option<EdnsOption> {
+        let (address, prefix) = match subnet.split_once('/') {
+            Some((address, prefix)) => (IpAddr::from_str(address).ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (IpAddr::from_str(subnet).ok()?, None)
+        };
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let source_prefix = prefix.unwrap_or(max_prefix);
+        if source_prefix > max_prefix {
+            return None;
+        }
+        Some(EdnsOption::ClientSubnet { address: mask_address(address, source_prefix), source_prefix, scope_prefix: 0 })
+    }
+
+    /**
+     * Client cookie option with random client cookie
+     */
+    pub fn client_cookie() -> EdnsOption {
+        EdnsOption::Cookie { client: (0..CLIENT_COOKIE_SIZE).map(|_| rand::random()).collect(), server: Vec::new() }
+    }
+
+    fn write(&self, buff: &mut Vec<u8>) {
+        let (code, data) = match self {
+            EdnsOption::Nsid { id } => (OPTION_NSID, id.clone()),
+            EdnsOption::ClientSubnet { address, source_prefix, scope_prefix } => {
+                let (family, octets) = match address {
+                    IpAddr::V4(ip) => (FAMILY_IPV4, ip.octets().to_vec()),
+                    IpAddr::V6(ip) => (FAMILY_IPV6, ip.octets().to_vec())
+                };
+                let mut data = family.to_be_bytes().to_vec();
+                data.push(*source_prefix);
+                data.push(*scope_prefix);
+                // only the bytes covered by the source prefix are sent
+                data.extend_from_slice(&octets[..(*source_prefix as usize).div_ceil(8)]);
+                (OPTION_CLIENT_SUBNET, data)
+            }
+            EdnsOption::Cookie { client, server } => (OPTION_COOKIE, [client.as_slice(), server.as_slice()].concat()),
+            EdnsOption::Unknown { code, data } => (*code, data.clone())
+        };
+        buff.extend_from_slice(&code.to_be_bytes());
+        buff.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        buff.extend_from_slice(&data);
+    }
+
+    fn read(code: u16, data: &[u8]) -> Result<EdnsOption> {
+        let malformed = |name: &str| NetDoctorError::MalformedPacket(format!("{} option of {} bytes", name, data.len()));
+        let option = match code {
+            OPTION_NSID => EdnsOption::Nsid { id: data.to_vec() },
+            OPTION_CLIENT_SUBNET => {
+                let (header, address) = data.split_at_checked(4).ok_or_else(|| malformed("client subnet"))?;
+                let family = u16::from_be_bytes([header[0], header[1]]);
+                let (source_prefix, scope_prefix) = (header[2], header[3]);
+                let address = match family {
+                    FAMILY_IPV4 if address.len() <= 4 => {
+                        let mut octets = [0; 4];
+                        octets[..address.len()].copy_from_slice(address);
+                        IpAddr::V4(Ipv4Addr::from(octets))
+                    }
+                    FAMILY_IPV6 if address.len() <= 16 => {
+                        let mut octets = [0; 16];
+                        octets[..address.len()].copy_from_slice(address);
+                        IpAddr::V6(Ipv6Addr::from(octets))
+                    }
+                    _ => return Err(malformed("client subnet"))
+                };
+                EdnsOption::ClientSubnet { address, source_prefix, scope_prefix }
+            }
+            OPTION_COOKIE => {
+                if data.len() != CLIENT_COOKIE_SIZE && !(16..=40).contains(&data.len()) {
+                    return Err(malformed("cookie"));
+                }
+                let (client, server) = data.split_at(CLIENT_COOKIE_SIZE);
+                EdnsOption::Cookie { client: client.to_vec(), server: server.to_vec() }
+            }
+            _ => EdnsOption::Unknown { code, data: data.to_vec() }
+        };
+        Ok(option)
+    }
+}
+
+impl fmt::Display for EdnsOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EdnsOption::Nsid { id } => {
+                write!(f, "NSID")?;
+                if id.is_empty() {
+                    return Ok(());
+                }
+                write!(f, " {}", to_hex(id))?;
+                // nsid is often host name of the server
+                if id.iter().all(|b| b.is_ascii_graphic()) {
+                    write!(f, " (\"{}\")", String::from_utf8_lossy(id))?;
+                }
+                Ok(())
+            }
+            EdnsOption::ClientSubnet { address, source_prefix, scope_prefix } =>
+                write!(f, "CLIENT-SUBNET {}/{}/{}", address, source_prefix, scope_prefix),
+            EdnsOption::Cookie { client, server } => write!(f, "COOKIE client={} server={}", to_hex(client), to_hex(server)),
+            EdnsOption::Unknown { code, data } => write!(f, "OPT{} {}", code, to_hex(data))
+        }
+    }
+}
+
+/**
+ * Decode options of OPT rdata
+ */
+pub fn read_options(data: &[u8]) -> Result<Vec<EdnsOption>> {
+    let mut options: Vec<EdnsOption> = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let header = data.get(pos..pos + 4).ok_or_else(|| NetDoctorError::MalformedPacket(
+            format!("edns option header at {} exceeds rdata of {} bytes", pos, data.len())))?;
+        let code = u16::from_be_bytes([header[0], header[1]]);
+        let size = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let option = data.get(pos + 4..pos + 4 + size).ok_or_else(|| NetDoctorError::MalformedPacket(
+            format!("edns option {} of {} bytes exceeds rdata of {} bytes", code, size, data.len())))?;
+        options.push(EdnsOption::read(code, option)?);
+        pos += 4 + size;
+    }
+    Ok(options)
+}
+
+/**
+ * Clear host bits after prefix
+ */
+fn mask_address(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(ip) => IpAddr::V4(Ipv4Addr::from(u32::from(ip) & u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0))),
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_subnet_test() {
+        let option = EdnsOption::client_subnet("192.0.2.130/25").unwrap();
+        assert_eq!(option, EdnsOption::ClientSubnet { address: IpAddr::from([192, 0, 2, 128]), source_prefix: 25, scope_prefix: 0 });
+        let mut data = Vec::new();
+        option.write(&mut data);
+        // 4 bytes of 25 bits prefix
+        assert_eq!(data, [0, 8, 0, 8, 0, 1, 25, 0, 192, 0, 2, 128]);
+
+        let option = EdnsOption::client_subnet("2001:db8:1::/48").unwrap();
+        let mut data = Vec::new();
+        option.write(&mut data);
+        assert_eq!(data, [0, 8, 0, 10, 0, 2, 48, 0, 0x20, 0x01, 0x0d, 0xb8, 0, 1]);
+        assert_eq!(read_options(&data).unwrap(), vec![option]);
+
+        assert_eq!(EdnsOption::client_subnet("192.0.2.1"),
+            Some(EdnsOption::ClientSubnet { address: IpAddr::from([192, 0, 2, 1]), source_prefix: 32, scope_prefix: 0 }));
+        assert_eq!(EdnsOption::client_subnet("192.0.2.0/0").map(|o| o.to_string()).as_deref(), Some("CLIENT-SUBNET 0.0.0.0/0/0"));
+        assert_eq!(EdnsOption::client_subnet("192.0.2.0/33"), None);
+        assert_eq!(EdnsOption::client_subnet("example.com/24"), None);
+    }
+
+    #[test]
+    fn edns_record_test() {
+        let mut edns = Edns::new(DEFAULT_UDP_SIZE);
+        edns.dnssec_ok = true;
+        edns.options = vec![EdnsOption::Nsid { id: Vec::new() }, EdnsOption::Cookie { client: vec![1; 8], server: Vec::new() }];
+        let record = edns.to_record();
+        assert_eq!(record[..11], [0, 0, 41, 0x04, 0xd0, 0, 0, 0x80, 0, 0, 16]);
+        assert_eq!(read_options(&record[11..]).unwrap(), edns.options);
+
+        let edns = Edns::from_record(4096, 0x0100_8000, Vec::new());
+        assert_eq!((edns.extended_rcode, edns.version, edns.dnssec_ok, edns.udp_size), (1, 0, true, 4096));
+    }
+
+    #[test]
+    fn read_options_test() {
+        // nsid of "ns1", server cookie and padding
+        let mut data = vec![0, 3, 0, 3, b'n', b's', b'1', 0, 10, 0, 16];
+        data.extend_from_slice(&[1; 8]);
+        data.extend_from_slice(&[2; 8]);
+        data.extend_from_slice(&[0, 12, 0, 2, 0, 0]);
+        let options = read_options(&data).unwrap();
+        assert_eq!(options[0].to_string(), "NSID 6e7331 (\"ns1\")");
+        assert_eq!(options[1], EdnsOption::Cookie { client: vec![1; 8], server: vec![2; 8] });
+        assert_eq!(options[2], EdnsOption::Unknown { code: 12, data: vec![0, 0] });
+
+        // option length exceeds rdata
+        assert!(matches!(read_options(&[0, 3, 0, 4, 0]), Err(NetDoctorError::MalformedPacket(_))));
+        // cookie of 9 bytes
+        assert!(matches!(read_options(&[0, 10, 0, 9, 1, 1, 1, 1, 1, 1, 1, 1, 1]), Err(NetDoctorError::MalformedPacket(_))));
+        // ipv4 address of 5 bytes
+        assert!(matches!(read_options(&[0, 8, 0, 9, 0, 1, 32, 0, 1, 1, 1, 1, 1]), Err(NetDoctorError::MalformedPacket(_))));
+    }
+}
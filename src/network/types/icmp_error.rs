@@ -0,0 +1,426 @@
+use std::{fmt, net::{IpAddr, Ipv4Addr, Ipv6Addr}};
+
+use pnet::packet::{Packet, ip::{IpNextHeaderProtocol, IpNextHeaderProtocols}, ipv4::Ipv4Packet};
+use serde::Serialize;
+
+use crate::utils::serde_utils;
+
+const ICMP_HEADER_SIZE: usize = 8;
+const IPV6_HEADER_SIZE: usize = 40;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+
+/**
+ * Kind of icmp error
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IcmpErrorKind {
+    NetworkUnreachable,
+    HostUnreachable,
+    ProtocolUnreachable,
+    PortUnreachable,
+    // next-hop mtu of ipv4 (0 if router does not tell)
+    FragmentationNeeded(u16),
+    // mtu of ipv6 link
+    PacketTooBig(u32),
+    SourceRouteFailed,
+    NetworkUnknown,
+    HostUnknown,
+    AdminProhibited,
+    BeyondScope,
+    // other destination unreachable code
+    Unreachable(u8),
+    TtlExceeded,
+    ReassemblyTimeExceeded,
+    // better gateway
+    Redirect(IpAddr),
+    // octet offset of the problem in original packet
+    ParameterProblem(u32)
+}
+
+/**
+ * Header of the original packet carried by icmp error
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OriginalHeader {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    #[serde(serialize_with = "serde_utils::protocol")]
+    pub protocol: IpNextHeaderProtocol,
+    // identifier and sequence number of icmp echo request
+    pub echo: Option<(u16, u16)>,
+    // source and destination port of tcp and udp
+    pub ports: Option<(u16, u16)>
+}
+
+/**
+ * Icmp error message with the original header which triggered it
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IcmpError {
+    // router or host which sent the error
+    pub source: IpAddr,
+    pub icmp_type: u8,
+    pub icmp_code: u8,
+    pub kind: IcmpErrorKind,
+    pub original: Option<OriginalHeader>
+}
+
+impl IcmpError {
+    /**
+     * Parse icmp error in ipv4 packet, None if it is not an icmp error
+     */
+    pub fn from_ipv4(packet: &Ipv4Packet) -> Option<IcmpError> {
+        if packet.get_next_level_protocol() != IpNextHeaderProtocols::Icmp {
+            return None;
+        }
+        let icmp = packet.payload();
+        if icmp.len() < ICMP_HEADER_SIZE {
+            return None;
+        }
+        let (icmp_type, icmp_code) = (icmp[0], icmp[1]);
+        let rest = u32::from_be_bytes([icmp[4], icmp[5], icmp[6], icmp[7]]);
+        let kind = error_kind_v4(icmp_type, icmp_code, rest)?;
+        Some(IcmpError {
+            source: IpAddr::V4(packet.get_source()),
+            icmp_type,
+            icmp_code,
+            kind,
+            original: OriginalHeader::from_ipv4(&icmp[ICMP_HEADER_SIZE..])
+        })
+    }
+
+    /**
+     * Parse icmpv6 error without ipv6 header, None if it is not an icmpv6 error
+     */
+    pub fn from_icmpv6(icmp: &[u8], source: Ipv6Addr) -> Option<IcmpError> {
+        if icmp.len() < ICMP_HEADER_SIZE {
+            return None;
+        }
+        let (icmp_type, icmp_code) = (icmp[0], icmp[1]);
+        let rest = u32::from_be_bytes([icmp[4], icmp[5], icmp[6], icmp[7]]);
+        let kind = error_kind_v6(icmp_type, icmp_code, rest)?;
+        Some(IcmpError {
+            source: IpAddr::V6(source),
+            icmp_type,
+            icmp_code,
+            kind,
+            original: OriginalHeader::from_ipv6(&icmp[ICMP_HEADER_SIZE..])
+        })
+    }
+
+    /**
+     * Make icmp error from the one queued by IP_RECVERR or IPV6_RECVERR.
+     * Kernel gives the info field in host order, and the request without ip header.
+     */
+    pub fn from_queued(source: IpAddr, icmp_type: u8, icmp_code: u8, info: u32,
+                       local: IpAddr, destination: IpAddr, request: &[u8]) -> Option<IcmpError> {
+        let (kind, protocol) = match source {
+            // pointer of parameter problem is already shifted
+            IpAddr::V4(_) if icmp_type == 12 => (error_kind_v4(icmp_type, icmp_code, info << 24)?, IpNextHeaderProtocols::Icmp),
+            IpAddr::V4(_) => (error_kind_v4(icmp_type, icmp_code, info)?, IpNextHeaderProtocols::Icmp),
+            IpAddr::V6(_) => (error_kind_v6(icmp_type, icmp_code, info)?, IpNextHeaderProtocols::Icmpv6)
+        };
+        Some(IcmpError {
+            source,
+            icmp_type,
+            icmp_code,
+            kind,
+            original: Some(OriginalHeader::new(local, destination, protocol, request))
+        })
+    }
+
+    /**
+     * Check the error is triggered by the icmp echo request
+     */
+    pub fn is_echo_of(&self, identifier: u16, sequence: u16) -> bool {
+        matches!(&self.original, Some(original) if original.echo == Some((identifier, sequence)))
+    }
+
+    /**
+     * Human readable diagnosis of the error
+     */
+    pub fn diagnosis(&self) -> String {
+        match self.kind {
+            IcmpErrorKind::NetworkUnreachable =>
+                "Destination Net Unreachable, a router has no route to the target network".to_string(),
+            IcmpErrorKind::HostUnreachable =>
+                "Destination Host Unreachable, the last router could not deliver to the host".to_string(),
+            IcmpErrorKind::ProtocolUnreachable =>
+                "Destination Protocol Unreachable, the target does not support the protocol".to_string(),
+            IcmpErrorKind::PortUnreachable =>
+                "Destination Port Unreachable, no service is listening on the port".to_string(),
+            IcmpErrorKind::FragmentationNeeded(mtu) =>
+                format!("Fragmentation Needed and DF set, next-hop mtu={}", mtu),
+            IcmpErrorKind::PacketTooBig(mtu) =>
+                format!("Packet Too Big, mtu={}", mtu),
+            IcmpErrorKind::SourceRouteFailed =>
+                "Source Route Failed".to_string(),
+            IcmpErrorKind::NetworkUnknown =>
+                "Destination Net Unknown".to_string(),
+            IcmpErrorKind::HostUnknown =>
+                "Destination Host Unknown".to_string(),
+            IcmpErrorKind::AdminProhibited =>
+                "Communication Administratively Prohibited, blocked by a firewall or ACL".to_string(),
+            IcmpErrorKind::BeyondScope =>
+                "Beyond Scope of Source Address".to_string(),
+            IcmpErrorKind::Unreachable(code) =>
+                format!("Destination Unreachable, code={}", code),
+            IcmpErrorKind::TtlExceeded =>
+                "Time to live exceeded in transit, routing loop or too small ttl".to_string(),
+            IcmpErrorKind::ReassemblyTimeExceeded =>
+                "Fragment reassembly time exceeded, some fragments were lost".to_string(),
+            IcmpErrorKind::Redirect(gateway) =>
+                format!("Redirect, use gateway {}", gateway),
+            IcmpErrorKind::ParameterProblem(pointer) =>
+                format!("Parameter Problem, bad header at byte {}", pointer)
+        }
+    }
+}
+
+/**
+ * Kind of icmp error by type, code and the rest of header
+ */
+fn error_kind_v4(icmp_type: u8, icmp_code: u8, rest: u32) -> Option<IcmpErrorKind> {
+    let kind = match (icmp_type, icmp_code) {
+        (3, 0) => IcmpErrorKind::NetworkUnreachable,
+        (3, 1) => IcmpErrorKind::HostUnreachable,
+        (3, 2) => IcmpErrorKind::ProtocolUnreachable,
+        (3, 3) => IcmpErrorKind::PortUnreachable,
+        (3, 4) => IcmpErrorKind::FragmentationNeeded((rest & 0xffff) as u16),
+        (3, 5) => IcmpErrorKind::SourceRouteFailed,
+        (3, 6) => IcmpErrorKind::NetworkUnknown,
+        (3, 7) => IcmpErrorKind::HostUnknown,
+        (3, 9) | (3, 10) | (3, 13) => IcmpErrorKind::AdminProhibited,
+        (3, code) => IcmpErrorKind::Unreachable(code),
+        (5, _) => IcmpErrorKind::Redirect(IpAddr::V4(Ipv4Addr::from(rest))),
+        (11, 0) => IcmpErrorKind::TtlExceeded,
+        (11, _) => IcmpErrorKind::ReassemblyTimeExceeded,
+        (12, _) => IcmpErrorKind::ParameterProblem(rest >> 24),
+        _ => return None
+    };
+    Some(kind)
+}
+
+/**
+ * Kind of icmpv6 error by type, code and the rest of header
+ */
+fn error_kind_v6(icmp_type: u8, icmp_code: u8, rest: u32) -> Option<IcmpErrorKind> {
+    let kind = match (icmp_type, icmp_code) {
+        (1, 0) => IcmpErrorKind::NetworkUnreachable,
+        (1, 1) | (1, 5) | (1, 6) => IcmpErrorKind::AdminProhibited,
+        (1, 2) => IcmpErrorKind::BeyondScope,
+        (1, 3) => IcmpErrorKind::HostUnreachable,
+        (1, 4) => IcmpErrorKind::PortUnreachable,
+        (1, code) => IcmpErrorKind::Unreachable(code),
+        (2, _) => IcmpErrorKind::PacketTooBig(rest),
+        (3, 0) => IcmpErrorKind::TtlExceeded,
+        (3, _) => IcmpErrorKind::ReassemblyTimeExceeded,
+        (4, _) => IcmpErrorKind::ParameterProblem(rest),
+        _ => return None
+    };
+    Some(kind)
+}
+
+impl fmt::Display for IcmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "From {}: {}", self.source, self.diagnosis())?;
+        if let Some(original) = &self.original {
+            write!(f, " (original {})", original)?;
+        }
+        Ok(())
+    }
+}
+
+impl OriginalHeader {
+    /**
+     * Parse original ipv4 header and first 8 bytes of its payload
+     */
+    fn from_ipv4(buff: &[u8]) -> Option<OriginalHeader> {
+        let ip_packet = Ipv4Packet::new(buff)?;
+        let header_length = ip_packet.get_header_length() as usize * 4;
+        let protocol = ip_packet.get_next_level_protocol();
+        Some(OriginalHeader::new(
+            IpAddr::V4(ip_packet.get_source()),
+            IpAddr::V4(ip_packet.get_destination()),
+            protocol,
+            buff.get(header_length..).unwrap_or_default()
+        ))
+    }
+
+    /**
+     * Parse original ipv6 header and the beginning of its payload
+     */
+    fn from_ipv6(buff: &[u8]) -> Option<OriginalHeader> {
+        if buff.len() < IPV6_HEADER_SIZE {
+            return None;
+        }
+        let source: [u8; 16] = buff[8..24].try_into().ok()?;
+        let destination: [u8; 16] = buff[24..40].try_into().ok()?;
+        Some(OriginalHeader::new(
+            IpAddr::V6(Ipv6Addr::from(source)),
+            IpAddr::V6(Ipv6Addr::from(destination)),
+            IpNextHeaderProtocol::new(buff[6]),
+            &buff[IPV6_HEADER_SIZE..]
+        ))
+    }
+
+    fn new(source: IpAddr, destination: IpAddr, protocol: IpNextHeaderProtocol, payload: &[u8]) -> OriginalHeader {
+        let mut header = OriginalHeader { source, destination, protocol, echo: None, ports: None };
+        if payload.len() < 8 {
+            return header;
+        }
+        let first = u16::from_be_bytes([payload[0], payload[1]]);
+        let second = u16::from_be_bytes([payload[2], payload[3]]);
+        let identifier = u16::from_be_bytes([payload[4], payload[5]]);
+        let sequence = u16::from_be_bytes([payload[6], payload[7]]);
+        match protocol {
+            IpNextHeaderProtocols::Icmp if payload[0] == ICMP_ECHO_REQUEST => {
+                header.echo = Some((identifier, sequence));
+            }
+            IpNextHeaderProtocols::Icmpv6 if payload[0] == ICMPV6_ECHO_REQUEST => {
+                header.echo = Some((identifier, sequence));
+            }
+            IpNextHeaderProtocols::Tcp | IpNextHeaderProtocols::Udp => {
+                header.ports = Some((first, second));
+            }
+            _ => {}
+        }
+        header
+    }
+}
+
+impl fmt::Display for OriginalHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} {}", self.source, self.destination, self.protocol)?;
+        if let Some((identifier, sequence)) = self.echo {
+            write!(f, " id={} seq={}", identifier, sequence)?;
+        }
+        if let Some((source_port, destination_port)) = self.ports {
+            write!(f, " sport={} dport={}", source_port, destination_port)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Make ipv4 packet with the payload, for tests
+ */
+#[cfg(test)]
+pub(crate) fn make_ipv4(source: Ipv4Addr, destination: Ipv4Addr, protocol: IpNextHeaderProtocol, payload: &[u8]) -> Vec<u8> {
+    let total_length = 20 + payload.len();
+    let mut buff: Vec<u8> = vec![0; total_length];
+    let mut packet = pnet::packet::ipv4::MutableIpv4Packet::new(&mut buff).unwrap();
+    packet.set_version(4);
+    packet.set_header_length(5);
+    packet.set_total_length(total_length as u16);
+    packet.set_next_level_protocol(protocol);
+    packet.set_source(source);
+    packet.set_destination(destination);
+    packet.set_payload(payload);
+    buff
+}
+
+/**
+ * Wrap the original packet in icmp error sent by the router, for tests
+ */
+#[cfg(test)]
+pub(crate) fn make_icmp_error(router: Ipv4Addr, icmp_type: u8, icmp_code: u8, rest: u32, original: &[u8]) -> Vec<u8> {
+    let mut icmp = vec![icmp_type, icmp_code, 0, 0];
+    icmp.extend_from_slice(&rest.to_be_bytes());
+    icmp.extend_from_slice(original);
+    // error is sent back to the source of the original packet
+    let destination = Ipv4Packet::new(original).map_or(Ipv4Addr::UNSPECIFIED, |packet| packet.get_source());
+    make_ipv4(router, destination, IpNextHeaderProtocols::Icmp, &icmp)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn from_ipv4_fragmentation_needed_test() {
+        let echo = [8, 0, 0, 0, 0x12, 0x34, 0, 5];
+        let original = make_ipv4(Ipv4Addr::new(192, 0, 2, 2), Ipv4Addr::new(198, 51, 100, 1), IpNextHeaderProtocols::Icmp, &echo);
+        let buff = make_icmp_error(Ipv4Addr::new(10, 0, 0, 1), 3, 4, 1400, &original);
+        let error = IcmpError::from_ipv4(&Ipv4Packet::new(&buff).unwrap()).unwrap();
+
+        assert_eq!(error.kind, IcmpErrorKind::FragmentationNeeded(1400));
+        assert_eq!(error.source, IpAddr::from_str("10.0.0.1").unwrap());
+        let orig = error.original.as_ref().unwrap();
+        assert_eq!(orig.destination, IpAddr::from_str("198.51.100.1").unwrap());
+        assert_eq!(orig.echo, Some((0x1234, 5)));
+        assert!(error.is_echo_of(0x1234, 5));
+        assert!(!error.is_echo_of(0x1234, 6));
+    }
+
+    #[test]
+    fn from_ipv4_port_unreachable_test() {
+        let udp = [0x30, 0x39, 0, 53, 0, 8, 0, 0];
+        let original = make_ipv4(Ipv4Addr::new(192, 0, 2, 2), Ipv4Addr::new(198, 51, 100, 1), IpNextHeaderProtocols::Udp, &udp);
+        let buff = make_icmp_error(Ipv4Addr::new(10, 0, 0, 1), 3, 3, 0, &original);
+        let error = IcmpError::from_ipv4(&Ipv4Packet::new(&buff).unwrap()).unwrap();
+
+        assert_eq!(error.kind, IcmpErrorKind::PortUnreachable);
+        assert_eq!(error.original.as_ref().unwrap().ports, Some((12345, 53)));
+        assert_eq!(error.to_string(), "From 10.0.0.1: Destination Port Unreachable, no service is listening on the port \
+            (original 192.0.2.2 -> 198.51.100.1 Udp sport=12345 dport=53)");
+    }
+
+    #[test]
+    fn from_ipv4_other_kinds_test() {
+        let parse = |icmp_type, icmp_code, rest| {
+            let buff = make_icmp_error(Ipv4Addr::new(10, 0, 0, 1), icmp_type, icmp_code, rest, &[]);
+            IcmpError::from_ipv4(&Ipv4Packet::new(&buff).unwrap()).map(|e| e.kind)
+        };
+        assert_eq!(parse(3, 13, 0), Some(IcmpErrorKind::AdminProhibited));
+        assert_eq!(parse(5, 1, 0xc0000201), Some(IcmpErrorKind::Redirect(IpAddr::from_str("192.0.2.1").unwrap())));
+        assert_eq!(parse(11, 1, 0), Some(IcmpErrorKind::ReassemblyTimeExceeded));
+        assert_eq!(parse(12, 0, 9 << 24), Some(IcmpErrorKind::ParameterProblem(9)));
+        // echo reply is not an error
+        assert_eq!(parse(0, 0, 0), None);
+    }
+
+    #[test]
+    fn from_queued_test() {
+        let local = IpAddr::from_str("0.0.0.0").unwrap();
+        let destination = IpAddr::from_str("198.51.100.1").unwrap();
+        let echo = [8, 0, 0, 0, 0x12, 0x34, 0, 5];
+        let error = IcmpError::from_queued(IpAddr::from_str("10.0.0.1").unwrap(), 11, 0, 0, local, destination, &echo).unwrap();
+        assert_eq!(error.kind, IcmpErrorKind::TtlExceeded);
+        assert_eq!(error.original.as_ref().unwrap().destination, destination);
+        assert!(error.is_echo_of(0x1234, 5));
+
+        let parse = |icmp_type, icmp_code, info| {
+            IcmpError::from_queued(IpAddr::from_str("10.0.0.1").unwrap(), icmp_type, icmp_code, info, local, destination, &echo)
+                .map(|e| e.kind)
+        };
+        assert_eq!(parse(3, 4, 1400), Some(IcmpErrorKind::FragmentationNeeded(1400)));
+        assert_eq!(parse(12, 0, 9), Some(IcmpErrorKind::ParameterProblem(9)));
+
+        let echo_v6 = [128, 0, 0, 0, 0, 7, 0, 1];
+        let error = IcmpError::from_queued(IpAddr::from_str("2001:db8::fe").unwrap(), 2, 0, 1280,
+                                           IpAddr::from_str("::").unwrap(), IpAddr::from_str("2001:db8::1").unwrap(), &echo_v6).unwrap();
+        assert_eq!(error.kind, IcmpErrorKind::PacketTooBig(1280));
+        assert!(error.is_echo_of(7, 1));
+    }
+
+    #[test]
+    fn from_icmpv6_packet_too_big_test() {
+        let mut original: Vec<u8> = vec![0; IPV6_HEADER_SIZE];
+        original[6] = IpNextHeaderProtocols::Icmpv6.0;
+        original[8..24].copy_from_slice(&Ipv6Addr::from_str("2001:db8::2").unwrap().octets());
+        original[24..40].copy_from_slice(&Ipv6Addr::from_str("2001:db8::1").unwrap().octets());
+        original.extend_from_slice(&[128, 0, 0, 0, 0, 7, 0, 1]);
+        let mut icmp = vec![2, 0, 0, 0];
+        icmp.extend_from_slice(&1280u32.to_be_bytes());
+        icmp.extend_from_slice(&original);
+
+        let error = IcmpError::from_icmpv6(&icmp, Ipv6Addr::from_str("2001:db8::fe").unwrap()).unwrap();
+        assert_eq!(error.kind, IcmpErrorKind::PacketTooBig(1280));
+        assert!(error.is_echo_of(7, 1));
+    }
+}
@@ -1,26 +1,47 @@
-use std::{net::{IpAddr, Ipv4Addr, UdpSocket, SocketAddr, SocketAddrV4}, str::FromStr, vec, time::Duration, os::fd::{FromRawFd, AsRawFd}, char::MAX};
+use std::{fmt, io, sync::atomic::{AtomicBool, Ordering}, net::{IpAddr, Ipv4Addr, UdpSocket, SocketAddr, SocketAddrV4}, vec, time::Duration};
 use log::{error, debug};
 
-use pnet::{packet::{ipv4::{MutableIpv4Packet, Ipv4Packet}, Packet, ip::IpNextHeaderProtocols, udp::{MutableUdpPacket, UdpPacket, ipv4_checksum}, ethernet::{MutableEthernetPacket, EtherTypes}}, transport::{transport_channel, udp_packet_iter, ipv4_packet_iter}};
+use pnet::packet::{ipv4::{MutableIpv4Packet, Ipv4Packet}, Packet, ip::IpNextHeaderProtocols, udp::{MutableUdpPacket, ipv4_checksum}};
 use rand::Rng;
+use serde::Serialize;
 
-use crate::{types::{BaseConfig, EchoConfig}, utils::{os_utils, ip_utils::get_ip_from_nic}};
+use crate::{error::Result, types::BaseConfig};
+
+use super::dns::resolve_target_v4;
 
 const MAX_PACKET_SIZE: usize = 64;
 const UDP_ECHO_PORT: u16 = 7;
+const ECHO_COUNT: u32 = 4;
+
+/**
+ * Report of udp echo
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct EchoReport {
+    pub destination: Ipv4Addr,
+    pub transmitted: u32,
+    pub received: u32
+}
+
+impl fmt::Display for EchoReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ECHO: {} datagrams transmitted to {}, {} echoed", self.transmitted, self.destination, self.received)
+    }
+}
 
 /**
  * send Udp echo
  */
-pub fn udp_echo(config: BaseConfig) {
+pub fn udp_echo(config: BaseConfig) -> Result<EchoReport> {
+
+    let dest_ip = resolve_target_v4(&config.ip, &config.resolve)?;
 
     let mut ip_buff: Vec<u8> = vec![0; MAX_PACKET_SIZE + 20];
     let mut ip_packet: MutableIpv4Packet = MutableIpv4Packet::new(&mut ip_buff).unwrap();
-    make_ip_packet(&mut ip_packet, &config);
+    make_ip_packet(&mut ip_packet, dest_ip);
 
-    let port: u16 = rand::thread_rng().gen();
-    let socket = UdpSocket::bind("127.0.0.1:".to_string() + port.to_string().as_str()).unwrap();
-    let addr = socket.local_addr().unwrap();
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let addr = socket.local_addr()?;
     let mut udp_buff: Vec<u8> = vec![0; MAX_PACKET_SIZE];
     let mut udp_packet: MutableUdpPacket = MutableUdpPacket::new(&mut udp_buff).unwrap();
     make_udp_packet(&mut udp_packet, &addr);
@@ -28,39 +49,58 @@ pub fn udp_echo(config: BaseConfig) {
 
     ip_packet.set_payload(udp_packet.packet());
 
-    socket.set_read_timeout(Some(Duration::from_millis(3000)));
+    socket.set_read_timeout(Some(Duration::from_millis(3000)))?;
 
-    rayon::join(|| {
-        for i in 1..5 {
+    let is_sent = AtomicBool::new(false);
+    let (transmitted, received) = rayon::join(|| -> io::Result<u32> {
+        let mut transmitted = 0;
+        for i in 1..=ECHO_COUNT {
             let send_packet = Ipv4Packet::new(ip_packet.packet()).unwrap();
             debug!("ECHO: send_packet {:?}", i);
-            let dest_ip = config.ip.clone() + ":7";
-            socket.send_to(send_packet.packet(), SocketAddr::V4(SocketAddrV4::from_str(&dest_ip).unwrap())).unwrap();
-            std::thread::sleep(Duration::from_millis(1000));
+            if let Err(e) = socket.send_to(send_packet.packet(), SocketAddr::V4(SocketAddrV4::new(dest_ip, UDP_ECHO_PORT))) {
+                // let the receiver stop
+                is_sent.store(true, Ordering::Release);
+                return Err(e);
+            }
+            transmitted += 1;
+            if i < ECHO_COUNT {
+                std::thread::sleep(Duration::from_millis(1000));
+            }
         }
+        is_sent.store(true, Ordering::Release);
+        Ok(transmitted)
     },
      || {
-
-        loop {
-            let mut buff: Vec<u8> = vec![0; MAX_PACKET_SIZE];
+        let mut received = 0;
+        // stop when the last echo is received or timed out
+        while received < ECHO_COUNT {
+            let mut buff: Vec<u8> = vec![0; MAX_PACKET_SIZE + 20];
             match socket.recv_from(&mut buff) {
-                Ok(_) => {
+                Ok((_, source)) if source.ip() == IpAddr::V4(dest_ip) => {
                     let packet = Ipv4Packet::new(&buff);
                     debug!("ECHO: {:?}", packet.unwrap());
+                    received += 1;
                 }
+                Ok(_) => {}
                 Err(e) => {
+                    if is_sent.load(Ordering::Acquire) {
+                        break;
+                    }
                     error!("ECHO: {:?}", e);
                 }
             }
         }
+        received
     });
 
-
-
-
+    Ok(EchoReport {
+        destination: dest_ip,
+        transmitted: transmitted?,
+        received
+    })
 }
 
-fn make_ip_packet(packet: &mut MutableIpv4Packet, config: &BaseConfig) {
+fn make_ip_packet(packet: &mut MutableIpv4Packet, dest_ip: Ipv4Addr) {
     let id = rand::thread_rng().gen();
     packet.set_version(4);
     packet.set_header_length(5);
@@ -68,7 +108,7 @@ fn make_ip_packet(packet: &mut MutableIpv4Packet, config: &BaseConfig) {
     packet.set_identification(id);
     packet.set_ttl(64);
     packet.set_next_level_protocol(IpNextHeaderProtocols::Udp);
-    packet.set_destination(Ipv4Addr::from_str(&config.ip).unwrap());
+    packet.set_destination(dest_ip);
 }
 
 fn make_udp_packet(packet: &mut MutableUdpPacket, addr: &SocketAddr) {
@@ -0,0 +1,273 @@
+use std::{collections::HashMap, io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket}, sync::{Mutex, atomic::{AtomicBool, Ordering}}, thread, time::{Duration, Instant}};
+use log::{debug, error};
+
+use pnet::{packet::{Packet, ip::IpNextHeaderProtocols}, transport::{transport_channel, TransportChannelType, TransportProtocol, TransportReceiver, ipv4_packet_iter, icmpv6_packet_iter}};
+
+use super::{dns::make_dns_packet, port_scan::PortState, types::{dns_packet::DnsPacket, icmp_error::{IcmpError, IcmpErrorKind}}};
+
+const MAX_PACKET_SIZE: usize = 1500;
+const DNS_PORT: u16 = 53;
+const NTP_PORT: u16 = 123;
+const SNMP_PORT: u16 = 161;
+const NTP_PACKET_SIZE: usize = 48;
+// LI=0, VN=3, Mode=3 (client)
+const NTP_CLIENT_MODE: u8 = 0x1b;
+// SNMPv1 GetRequest of sysDescr.0 with community "public"
+const SNMP_GET_SYS_DESCR: [u8; 43] = [
+    0x30, 0x29,
+    0x02, 0x01, 0x00,
+    0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c',
+    0xa0, 0x1c,
+    0x02, 0x04, 0x00, 0x00, 0x00, 0x01,
+    0x02, 0x01, 0x00,
+    0x02, 0x01, 0x00,
+    0x30, 0x0e,
+    0x30, 0x0c,
+    0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00,
+    0x05, 0x00
+];
+// interval between datagrams (ms), hosts limit icmp errors to about 1 per second
+const UDP_INTERVAL: u64 = 20;
+// poll interval of receivers to check the end of sending
+const UDP_POLL: u64 = 100;
+// unanswered ports are probed again, port unreachable may be rate limited
+const UDP_RETRY: u8 = 3;
+
+/**
+ * Scan udp ports, ports without response are open|filtered.
+ * Results are ordered as ports.
+ */
+pub fn udp_scan(dest_ip: IpAddr, ports: &[u16], timeout: Duration) -> io::Result<Vec<(u16, PortState)>> {
+    let bind_ip = match dest_ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(bind_ip, 0))?;
+    socket.set_read_timeout(Some(Duration::from_millis(UDP_POLL)))?;
+    let source_port = socket.local_addr()?.port();
+
+    // unconnected udp socket does not report icmp errors, read them from raw channel
+    let protocol = match dest_ip {
+        IpAddr::V4(_) => TransportChannelType::Layer3(IpNextHeaderProtocols::Icmp),
+        IpAddr::V6(_) => TransportChannelType::Layer4(TransportProtocol::Ipv6(IpNextHeaderProtocols::Icmpv6))
+    };
+    let (_, mut rx) = transport_channel(4096, protocol)?;
+    debug!("UdpScan: source port {} -> {}", source_port, dest_ip);
+
+    let states: Mutex<HashMap<u16, PortState>> = Mutex::new(HashMap::new());
+    for round in 0..UDP_RETRY {
+        let unanswered: Vec<u16> = {
+            let states = states.lock().unwrap();
+            ports.iter().copied().filter(|port| !states.contains_key(port)).collect()
+        };
+        if unanswered.is_empty() {
+            break;
+        }
+        debug!("UdpScan: round {} {} ports", round + 1, unanswered.len());
+        let is_sent = AtomicBool::new(false);
+        let deadline = || -> Option<Instant> {
+            is_sent.load(Ordering::Acquire).then(|| Instant::now() + timeout)
+        };
+
+        thread::scope(|s| -> io::Result<()> {
+            let icmp_receiver = s.spawn(|| -> io::Result<()> {
+                let mut end: Option<Instant> = None;
+                loop {
+                    end = end.or_else(deadline);
+                    let mut wait = Duration::from_millis(UDP_POLL);
+                    if let Some(end) = end {
+                        wait = end.saturating_duration_since(Instant::now());
+                        if wait.is_zero() {
+                            return Ok(());
+                        }
+                    }
+                    let Some(icmp_error) = next_icmp_error(&mut rx, dest_ip, wait)? else {
+                        continue;
+                    };
+                    if let Some((port, state)) = get_icmp_response(&icmp_error, dest_ip, source_port) {
+                        debug!("UdpScan: {} {}", port, icmp_error);
+                        states.lock().unwrap().entry(port).or_insert(state);
+                    }
+                }
+            });
+            let udp_receiver = s.spawn(|| -> io::Result<()> {
+                let mut buf: Vec<u8> = vec![0; MAX_PACKET_SIZE];
+                let mut end: Option<Instant> = None;
+                loop {
+                    end = end.or_else(deadline);
+                    if matches!(end, Some(end) if Instant::now() >= end) {
+                        return Ok(());
+                    }
+                    match socket.recv_from(&mut buf) {
+                        Ok((size, source)) if source.ip() == dest_ip => {
+                            debug!("UdpScan: {} replied {} bytes", source.port(), size);
+                            states.lock().unwrap().insert(source.port(), PortState::Open);
+                        }
+                        Ok(_) => {}
+                        Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                        // error of previous datagram may be queued on linux
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+                        Err(e) => return Err(e)
+                    }
+                }
+            });
+
+            for port in &unanswered {
+                if let Err(e) = socket.send_to(&udp_payload(*port), SocketAddr::new(dest_ip, *port)) {
+                    error!("UdpScan: {} {:?}", port, e);
+                }
+                thread::sleep(Duration::from_millis(UDP_INTERVAL));
+            }
+            is_sent.store(true, Ordering::Release);
+
+            icmp_receiver.join().unwrap()?;
+            udp_receiver.join().unwrap()
+        })?;
+    }
+
+    let states = states.into_inner().unwrap();
+    Ok(ports.iter().map(|port| (*port, *states.get(port).unwrap_or(&PortState::OpenFiltered))).collect())
+}
+
+/**
+ * Receive next icmp error from raw channel
+ */
+fn next_icmp_error(rx: &mut TransportReceiver, dest_ip: IpAddr, wait: Duration) -> io::Result<Option<IcmpError>> {
+    match dest_ip {
+        IpAddr::V4(_) => {
+            let mut rx = ipv4_packet_iter(rx);
+            let res = rx.next_with_timeout(wait)?;
+            Ok(res.and_then(|(res_packet, _)| IcmpError::from_ipv4(&res_packet)))
+        }
+        IpAddr::V6(_) => {
+            let mut rx = icmpv6_packet_iter(rx);
+            let res = rx.next_with_timeout(wait)?;
+            Ok(res.and_then(|(res_packet, source)| match source {
+                IpAddr::V6(source) => IcmpError::from_icmpv6(res_packet.packet(), source),
+                IpAddr::V4(_) => None
+            }))
+        }
+    }
+}
+
+/**
+ * Check the icmp error is triggered by our datagram, port unreachable is closed and others are filtered
+ */
+fn get_icmp_response(icmp_error: &IcmpError, dest_ip: IpAddr, source_port: u16) -> Option<(u16, PortState)> {
+    let original = icmp_error.original.as_ref()?;
+    if original.protocol != IpNextHeaderProtocols::Udp || original.destination != dest_ip {
+        return None;
+    }
+    let (original_source_port, port) = original.ports?;
+    if original_source_port != source_port {
+        return None;
+    }
+    match icmp_error.kind {
+        IcmpErrorKind::PortUnreachable => Some((port, PortState::Closed)),
+        IcmpErrorKind::HostUnreachable
+            | IcmpErrorKind::ProtocolUnreachable
+            | IcmpErrorKind::NetworkUnreachable
+            | IcmpErrorKind::AdminProhibited
+            | IcmpErrorKind::Unreachable(_) => Some((port, PortState::Filtered)),
+        _ => None
+    }
+}
+
+/**
+ * Payload which the service on the port answers, empty datagram for unknown port
+ */
+pub fn udp_payload(port: u16) -> Vec<u8> {
+    match port {
+        DNS_PORT => {
+            let mut dns_packet = DnsPacket::new();
+            make_dns_packet(&mut dns_packet, "example.com", 1, None);
+            // fixed name is always encoded
+            dns_packet.make_packet().unwrap();
+            dns_packet.packet().to_vec()
+        }
+        NTP_PORT => {
+            let mut buf: Vec<u8> = vec![0; NTP_PACKET_SIZE];
+            buf[0] = NTP_CLIENT_MODE;
+            buf
+        }
+        SNMP_PORT => SNMP_GET_SYS_DESCR.to_vec(),
+        _ => Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pnet::packet::ip::IpNextHeaderProtocol;
+
+    use crate::network::types::icmp_error::OriginalHeader;
+
+    use super::*;
+
+    fn make_port_unreachable(kind: IcmpErrorKind, protocol: IpNextHeaderProtocol, ports: (u16, u16)) -> IcmpError {
+        IcmpError {
+            source: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            icmp_type: 3,
+            icmp_code: 3,
+            kind,
+            original: Some(OriginalHeader {
+                source: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)),
+                destination: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+                protocol,
+                echo: None,
+                ports: Some(ports)
+            })
+        }
+    }
+
+    #[test]
+    fn udp_payload_test() {
+        let dns = udp_payload(53);
+        // one question
+        assert_eq!(&dns[4..6], &[0, 1]);
+        let ntp = udp_payload(123);
+        assert_eq!(ntp.len(), NTP_PACKET_SIZE);
+        assert_eq!(ntp[0] & 0x07, 3);
+        let snmp = udp_payload(161);
+        // length of sequence covers the message
+        assert_eq!(snmp[1] as usize, snmp.len() - 2);
+        assert_eq!(snmp[14] as usize, snmp.len() - 15);
+        assert!(udp_payload(9999).is_empty());
+    }
+
+    #[test]
+    fn get_icmp_response_test() {
+        let dest_ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let udp = IpNextHeaderProtocols::Udp;
+        let icmp_error = make_port_unreachable(IcmpErrorKind::PortUnreachable, udp, (40000, 161));
+        assert_eq!(get_icmp_response(&icmp_error, dest_ip, 40000), Some((161, PortState::Closed)));
+        // other socket
+        assert_eq!(get_icmp_response(&icmp_error, dest_ip, 40001), None);
+        // other target
+        assert_eq!(get_icmp_response(&icmp_error, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 9)), 40000), None);
+
+        let icmp_error = make_port_unreachable(IcmpErrorKind::AdminProhibited, udp, (40000, 161));
+        assert_eq!(get_icmp_response(&icmp_error, dest_ip, 40000), Some((161, PortState::Filtered)));
+
+        let icmp_error = make_port_unreachable(IcmpErrorKind::PortUnreachable, IpNextHeaderProtocols::Tcp, (40000, 161));
+        assert_eq!(get_icmp_response(&icmp_error, dest_ip, 40000), None);
+    }
+
+    #[test]
+    #[ignore = "needs raw socket"]
+    fn udp_scan_test() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let open_port = server.local_addr().unwrap().port();
+        let closed_port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let echo = thread::spawn(move || {
+            let mut buf = [0; 64];
+            let (size, source) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..size], source).unwrap();
+        });
+
+        let results = udp_scan(IpAddr::V4(Ipv4Addr::LOCALHOST), &[open_port, closed_port], Duration::from_millis(500)).unwrap();
+        assert!(results.contains(&(closed_port, PortState::Closed)));
+        // empty datagram is echoed back
+        assert!(results.contains(&(open_port, PortState::Open)));
+        echo.join().unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+use std::{cell::Cell, fmt::Display, time::{Instant, SystemTime, UNIX_EPOCH}};
+use log::{error, info};
+
+use serde::Serialize;
+
+use crate::{error::{NetDoctorError, Result}, network::{arp::ArpReply, dns::DnsResponse, dns_trace::{DnsTraceReport, TraceStep}, dnssec::{ChainLink, DnssecReport}, ping::{PingProbe, PingReport}, pmtu::PmtuReport, port_scan::{PortResult, PortScanReport}, sweep::{SweepHost, SweepReport}, traceroute::{TracerouteHop, TracerouteReport}, types::dns_packet::DnsAnswer, udp_echo::EchoReport}, types::OutputFormat};
+
+// bumped when a field is removed or changes its meaning
+pub const SCHEMA_VERSION: u32 = 1;
+
+/**
+ * Report of a command, printed as log lines or json document
+ */
+pub trait Report: Serialize + Display {
+    type Record: Serialize;
+
+    /**
+     * Records which ndjson emits one per line before the result
+     */
+    fn records(&self) -> &[Self::Record] {
+        &[]
+    }
+}
+
+impl Report for PingReport {
+    type Record = PingProbe;
+
+    fn records(&self) -> &[PingProbe] {
+        &self.probes
+    }
+}
+
+impl Report for TracerouteReport {
+    type Record = TracerouteHop;
+
+    fn records(&self) -> &[TracerouteHop] {
+        &self.hops
+    }
+}
+
+impl Report for SweepReport {
+    type Record = SweepHost;
+
+    fn records(&self) -> &[SweepHost] {
+        &self.hosts
+    }
+}
+
+impl Report for PortScanReport {
+    type Record = PortResult;
+
+    fn records(&self) -> &[PortResult] {
+        &self.ports
+    }
+}
+
+impl Report for DnsResponse {
+    type Record = DnsAnswer;
+
+    fn records(&self) -> &[DnsAnswer] {
+        &self.answers
+    }
+}
+
+impl Report for DnsTraceReport {
+    type Record = TraceStep;
+
+    fn records(&self) -> &[TraceStep] {
+        &self.steps
+    }
+}
+
+impl Report for DnssecReport {
+    type Record = ChainLink;
+
+    fn records(&self) -> &[ChainLink] {
+        &self.links
+    }
+}
+
+impl Report for PmtuReport {
+    type Record = ();
+}
+
+impl Report for ArpReply {
+    type Record = ();
+}
+
+impl Report for EchoReport {
+    type Record = ();
+}
+
+/**
+ * A record line of ndjson
+ */
+#[derive(Serialize)]
+struct RecordLine<'a, R: Serialize> {
+    schema_version: u32,
+    #[serde(rename = "type")]
+    line_type: &'static str,
+    command: &'a str,
+    target: &'a str,
+    record: &'a R
+}
+
+/**
+ * Result document of a command
+ */
+#[derive(Serialize)]
+struct Document<'a, T: Serialize> {
+    schema_version: u32,
+    #[serde(rename = "type")]
+    line_type: &'static str,
+    command: &'a str,
+    target: &'a str,
+    interface: Option<&'a str>,
+    // "ok" or "error"
+    status: &'static str,
+    // unix time (ms) of the command start
+    started_at: u64,
+    elapsed_ms: f64,
+    result: Option<&'a T>,
+    error: Option<ErrorDetail>
+}
+
+/**
+ * Error of the command
+ */
+#[derive(Serialize)]
+struct ErrorDetail {
+    kind: &'static str,
+    message: String,
+    exit_code: i32
+}
+
+impl From<&NetDoctorError> for ErrorDetail {
+    fn from(e: &NetDoctorError) -> Self {
+        ErrorDetail { kind: e.kind(), message: e.to_string(), exit_code: e.exit_code() }
+    }
+}
+
+/**
+ * Printer of a command result
+ */
+pub struct Output {
+    format: OutputFormat,
+    command: &'static str,
+    target: String,
+    interface: Option<String>,
+    started_at: SystemTime,
+    start: Instant,
+    // number of records streamed while the command runs
+    streamed: Cell<usize>
+}
+
+impl Output {
+    pub fn new(format: OutputFormat, command: &'static str, target: &str, interface: Option<&str>) -> Self {
+        Output {
+            format,
+            command,
+            target: target.to_string(),
+            interface: interface.map(|name| name.to_string()),
+            started_at: SystemTime::now(),
+            start: Instant::now(),
+            streamed: Cell::new(0)
+        }
+    }
+
+    /**
+     * Stream a record as soon as it is available, only ndjson prints it
+     */
+    pub fn record(&self, record: &impl Serialize) {
+        if self.format == OutputFormat::Ndjson {
+            println!("{}", self.record_line(record));
+            self.streamed.set(self.streamed.get() + 1);
+        }
+    }
+
+    /**
+     * Print the result of the command
+     */
+    pub fn report<T: Report>(&self, report: &Result<T>) {
+        match self.format {
+            OutputFormat::Text => match report {
+                Ok(report) => {
+                    for line in report.to_string().lines() {
+                        info!("{}", line);
+                    }
+                }
+                Err(e) => error!("{}", e)
+            },
+            OutputFormat::Json | OutputFormat::Ndjson => {
+                for line in self.render(report) {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+
+    /**
+     * Json lines of the result, records are omitted when they are already streamed
+     */
+    fn render<T: Report>(&self, report: &Result<T>) -> Vec<String> {
+        let document = Document {
+            schema_version: SCHEMA_VERSION,
+            line_type: "result",
+            command: self.command,
+            target: &self.target,
+            interface: self.interface.as_deref(),
+            status: if report.is_ok() { "ok" } else { "error" },
+            started_at: self.started_at.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or_default(),
+            elapsed_ms: self.start.elapsed().as_secs_f64() * 1000.0,
+            result: report.as_ref().ok(),
+            error: report.as_ref().err().map(ErrorDetail::from)
+        };
+        if self.format == OutputFormat::Json {
+            return vec![serde_json::to_string_pretty(&document).unwrap()];
+        }
+
+        let mut lines: Vec<String> = Vec::new();
+        if let (Ok(report), 0) = (report, self.streamed.get()) {
+            lines.extend(report.records().iter().map(|record| self.record_line(record)));
+        }
+        lines.push(serde_json::to_string(&document).unwrap());
+        lines
+    }
+
+    fn record_line(&self, record: &impl Serialize) -> String {
+        let line = RecordLine {
+            schema_version: SCHEMA_VERSION,
+            line_type: "record",
+            command: self.command,
+            target: &self.target,
+            record
+        };
+        serde_json::to_string(&line).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::{IpAddr, Ipv4Addr}, time::Duration};
+
+    use serde_json::Value;
+
+    use crate::{network::port_scan::PortState, types::ScanMode};
+
+    use super::*;
+
+    fn make_port_scan_report() -> PortScanReport {
+        PortScanReport {
+            destination: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            mode: ScanMode::Udp,
+            ports: vec![
+                PortResult { port: 53, state: PortState::Open, banner: None },
+                PortResult { port: 161, state: PortState::OpenFiltered, banner: None }
+            ],
+            time: Duration::from_millis(1500)
+        }
+    }
+
+    #[test]
+    fn render_json_test() {
+        let output = Output::new(OutputFormat::Json, "port_scan", "192.0.2.1", None);
+        let lines = output.render(&Ok(make_port_scan_report()));
+        assert_eq!(lines.len(), 1);
+        let document: Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(document["schema_version"], SCHEMA_VERSION);
+        assert_eq!(document["status"], "ok");
+        assert_eq!(document["interface"], Value::Null);
+        assert_eq!(document["result"]["mode"], "udp");
+        assert_eq!(document["result"]["time_ms"], 1500.0);
+        assert_eq!(document["result"]["ports"][1]["state"], "open|filtered");
+        assert_eq!(document["error"], Value::Null);
+
+        let report: Result<PortScanReport> = Err(NetDoctorError::InvalidArgument("port specification 0".to_string()));
+        let document: Value = serde_json::from_str(&output.render(&report)[0]).unwrap();
+        assert_eq!(document["status"], "error");
+        assert_eq!(document["result"], Value::Null);
+        assert_eq!(document["error"]["kind"], "invalid_argument");
+        assert_eq!(document["error"]["exit_code"], 64);
+    }
+
+    #[test]
+    fn render_ndjson_test() {
+        let output = Output::new(OutputFormat::Ndjson, "port_scan", "192.0.2.1", None);
+        let lines = output.render(&Ok(make_port_scan_report()));
+        let documents: Vec<Value> = lines.iter().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(documents.len(), 3);
+        assert_eq!(documents[0]["type"], "record");
+        assert_eq!(documents[0]["record"]["port"], 53);
+        assert_eq!(documents[2]["type"], "result");
+        assert!(lines.iter().all(|line| !line.contains('\n')));
+
+        // streamed records are not repeated
+        output.streamed.set(2);
+        assert_eq!(output.render(&Ok(make_port_scan_report())).len(), 1);
+    }
+}
@@ -1,11 +1,204 @@
-use clap::builder::Str;
+use std::{fmt, net::IpAddr};
 
+use clap::ValueEnum;
+use serde::Serialize;
+
+/**
+ * Ip version of target
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpVersion {
+    Any,
+    V4,
+    V6
+}
+
+impl IpVersion {
+    pub fn matches(&self, ip: &IpAddr) -> bool {
+        match self {
+            IpVersion::Any => true,
+            IpVersion::V4 => ip.is_ipv4(),
+            IpVersion::V6 => ip.is_ipv6()
+        }
+    }
+}
+
+/**
+ * Resolver of target hostname
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Resolver {
+    // getaddrinfo of os
+    System,
+    // network::dns of this tool
+    Dns
+}
+
+/**
+ * Mode of port scan
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanMode {
+    // full tcp handshake by connect
+    Connect,
+    // half-open scan by raw tcp syn
+    Syn,
+    // udp datagram and icmp port unreachable
+    Udp
+}
+
+/**
+ * Dns record type of query
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RecordType {
+    A,
+    Aaaa,
+    Cname,
+    Mx,
+    Txt,
+    Ns,
+    Soa,
+    Srv,
+    Ptr,
+    Caa,
+    Ds,
+    Rrsig,
+    Dnskey
+}
+
+impl RecordType {
+    /**
+     * TYPE value of the resource record
+     */
+    pub fn code(&self) -> u16 {
+        match self {
+            RecordType::A => 1,
+            RecordType::Ns => 2,
+            RecordType::Cname => 5,
+            RecordType::Soa => 6,
+            RecordType::Ptr => 12,
+            RecordType::Mx => 15,
+            RecordType::Txt => 16,
+            RecordType::Aaaa => 28,
+            RecordType::Srv => 33,
+            RecordType::Ds => 43,
+            RecordType::Rrsig => 46,
+            RecordType::Dnskey => 48,
+            RecordType::Caa => 257
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<RecordType> {
+        RecordType::value_variants().iter().copied().find(|t| t.code() == code)
+    }
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_uppercase())
+    }
+}
+
+/**
+ * Output format of command results
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    // log lines for human
+    Text,
+    // one json document of the result
+    Json,
+    // one json document per line, records first and the result at last
+    Ndjson
+}
+
+/**
+ * Target resolve config
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct ResolveConfig {
+    pub ip_version: IpVersion,
+    pub resolver: Resolver
+}
+
+impl ResolveConfig {
+    /**
+     * Resolve ipv4 address unless ipv6 is forced, for ipv4 only commands
+     */
+    pub fn ipv4_only(&self) -> ResolveConfig {
+        match self.ip_version {
+            IpVersion::Any => ResolveConfig { ip_version: IpVersion::V4, resolver: self.resolver },
+            _ => *self
+        }
+    }
+}
 
 /**
  * Command base config
  */
 pub struct BaseConfig {
-    pub ip: String
+    pub ip: String,
+    pub resolve: ResolveConfig
+}
+
+/**
+ * Ping config
+ */
+pub struct PingConfig {
+    pub ip: String,
+    pub resolve: ResolveConfig,
+    pub count: u16,
+    pub interval: u64,
+    pub ttl: u8
+}
+
+/**
+ * Traceroute config
+ */
+pub struct TracerouteConfig {
+    pub ip: String,
+    pub resolve: ResolveConfig,
+    pub max_hops: u8,
+    pub probes: u8,
+    // look up names of routers
+    pub rdns: bool
+}
+
+/**
+ * Path mtu discovery config
+ */
+pub struct PmtuConfig {
+    pub ip: String,
+    pub resolve: ResolveConfig,
+    pub min_mtu: u16,
+    pub max_mtu: u16
+}
+
+/**
+ * Ping sweep config
+ */
+pub struct SweepConfig {
+    pub target: String,
+    pub rate: u32,
+    pub timeout: u64,
+    // look up names of alive hosts
+    pub rdns: bool
+}
+
+/**
+ * Port scan config
+ */
+pub struct PortScanConfig {
+    pub ip: String,
+    pub resolve: ResolveConfig,
+    pub ports: String,
+    pub mode: ScanMode,
+    pub timeout: u64,
+    pub concurrency: usize,
+    pub banners: bool
 }
 
 /**
@@ -16,11 +209,65 @@ pub struct ArpConfig {
     pub interface_name: String
 }
 
+/**
+ * EDNS0 options of dns queries
+ */
+#[derive(Debug, Clone)]
+pub struct EdnsConfig {
+    // send OPT record
+    pub enabled: bool,
+    pub udp_size: u16,
+    pub dnssec_ok: bool,
+    // client subnet like 192.0.2.0/24
+    pub client_subnet: Option<String>,
+    pub nsid: bool,
+    pub cookie: bool
+}
+
+/**
+ * Encrypted transport of dns queries, DNS-over-TLS or DNS-over-HTTPS
+ */
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    // DNS-over-TLS to port 853 of the servers
+    pub tls: bool,
+    // DNS-over-HTTPS url like https://dns.example/dns-query
+    pub https: Option<String>,
+    // DoH by GET instead of POST
+    pub get: bool,
+    // name of SNI and certificate, host of the url or server address if None
+    pub server_name: Option<String>,
+    // base64 sha256 of SubjectPublicKeyInfo, the server is trusted by any of them instead of CA
+    pub pins: Vec<String>,
+    // PEM file of CA certificates, the built-in roots if None
+    pub ca_file: Option<String>
+}
+
 pub struct DnsConfig {
     pub domain: String,
-    pub interface_name: String
+    pub interface_name: String,
+    pub record_type: RecordType,
+    // server address with optional port, nameservers of resolv.conf if None
+    pub server: Option<String>,
+    // domain is ip address to look up PTR record
+    pub reverse: bool,
+    // query over tcp, otherwise tcp is used only when udp response is truncated
+    pub tcp: bool,
+    pub edns: EdnsConfig,
+    pub tls: TlsConfig,
+    // resolve iteratively from root servers
+    pub trace: bool,
+    // root hints file of trace, installed file or built-in root servers if None
+    pub root_hints: Option<String>,
+    // port of servers in trace
+    pub port: u16,
+    // validate the chain of trust from the answer up to a trust anchor
+    pub dnssec: bool,
+    // file of DS records of trust anchors, the root key signing keys if None
+    pub trust_anchor: Option<String>
 }
 
+#[allow(unused)]
 pub struct EchoConfig {
     pub ip: String,
     pub interface_name: String
@@ -1,3 +1,4 @@
 pub mod os_utils;
 pub mod ip_utils;
-pub mod byte_utils;
\ No newline at end of file
+pub mod byte_utils;
+pub mod serde_utils;
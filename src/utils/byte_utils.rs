@@ -1,7 +1,7 @@
 /**
  * Replace to target bytes vector by bytes vector
  */
-pub fn range_byte_replace<'a>(vec: &'a mut Vec<u8>, start: usize, data: Vec<u8>) -> &'a Vec<u8> {
+pub fn range_byte_replace(vec: &mut Vec<u8>, start: usize, data: Vec<u8>) -> &Vec<u8> {
     let end = start + data.len();
     vec.splice(start..end, data);
     vec
@@ -15,6 +15,23 @@ pub fn get_padding(num: u8, size: usize) -> Vec<u8> {
     vec
 }
 
+/**
+ * Lowercase hex string of bytes
+ */
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/**
+ * Bytes of hex string in either case, None if it is not hex or has odd length
+ */
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -33,4 +50,12 @@ mod tests {
         let v = get_padding(10, 3);
         assert_eq!(v, [0, 0, 10]);
     }
+
+    #[test]
+    fn from_hex_test() {
+        assert_eq!(from_hex("00aBff"), Some(vec![0, 0xab, 0xff]));
+        assert_eq!(to_hex(&from_hex("e06d44").unwrap()), "e06d44");
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
 }
\ No newline at end of file
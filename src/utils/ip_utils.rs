@@ -1,15 +1,33 @@
-use std::net::IpAddr;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 
 use pnet::datalink::NetworkInterface;
 
+use crate::error::{NetDoctorError, Result};
+
+
+/**
+ * Get Ipv4 address from network interface
+ */
+pub fn get_ip_from_nic(nic: &NetworkInterface) -> Result<Ipv4Addr> {
+    nic.ips.iter()
+        .find_map(|ip| match ip.ip() {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None
+        })
+        .ok_or_else(|| NetDoctorError::NoIpv4Address(nic.name.clone()))
+}
 
 /**
- * Get Ip from network interface
+ * Get local ip address used to reach the destination by routing table
  */
-pub fn get_ip_from_nic(nic: &NetworkInterface) -> IpAddr {
-    nic.clone().ips.into_iter()
-        .filter(|ip| ip.is_ipv4())
-        .next()
-        .unwrap()
-        .ip()
-}
\ No newline at end of file
+pub fn get_source_ip(dest_ip: IpAddr) -> io::Result<IpAddr> {
+    let bind_ip = match dest_ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    };
+    // connect of udp socket only selects route, nothing is sent
+    let socket = UdpSocket::bind(SocketAddr::new(bind_ip, 0))?;
+    socket.connect(SocketAddr::new(dest_ip, 9))?;
+    Ok(socket.local_addr()?.ip())
+}
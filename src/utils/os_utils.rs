@@ -1,27 +1,25 @@
 use log::debug;
-use log::error;
 use pnet::datalink::interfaces;
 use pnet::datalink::NetworkInterface;
 
+use crate::error::NetDoctorError;
+use crate::error::Result;
+
 
 
 /**
  * Get active network interface
  */
-pub fn get_active_interface(interace_name: &str) -> NetworkInterface {
+pub fn get_active_interface(interace_name: &str) -> Result<NetworkInterface> {
     let interfaces = interfaces();
 
     let interface = interfaces.into_iter()
-        .filter(|nic| -> bool {
+        .find(|nic| -> bool {
             nic.name == interace_name
         })
-        .next()
-        .unwrap_or_else(|| {
-            error!("OS-utils: Failed get network interface");
-            panic!("failed get interface");
-        });
+        .ok_or_else(|| NetDoctorError::InterfaceNotFound(interace_name.to_string()))?;
 
     debug!("{:?}", interface);
 
-    return interface;
+    Ok(interface)
 }
\ No newline at end of file
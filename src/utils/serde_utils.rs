@@ -0,0 +1,42 @@
+use std::{fmt::Display, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use pnet::packet::ip::IpNextHeaderProtocol;
+use serde::Serializer;
+
+use super::byte_utils::to_hex;
+
+/**
+ * Serialize value as its Display string, e.g. mac address
+ */
+pub fn display<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/**
+ * Serialize duration as milliseconds
+ */
+pub fn millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+/**
+ * Serialize bytes as lowercase hex string
+ */
+pub fn hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(bytes))
+}
+
+/**
+ * Serialize bytes as base64 string with padding, e.g. dnssec key
+ */
+pub fn base64<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&STANDARD.encode(bytes))
+}
+
+/**
+ * Serialize ip protocol as its number
+ */
+pub fn protocol<S: Serializer>(protocol: &IpNextHeaderProtocol, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u8(protocol.0)
+}